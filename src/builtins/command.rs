@@ -0,0 +1,255 @@
+use crate::builtins::error;
+use crate::lang::exec::{is_executable, Resolution};
+use crate::lang::{ExecutionContext, JobManager, Result};
+use std::io::{self, Write};
+
+/// Words that are part of the shell's syntax, they're never run as commands
+const KEYWORDS: &[&str] = &["!", "{", "}", "[[", "]]", "function"];
+
+/// `command [-v | -V] name ...` or `command name [argument ...]`
+///
+/// Run a builtin or executable, skipping any function with the same name, e.g. to call `ls` from a function named `ls`.
+/// The executor handles that form itself, see `command_args`, so this is only run with `-v` or `-V`.
+///
+/// `-v` prints the path of an executable, or the name of anything else, `-V` describes it like `type`.
+/// The status is 1 if any of the names weren't found.
+pub fn command(_jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let mut verbose = None;
+    let mut names = &args[1..];
+    while let Some(option) = names.first() {
+        if option == "--" {
+            names = &names[1..];
+            break;
+        }
+        if !option.starts_with('-') || option.len() == 1 {
+            break;
+        }
+
+        for flag in option.chars().skip(1) {
+            match flag {
+                'v' => verbose = Some(false),
+                'V' => verbose = Some(true),
+                _ => {
+                    error("command", format!("-{}: invalid option", flag));
+                    error("command", "usage: command [-vV] command [arg ...]");
+                    return Ok(2);
+                }
+            }
+        }
+        names = &names[1..];
+    }
+
+    let verbose = match verbose {
+        Some(verbose) => verbose,
+        // `command` with nothing to run does nothing
+        None => return Ok(0),
+    };
+
+    let mut stdout = io::stdout();
+    let mut status = 0;
+    for name in names {
+        match lookup(ec, name, false).first() {
+            Some(found) if verbose => {
+                let _ = writeln!(stdout, "{}", found.describe(name));
+            }
+            Some(Found::Resolved(Resolution::File(path))) => {
+                let _ = writeln!(stdout, "{}", path.display());
+            }
+            Some(_) => {
+                let _ = writeln!(stdout, "{}", name);
+            }
+            None => {
+                if verbose {
+                    error("command", format!("{}: not found", name));
+                }
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// `type [-at] name ...`
+///
+/// Describe what each name runs: a shell keyword, a function, a builtin, or the path of an executable.
+/// `-a` lists everything the name could refer to, in the order they're looked up, instead of only the one that's run.
+/// `-t` prints a single word instead: `keyword`, `function`, `builtin` or `file`.
+pub fn r#type(_jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let (mut all, mut terse) = (false, false);
+    let mut names = &args[1..];
+    while let Some(option) = names.first() {
+        if option == "--" {
+            names = &names[1..];
+            break;
+        }
+        if !option.starts_with('-') || option.len() == 1 {
+            break;
+        }
+
+        for flag in option.chars().skip(1) {
+            match flag {
+                'a' => all = true,
+                't' => terse = true,
+                _ => {
+                    error("type", format!("-{}: invalid option", flag));
+                    error("type", "usage: type [-at] name [name ...]");
+                    return Ok(2);
+                }
+            }
+        }
+        names = &names[1..];
+    }
+
+    let mut stdout = io::stdout();
+    let mut status = 0;
+    for name in names {
+        let mut found = lookup(ec, name, all);
+        if found.is_empty() {
+            if !terse {
+                error("type", format!("{}: not found", name));
+            }
+            status = 1;
+            continue;
+        }
+
+        if !all {
+            found.truncate(1);
+        }
+        for found in found {
+            let line = if terse {
+                found.kind().to_string()
+            } else {
+                found.describe(name)
+            };
+            let _ = writeln!(stdout, "{}", line);
+        }
+    }
+    Ok(status)
+}
+
+/// Get the arguments of the command `args` runs with `command name [argument ...]`, or `None` if it isn't that form.
+///
+/// The executor runs the command in place of `command`, so it can be part of a pipeline like any other command.
+pub fn command_args(args: &[String]) -> Option<&[String]> {
+    if args.first().map(String::as_str) != Some("command") {
+        return None;
+    }
+
+    match args.get(1).map(String::as_str) {
+        Some("--") => args.get(2).map(|_| &args[2..]),
+        Some(name) if !name.starts_with('-') => Some(&args[1..]),
+        _ => None,
+    }
+}
+
+/// Something a command name refers to
+enum Found {
+    Keyword,
+    Resolved(Resolution),
+}
+
+impl Found {
+    fn kind(&self) -> &'static str {
+        match self {
+            Found::Keyword => "keyword",
+            Found::Resolved(Resolution::Function(_)) => "function",
+            Found::Resolved(Resolution::Builtin(_)) => "builtin",
+            Found::Resolved(Resolution::File(_)) => "file",
+        }
+    }
+
+    fn describe(&self, name: &str) -> String {
+        match self {
+            Found::Keyword => format!("{} is a shell keyword", name),
+            Found::Resolved(Resolution::Function(_)) => format!("{} is a function", name),
+            Found::Resolved(Resolution::Builtin(_)) => format!("{} is a shell builtin", name),
+            Found::Resolved(Resolution::File(path)) => {
+                format!("{} is {}", name, path.display())
+            }
+        }
+    }
+}
+
+/// Look up `name` with `ExecutionContext::resolve`, or `resolve_all` with `all`, after checking if it's a keyword.
+///
+/// Paths are only reported if they can be executed.
+fn lookup(ec: &mut ExecutionContext, name: &str, all: bool) -> Vec<Found> {
+    let mut found = Vec::new();
+    if KEYWORDS.contains(&name) {
+        found.push(Found::Keyword);
+        if !all {
+            return found;
+        }
+    }
+
+    let resolved = if all {
+        ec.resolve_all(name)
+    } else {
+        ec.resolve(name, false).into_iter().collect()
+    };
+    for resolution in resolved {
+        match resolution {
+            Resolution::File(path) if !is_executable(&path) => (),
+            resolution => found.push(Found::Resolved(resolution)),
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use super::command_args;
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::fs;
+
+    #[test]
+    fn args() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        assert_eq!(
+            command_args(&args(&["command", "ls", "-l"])),
+            Some(&args(&["ls", "-l"])[..])
+        );
+        assert_eq!(
+            command_args(&args(&["command", "--", "-x"])),
+            Some(&args(&["-x"])[..])
+        );
+        assert_eq!(command_args(&args(&["command", "-v", "ls"])), None);
+        assert_eq!(command_args(&args(&["command"])), None);
+        assert_eq!(command_args(&args(&["ls"])), None);
+    }
+
+    #[test]
+    fn resolution() {
+        forks!();
+
+        let out_file = "test/data/command-out.txt";
+        let script = format!(
+            "{{ function echo {{ printf 'fn\\n'; }}; echo a; command echo b; \
+            type echo [[ /bin/sh; type -t echo printf; command -v printf /bin/sh; \
+            command -V echo; type -a printf; }} > {}",
+            out_file
+        );
+
+        let mut ec = ExecutionContext::new();
+        let status = JobManager::new()
+            .run(&mut ec, Command::parse(script).expect("failed to parse"))
+            .expect("failed to run command");
+        assert_eq!(status.exit_code, 0);
+
+        let content = fs::read_to_string(out_file).expect("failed to read out file");
+        // `type -a` goes on to list every printf in PATH
+        assert!(content.starts_with(
+            "fn\nb\necho is a function\n[[ is a shell keyword\n/bin/sh is /bin/sh\n\
+            function\nbuiltin\nprintf\n/bin/sh\necho is a function\nprintf is a shell builtin\n"
+        ));
+
+        let status = JobManager::new()
+            .run(
+                &mut ec,
+                Command::parse("type rush-not-a-command 2>/dev/null").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(status.exit_code, 1);
+    }
+}
@@ -0,0 +1,381 @@
+use crate::builtins::{error, is_name, quote_value};
+use crate::env::variables::{AssignError, Attributes, Value};
+use crate::lang::{ExecutionContext, JobManager, Result};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::io::{self, Write};
+
+/// `declare [-p] [-aAilnrux] [+ilnrux] [name[=value] ...]`, also called `typeset`
+///
+/// Set attributes of variables, and assign the ones given a value.
+/// `-` adds an attribute and `+` removes it: `i` integer, `l` lowercase, `n` name reference, `r` readonly, `u` uppercase, `x` exported.
+/// `-a` and `-A` make the variables indexed and associative arrays.
+/// With `-p`, or without names, variables are printed as `declare` commands, limited to the ones with the given attributes.
+pub fn declare(_jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let options = match Options::parse("declare", args, "paAilnrux") {
+        Some(options) => options,
+        None => {
+            error(
+                "declare",
+                "usage: declare [-p] [-aAilnrux] [+ilnrux] [name[=value] ...]",
+            );
+            return Ok(2);
+        }
+    };
+
+    if options.print || options.names.is_empty() {
+        return Ok(print(ec, "declare", &options));
+    }
+    Ok(assign(ec, "declare", &options))
+}
+
+/// `readonly [-p] [name[=value] ...]`
+///
+/// Make variables readonly, after assigning the ones given a value.
+/// With `-p`, or without names, readonly variables are printed.
+pub fn readonly(_jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let mut options = match Options::parse("readonly", args, "p") {
+        Some(options) => options,
+        None => {
+            error("readonly", "usage: readonly [-p] [name[=value] ...]");
+            return Ok(2);
+        }
+    };
+    options.add.readonly = true;
+
+    if options.print || options.names.is_empty() {
+        return Ok(print(ec, "readonly", &options));
+    }
+    Ok(assign(ec, "readonly", &options))
+}
+
+struct Options<'a> {
+    print: bool,
+
+    /// `-a` or `-A`, true for associative arrays
+    array: Option<bool>,
+    add: Attributes,
+    remove: Attributes,
+    names: &'a [String],
+}
+
+impl<'a> Options<'a> {
+    /// Parse the options in `args`, only accepting the flags in `flags`
+    fn parse(builtin: &str, args: &'a [String], flags: &str) -> Option<Options<'a>> {
+        let mut options = Options {
+            print: false,
+            array: None,
+            add: Attributes::default(),
+            remove: Attributes::default(),
+            names: &args[1..],
+        };
+
+        while let Some(option) = options.names.first() {
+            if option == "--" {
+                options.names = &options.names[1..];
+                break;
+            }
+            let add = match option.chars().next() {
+                Some('-') => true,
+                Some('+') => false,
+                _ => break,
+            };
+            if option.len() == 1 {
+                break;
+            }
+
+            for flag in option.chars().skip(1) {
+                let attributes = if add {
+                    &mut options.add
+                } else {
+                    &mut options.remove
+                };
+                match flag {
+                    _ if !flags.contains(flag) || (!add && "aA".contains(flag)) => {
+                        error(builtin, format!("{}{}: invalid option", &option[..1], flag));
+                        return None;
+                    }
+                    'p' => options.print = true,
+                    'a' if add => options.array = Some(false),
+                    'A' if add => options.array = Some(true),
+                    'i' => attributes.integer = true,
+                    'l' => attributes.lowercase = true,
+                    'n' => attributes.nameref = true,
+                    'u' => attributes.uppercase = true,
+                    'x' => attributes.exported = true,
+                    'r' => attributes.readonly = true,
+                    _ => unreachable!(),
+                }
+            }
+            options.names = &options.names[1..];
+        }
+        Some(options)
+    }
+
+    /// The attributes a variable with `old` attributes ends up with, except that readonly is only ever removed.
+    ///
+    /// Removing readonly from a readonly variable fails when the attributes are set.
+    fn apply(&self, old: Attributes) -> Attributes {
+        let (add, remove) = (self.add, self.remove);
+        let mut attributes = Attributes {
+            readonly: old.readonly && !remove.readonly,
+            exported: (old.exported || add.exported) && !remove.exported,
+            integer: (old.integer || add.integer) && !remove.integer,
+            lowercase: (old.lowercase || add.lowercase) && !remove.lowercase,
+            uppercase: (old.uppercase || add.uppercase) && !remove.uppercase,
+            nameref: (old.nameref || add.nameref) && !remove.nameref,
+        };
+
+        // the case conversions replace each other
+        if add.lowercase {
+            attributes.uppercase = false;
+        } else if add.uppercase {
+            attributes.lowercase = false;
+        }
+        attributes
+    }
+}
+
+/// Set attributes and assign values for each `name[=value]` in the options
+fn assign(ec: &mut ExecutionContext, builtin: &str, options: &Options) -> i32 {
+    let mut status = 0;
+    for arg in options.names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_name(name) {
+            error(builtin, format!("`{}': not a valid identifier", arg));
+            status = 1;
+            continue;
+        }
+        if options.add.nameref && value.is_some_and(|value| !is_name(value)) {
+            error(
+                builtin,
+                format!("`{}': invalid variable name for name reference", arg),
+            );
+            status = 1;
+            continue;
+        }
+
+        if let Err(e) = declare_variable(ec, name, value, options) {
+            error(builtin, e.to_string());
+            status = 1;
+        }
+    }
+    status
+}
+
+fn declare_variable(
+    ec: &mut ExecutionContext,
+    name: &str,
+    value: Option<&str>,
+    options: &Options,
+) -> std::result::Result<(), AssignError> {
+    let vars = ec.variables_mut();
+    let name = OsString::from(name);
+
+    // the value is assigned before the variable becomes readonly, so `readonly x=1` can set it
+    let attributes = options.apply(vars.attributes(&name));
+    vars.set_attributes(&name, attributes)?;
+    if let Some(associative) = options.array {
+        vars.declare_array(&name, associative)?;
+    }
+    match value {
+        Some(value) if options.add.nameref => vars.set_nameref(name.clone(), value)?,
+        Some(value) => vars.set(name.clone(), value)?,
+        None => (),
+    }
+
+    if options.add.readonly {
+        vars.set_attributes(
+            &name,
+            Attributes {
+                readonly: true,
+                ..attributes
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Print variables as `declare` commands, the named ones or every one with the attributes in `options.add`
+fn print(ec: &ExecutionContext, builtin: &str, options: &Options) -> i32 {
+    let vars = ec.variables();
+    let mut stdout = io::stdout();
+    let mut status = 0;
+
+    let names: Vec<OsString> = if options.names.is_empty() {
+        let mut wanted = flags(options.add, None);
+        match options.array {
+            Some(true) => wanted.push('A'),
+            Some(false) => wanted.push('a'),
+            None => (),
+        }
+        vars.iter()
+            .map(|(name, _)| name)
+            .chain(vars.iter_attributes().map(|(name, _)| name))
+            .filter(|name| {
+                let flags = flags(vars.attributes(name), vars.get(name));
+                wanted.chars().all(|flag| flags.contains(flag))
+            })
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    } else {
+        options.names.iter().map(OsString::from).collect()
+    };
+
+    for name in names {
+        let attributes = vars.attributes(&name);
+        // a name reference's own value is the name it refers to
+        let value = match vars.get(&name) {
+            Some(value) => Some(value),
+            None if attributes != Attributes::default() => None,
+            None => {
+                error(builtin, format!("{}: not found", name.to_string_lossy()));
+                status = 1;
+                continue;
+            }
+        };
+
+        let mut line = format!("declare -{}", flags(attributes, value));
+        if line.len() == "declare -".len() {
+            line.push('-');
+        }
+        line.push(' ');
+        line.push_str(&name.to_string_lossy());
+        if let Some(value) = value {
+            line.push('=');
+            line.push_str(&quote_value(value));
+        }
+        let _ = writeln!(stdout, "{}", line);
+    }
+    status
+}
+
+/// The option letters of the attributes that are set and the kind of array `value` is, in the order `declare -p` prints them
+fn flags(attributes: Attributes, value: Option<&Value>) -> String {
+    [
+        (matches!(value, Some(Value::Indexed(_))), 'a'),
+        (matches!(value, Some(Value::Associative(_))), 'A'),
+        (attributes.integer, 'i'),
+        (attributes.lowercase, 'l'),
+        (attributes.nameref, 'n'),
+        (attributes.readonly, 'r'),
+        (attributes.uppercase, 'u'),
+        (attributes.exported, 'x'),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, flag)| *flag)
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::variables::AssignError;
+    use crate::lang::{ast::Command, ErrorKind, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::ffi::OsString;
+    use std::fs;
+
+    fn run(ec: &mut ExecutionContext, script: &str) -> i32 {
+        JobManager::new()
+            .run(ec, Command::parse(script).expect("failed to parse"))
+            .expect("failed to run script")
+            .exit_code
+    }
+
+    #[test]
+    fn attributes() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let value = |ec: &ExecutionContext, name: &str| ec.variables().value(&OsString::from(name));
+
+        assert_eq!(
+            run(
+                &mut ec,
+                "declare -i n=2*3; declare -u up=abc; declare -l low=ABC"
+            ),
+            0
+        );
+        assert_eq!(value(&ec, "n"), "6");
+        assert_eq!(value(&ec, "up"), "ABC");
+        assert_eq!(value(&ec, "low"), "abc");
+
+        assert_eq!(run(&mut ec, "declare -n ref=up; typeset ref=xyz"), 0);
+        assert_eq!(value(&ec, "ref"), "XYZ");
+        assert_eq!(value(&ec, "up"), "XYZ");
+
+        // exported variables are given to children, the shell's own environment doesn't change
+        let script = "declare -x exported_by_rush=yes; sh -c 'test \"$exported_by_rush\" = yes'";
+        assert_eq!(run(&mut ec, script), 0);
+        assert!(std::env::var("exported_by_rush").is_err());
+        let script = "declare +x exported_by_rush; sh -c 'test -z \"$exported_by_rush\"'";
+        assert_eq!(run(&mut ec, script), 0);
+        assert_eq!(run(&mut ec.clone(), "declare -x in_copy=1"), 0);
+        assert_eq!(run(&mut ec, "sh -c 'test -z \"$in_copy\"'"), 0);
+
+        assert_eq!(run(&mut ec, "declare 1x=1 2>/dev/null"), 1);
+        assert_eq!(run(&mut ec, "declare -q x 2>/dev/null"), 2);
+    }
+
+    #[test]
+    fn readonly() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let value = |ec: &ExecutionContext, name: &str| ec.variables().value(&OsString::from(name));
+
+        assert_eq!(run(&mut ec, "readonly r=1"), 0);
+        assert_eq!(run(&mut ec, "readonly r=2 2>/dev/null"), 1);
+        assert_eq!(run(&mut ec, "declare r=2 2>/dev/null"), 1);
+        assert_eq!(run(&mut ec, "declare +r r 2>/dev/null"), 1);
+        assert_eq!(run(&mut ec, "printf -v r x 2>/dev/null"), 1);
+        assert_eq!(run(&mut ec, "read r 2>/dev/null </dev/null"), 1);
+        assert_eq!(value(&ec, "r"), "1");
+
+        // arithmetic assignments are expansion errors, like unset variables with nounset
+        for script in &["echo $((r = 5))", "echo $((r++))"] {
+            let err = JobManager::new()
+                .run(&mut ec, Command::parse(script).expect("failed to parse"))
+                .expect_err("assigning a readonly variable should fail");
+            assert_eq!(
+                err.kind(),
+                &ErrorKind::Assign(AssignError::Readonly("r".into()))
+            );
+        }
+        assert_eq!(value(&ec, "r"), "1");
+
+        // a name reference doesn't get around readonly
+        assert_eq!(
+            run(&mut ec, "declare -n ref=r; declare ref=3 2>/dev/null"),
+            1
+        );
+        assert_eq!(value(&ec, "r"), "1");
+    }
+
+    #[test]
+    fn print() {
+        forks!();
+
+        let out_file = "test/data/declare-out.txt";
+        let script = format!(
+            "{{ declare -i i=1; declare -rx rx='a b'; declare -l unset_l; \
+            declare -p i rx unset_l; readonly -p; declare -p not_declared; }} > {} 2>/dev/null",
+            out_file
+        );
+
+        let mut ec = ExecutionContext::new();
+        assert_eq!(run(&mut ec, &script), 1);
+
+        let content = fs::read_to_string(out_file).expect("failed to read out file");
+        assert_eq!(
+            content,
+            "declare -i i=1\ndeclare -rx rx='a b'\ndeclare -l unset_l\ndeclare -rx rx='a b'\n"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use crate::builtins::printf::unescape;
+use crate::lang::{ExecutionContext, JobManager, Result};
+use std::io::{self, Write};
+
+/// `echo [-neE] [argument ...]`
+///
+/// Print the arguments separated by spaces, followed by a newline.
+///
+/// POSIX leaves options and backslashes up to the shell, so this behaves like bash's `echo`
+/// rather than the XSI one that always expands escapes, since that's what most scripts expect:
+/// `-n` leaves out the newline, `-e` expands backslash escapes like `printf`'s `%b`, and `-E` turns them back off.
+/// Only arguments made up entirely of those letters are options, so `echo -x` prints `-x`.
+/// `\c` in an expanded argument ends the output, without a newline.
+pub fn echo(_jm: &mut JobManager, _ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let mut newline = true;
+    let mut escapes = false;
+
+    let mut args = &args[1..];
+    while let Some(option) = args.first() {
+        let flags = match option.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() && flags.chars().all(|c| "neE".contains(c)) => flags,
+            _ => break,
+        };
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        args = &args[1..];
+    }
+
+    let mut out = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            out.push(b' ');
+        }
+        if !escapes {
+            out.extend_from_slice(arg.as_bytes());
+        } else if !unescape(arg, true, &mut out) {
+            newline = false;
+            break;
+        }
+    }
+    if newline {
+        out.push(b'\n');
+    }
+
+    let _ = io::stdout().write_all(&out);
+    Ok(0)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::fs;
+
+    #[test]
+    fn echo() {
+        forks!();
+
+        let out_file = "test/data/echo-out.txt";
+        let script = format!(
+            "{{ echo a  b; echo -n c; echo -x -e; echo -ne 'd\\te\\0101\\n'; \
+            echo -eE 'f\\n'; echo -e 'g\\ch' i; echo; }} > {}",
+            out_file
+        );
+
+        let mut ec = ExecutionContext::new();
+        let status = JobManager::new()
+            .run(&mut ec, Command::parse(script).expect("failed to parse"))
+            .expect("failed to run echo");
+        assert_eq!(status.exit_code, 0);
+
+        let content = fs::read_to_string(out_file).expect("failed to read out file");
+        assert_eq!(content, "a b\nc-x -e\nd\teA\nf\\n\ng\n");
+    }
+}
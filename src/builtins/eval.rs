@@ -0,0 +1,53 @@
+use crate::lang::{ast::Command, ExecutionContext, JobManager, Result};
+
+/// `eval [arg ...]`
+///
+/// Join the arguments with spaces, then parse and run the result as a command.
+pub fn eval(jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let source = args[1..].join(" ");
+    if source.trim().is_empty() {
+        return Ok(0);
+    }
+
+    match Command::parse(&source) {
+        Ok(command) => jm.run(ec, command).map(|status| status.exit_code),
+        Err(e) => {
+            eprintln!("{}", e);
+            Ok(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::ffi::OsString;
+
+    fn run(jm: &mut JobManager, ec: &mut ExecutionContext, script: &str) -> i32 {
+        jm.run(ec, Command::parse(script).unwrap())
+            .expect("failed to run script")
+            .exit_code
+    }
+
+    #[test]
+    fn eval() {
+        forks!();
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        assert_eq!(run(&mut jm, &mut ec, "eval set -- a 'b;' set -- c"), 0);
+        assert_eq!(ec.variables().value(&OsString::from("1")), "c");
+
+        // the arguments are expanded once before they're parsed
+        assert_eq!(run(&mut jm, &mut ec, "eval 'set -- \"$1\"d'"), 0);
+        assert_eq!(ec.variables().value(&OsString::from("1")), "cd");
+
+        assert_eq!(run(&mut jm, &mut ec, "eval"), 0);
+        assert_eq!(
+            run(&mut jm, &mut ec, "eval 'function f { return 3; }; f'"),
+            3
+        );
+        assert_eq!(run(&mut jm, &mut ec, "eval '}' 2>/dev/null"), 2);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::lang::{ExecutionContext, JobManager, Result};
+
+/// `exec [command [arg ...]]`
+///
+/// Replace the shell with `command`. If it can't be run, a non-interactive shell exits with status 126 or 127.
+/// Without a command, the redirects given to `exec` stay applied to the shell, e.g. `exec 2>errors.log`.
+pub fn exec(jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let args = match args.get(1).map(String::as_str) {
+        Some("--") => &args[2..],
+        _ => &args[1..],
+    };
+
+    if args.is_empty() {
+        jm.keep_redirects();
+        return Ok(0);
+    }
+
+    jm.replace_shell(ec, args)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::fds::OpenFd;
+    use crate::env::signals::Signal;
+    use crate::env::traps;
+    use crate::jobs::spawn::OpenMode;
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+    use std::ffi::OsString;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process::exit;
+
+    fn run(jm: &mut JobManager, ec: &mut ExecutionContext, script: &str) -> i32 {
+        jm.run(ec, Command::parse(script).unwrap())
+            .expect("failed to run script")
+            .exit_code
+    }
+
+    #[test]
+    fn exec() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        assert_eq!(
+            run(
+                &mut jm,
+                &mut ec,
+                "{ exec sh -c 'exit 3'; exit 4; } & wait $!"
+            ),
+            3
+        );
+        assert_eq!(
+            run(
+                &mut jm,
+                &mut ec,
+                "{ exec rush-not-a-real-command; exit 4; } 2>/dev/null & wait $!"
+            ),
+            127
+        );
+
+        // redirects without a command stay applied
+        let script = "{ exec 3>/dev/null 4>&3; sh -c 'printf hello >&4'; } & wait $!";
+        assert_eq!(run(&mut jm, &mut ec, script), 0);
+        assert_eq!(
+            run(
+                &mut jm,
+                &mut ec,
+                "{ exec 2>&-; sh -c 'printf hello >&2'; } & wait $!"
+            ),
+            2
+        );
+    }
+
+    #[test]
+    fn shell_fds() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+        let out_file = PathBuf::from("test/data/shell_fds-out.txt");
+
+        assert_eq!(
+            run(&mut jm, &mut ec, "exec 9>test/data/shell_fds-out.txt"),
+            0
+        );
+        assert_eq!(
+            ec.fds().get(9),
+            Some(&OpenFd::File(out_file.clone(), OpenMode::Write))
+        );
+
+        // groups run in the shell process, so their redirects are the shell's too
+        assert_eq!(run(&mut jm, &mut ec, "{ exec 8>&9; }"), 0);
+        assert_eq!(
+            ec.fds().get(8),
+            Some(&OpenFd::File(out_file.clone(), OpenMode::Write))
+        );
+        assert_eq!(run(&mut jm, &mut ec, "exec 8>&-"), 0);
+        assert_eq!(ec.fds().get(8), None);
+
+        // both children and builtins write to the shell's fd
+        assert_eq!(
+            run(&mut jm, &mut ec, "sh -c 'printf hello >&9'; set >&9"),
+            0
+        );
+        assert_eq!(run(&mut jm, &mut ec, "exec 9>&-"), 0);
+        assert_eq!(ec.fds().get(9), None);
+        assert_eq!(
+            run(&mut jm, &mut ec, "sh -c 'printf hello >&9' 2>/dev/null"),
+            2
+        );
+
+        let content = fs::read_to_string(&out_file).expect("failed to read out file");
+        assert!(content.starts_with("hello"));
+        assert!(content.contains("\nLINENO=1\n"));
+    }
+
+    #[test]
+    fn interactive() {
+        forks!();
+
+        // the shell's signal actions change when it's interactive, so it's tried in a child process
+        let child = match fork().expect("fork failed") {
+            ForkResult::Child => {
+                traps::ignore_interactive().expect("failed to ignore signals");
+                let mut ec = ExecutionContext::new();
+                let mut jm = JobManager::new();
+                let script = "exec rush-not-a-real-command 2>/dev/null; a=$?; exec ./Cargo.toml 2>/dev/null; b=$?";
+                let ran = jm.run(&mut ec, Command::parse(script).unwrap()).is_ok();
+                let value = |name: &str| ec.variables().value(&OsString::from(name));
+                let passed = ran && value("a") == "127" && value("b") == "126";
+                exit(if passed && Signal::SIGINT.is_ignored() {
+                    0
+                } else {
+                    1
+                })
+            }
+            ForkResult::Parent { child } => child,
+        };
+        assert_eq!(waitpid(child, None), Ok(WaitStatus::Exited(child, 0)));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::builtins::error;
+use crate::lang::{ErrorKind, ExecutionContext, JobManager, Result};
+
+/// `exit [n]`
+///
+/// Exit the shell with status `n`, or the last command's status. The `EXIT` trap is run before the shell exits.
+pub fn exit(_jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    match status(ec, "exit", args) {
+        Ok(status) => Err(ErrorKind::Exit(status).into()),
+        Err(status) => Ok(status),
+    }
+}
+
+/// `return [n]`
+///
+/// Stop running the current function or sourced script, its status is `n` or the last command's status.
+pub fn r#return(jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    if jm.call_depth() == 0 {
+        error(
+            "return",
+            "can only return from a function or sourced script",
+        );
+        return Ok(1);
+    }
+
+    match status(ec, "return", args) {
+        Ok(status) => Err(ErrorKind::Return(status).into()),
+        Err(status) => Ok(status),
+    }
+}
+
+/// Get the status for `exit` or `return`, `Err` is the builtin's own status if the arguments are wrong.
+///
+/// Like other shells, a status that isn't a number still exits, with status 2.
+fn status(ec: &ExecutionContext, builtin: &str, args: &[String]) -> std::result::Result<i32, i32> {
+    let args = match args.get(1).map(String::as_str) {
+        Some("--") => &args[2..],
+        _ => &args[1..],
+    };
+
+    match args {
+        [] => Ok(ec.variables().last_status()),
+        [status] => match status.parse::<i64>() {
+            // exit statuses are only 8 bits
+            Ok(status) => Ok((status & 0xff) as i32),
+            Err(_) => {
+                error(builtin, format!("{}: numeric argument required", status));
+                Ok(2)
+            }
+        },
+        _ => {
+            error(builtin, "too many arguments");
+            Err(1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ast::Command, ErrorKind, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+
+    fn run(jm: &mut JobManager, ec: &mut ExecutionContext, script: &str) -> i32 {
+        jm.run(ec, Command::parse(script).unwrap())
+            .expect("failed to run script")
+            .exit_code
+    }
+
+    #[test]
+    fn exit() {
+        forks!();
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        let err = jm
+            .run(
+                &mut ec,
+                Command::parse("exit 3; set -- not-reached").unwrap(),
+            )
+            .expect_err("exit should stop the script");
+        assert_eq!(err.kind(), &ErrorKind::Exit(3));
+        assert_eq!(ec.variables().value(&"#".into()), "0");
+
+        let err = jm
+            .run(&mut ec, Command::parse("exit 257").unwrap())
+            .expect_err("exit should stop the script");
+        assert_eq!(err.kind(), &ErrorKind::Exit(1));
+
+        assert_eq!(run(&mut jm, &mut ec, "exit 1 2 2>/dev/null"), 1);
+    }
+
+    #[test]
+    fn subshell_exit() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        assert_eq!(run(&mut jm, &mut ec, "{ exit 4; true; } & wait $!"), 4);
+        assert_eq!(
+            run(
+                &mut jm,
+                &mut ec,
+                "{ trap 'exit 6' EXIT; exit 5; } & wait $!"
+            ),
+            6
+        );
+    }
+
+    #[test]
+    fn r#return() {
+        forks!();
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        let script = "function f { return 4; set -- not-reached; }; f";
+        assert_eq!(run(&mut jm, &mut ec, script), 4);
+        assert_eq!(ec.variables().value(&"#".into()), "0");
+
+        assert_eq!(run(&mut jm, &mut ec, "function g { false; return; }; g"), 1);
+        assert_eq!(run(&mut jm, &mut ec, "return 2>/dev/null"), 1);
+    }
+}
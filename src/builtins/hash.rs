@@ -0,0 +1,116 @@
+use crate::builtins::{self, error};
+use crate::lang::{ExecutionContext, JobManager, Result};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+
+/// `hash [-r] [name ...]`
+///
+/// Commands are looked up in `PATH` once, then remembered until `PATH` changes.
+/// With no arguments the remembered commands' paths are printed, `-r` forgets all of them.
+/// Any names given are looked up and remembered, builtins and paths are skipped.
+pub fn hash(_jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let names = match args.get(1).map(String::as_str) {
+        Some("-r") => {
+            ec.forget_hashed_commands();
+            &args[2..]
+        }
+        Some("--") => &args[2..],
+        Some(option) if option.starts_with('-') && option.len() > 1 => {
+            error("hash", format!("{}: invalid option", option));
+            error("hash", "usage: hash [-r] [name ...]");
+            return Ok(2);
+        }
+        _ => {
+            if args.len() == 1 {
+                let mut stdout = io::stdout();
+                for (_, path) in ec.hashed_commands() {
+                    let _ = stdout.write_all(path.as_os_str().as_bytes());
+                    let _ = stdout.write_all(b"\n");
+                }
+                return Ok(0);
+            }
+            &args[1..]
+        }
+    };
+
+    let mut status = 0;
+    for name in names {
+        if name.contains('/') || builtins::lookup(name).is_some() {
+            continue;
+        }
+
+        if ec.find_executable(name).is_err() {
+            error("hash", format!("{}: not found", name));
+            status = 1;
+        }
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ExecutionContext, JobManager};
+    use std::ffi::OsString;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    fn hash(ec: &mut ExecutionContext, args: &[&str]) -> i32 {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        super::hash(&mut JobManager::new(), ec, &args).expect("hash failed")
+    }
+
+    fn hashed(ec: &mut ExecutionContext) -> Vec<(OsString, PathBuf)> {
+        ec.hashed_commands()
+            .map(|(name, path)| (name.clone(), path.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn lookup() {
+        let dir = std::env::temp_dir().join(format!("rush-hash-{}", std::process::id()));
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        fs::create_dir_all(first.join("rush-test-dir")).expect("failed to create directory");
+        fs::create_dir_all(&second).expect("failed to create directory");
+        for (path, mode) in &[
+            (first.join("rush-test-cmd"), 0o644),
+            (second.join("rush-test-cmd"), 0o755),
+            (second.join("rush-test-dir"), 0o755),
+        ] {
+            fs::write(path, "").expect("failed to create file");
+            fs::set_permissions(path, fs::Permissions::from_mode(*mode))
+                .expect("failed to set permissions");
+        }
+
+        let mut ec = ExecutionContext::new();
+        let path = format!("{}:{}", first.display(), second.display());
+        ec.variables_mut().define("PATH", path.as_str());
+
+        // files that can't be executed and directories are skipped
+        assert_eq!(
+            ec.find_executable("rush-test-cmd").ok(),
+            Some(second.join("rush-test-cmd"))
+        );
+        assert_eq!(hash(&mut ec, &["hash", "rush-test-dir", "set"]), 0);
+        assert_eq!(
+            hashed(&mut ec),
+            vec![
+                ("rush-test-cmd".into(), second.join("rush-test-cmd")),
+                ("rush-test-dir".into(), second.join("rush-test-dir")),
+            ]
+        );
+        assert_eq!(hash(&mut ec, &["hash", "rush-not-a-real-command"]), 1);
+
+        assert_eq!(hash(&mut ec, &["hash", "-r"]), 0);
+        assert!(hashed(&mut ec).is_empty());
+
+        assert_eq!(hash(&mut ec, &["hash", "rush-test-cmd"]), 0);
+        ec.variables_mut().define("PATH", first.as_os_str());
+        assert!(hashed(&mut ec).is_empty());
+        assert!(ec.find_executable("rush-test-cmd").is_err());
+
+        assert_eq!(hash(&mut ec, &["hash", "-x"]), 2);
+        fs::remove_dir_all(&dir).expect("failed to remove test directory");
+    }
+}
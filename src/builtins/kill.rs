@@ -0,0 +1,157 @@
+use crate::builtins::{error, list_signals};
+use crate::env::signals::Signal;
+use crate::lang::{ExecutionContext, JobManager, Result};
+use nix::sys::signal;
+use nix::unistd::Pid;
+use std::io::{self, Write};
+
+const USAGE: &str =
+    "usage: kill [-s sigspec | -n signum | -sigspec] pid | jobspec ... or kill -l [sigspec]";
+
+/// `kill [-s signal | -n number | -signal] pid|%job ...` or `kill -l [signal|status ...]`
+///
+/// Send a signal, `SIGTERM` by default, to processes or background jobs.
+/// Signal 0 doesn't send anything, it only checks the processes exist.
+/// `-l` lists every signal, or converts between signal names, numbers and the exit status of a killed process.
+pub fn kill(jm: &mut JobManager, _ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let mut targets = &args[1..];
+    let spec = match targets.first().map(String::as_str) {
+        Some("-l") | Some("-L") => return Ok(list(&targets[1..])),
+        Some("-s") | Some("-n") => {
+            let spec = targets.get(1).map(String::as_str);
+            targets = targets.get(2..).unwrap_or_default();
+            spec
+        }
+        Some("--") => {
+            targets = &targets[1..];
+            None
+        }
+        Some(option) if option.starts_with('-') && option.len() > 1 => {
+            targets = &targets[1..];
+            Some(&option[1..])
+        }
+        _ => None,
+    };
+
+    // `None` is signal 0
+    let sig = match spec {
+        None => Some(Signal::SIGTERM),
+        Some("0") => None,
+        Some(spec) => match Signal::parse(spec) {
+            Some(sig) => Some(sig),
+            None => {
+                error("kill", format!("{}: invalid signal specification", spec));
+                return Ok(1);
+            }
+        },
+    };
+
+    if targets.is_empty() {
+        error("kill", USAGE);
+        return Ok(2);
+    }
+
+    let mut status = 0;
+    for target in targets {
+        let pid = if target.starts_with('%') {
+            match jm.find_job(target) {
+                Some(job) => job.pid,
+                None => {
+                    error("kill", format!("{}: no such job", target));
+                    status = 1;
+                    continue;
+                }
+            }
+        } else {
+            match target.parse() {
+                Ok(pid) => Pid::from_raw(pid),
+                Err(_) => {
+                    error(
+                        "kill",
+                        format!("{}: arguments must be process or job IDs", target),
+                    );
+                    status = 1;
+                    continue;
+                }
+            }
+        };
+
+        let result = match sig {
+            Some(sig) => sig.send(pid),
+            None => signal::kill(pid, None),
+        };
+        if let Err(e) = result {
+            error("kill", format!("({}) - {}", pid, e));
+            status = 1;
+        }
+    }
+
+    Ok(status)
+}
+
+/// `kill -l`, names are printed for numbers and exit statuses, numbers for names
+fn list(specs: &[String]) -> i32 {
+    if specs.is_empty() {
+        list_signals();
+        return 0;
+    }
+
+    let mut stdout = io::stdout();
+    let mut status = 0;
+    for spec in specs {
+        let sig = match spec.parse::<i32>() {
+            // exit statuses of killed processes are 128 + the signal's number
+            Ok(n) if n > 128 => Signal::from_number(n - 128),
+            Ok(n) => Signal::from_number(n),
+            Err(_) => Signal::parse(spec),
+        };
+
+        match sig {
+            Some(sig) if spec.parse::<i32>().is_ok() => {
+                let name = sig.name();
+                let _ = writeln!(stdout, "{}", name.trim_start_matches("SIG"));
+            }
+            Some(sig) => {
+                let _ = writeln!(stdout, "{}", sig.number());
+            }
+            None => {
+                error("kill", format!("{}: invalid signal specification", spec));
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+
+    fn run(jm: &mut JobManager, ec: &mut ExecutionContext, script: &str) -> i32 {
+        jm.run(ec, Command::parse(script).unwrap())
+            .expect("failed to run script")
+            .exit_code
+    }
+
+    #[test]
+    fn signals() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        assert_eq!(
+            run(&mut jm, &mut ec, "sleep 5 & kill -s KILL $!; wait $!"),
+            128 + 9
+        );
+        assert_eq!(run(&mut jm, &mut ec, "sleep 5 & kill -USR1 %%; wait"), 0);
+        assert_eq!(run(&mut jm, &mut ec, "sleep 5 & kill -0 %1"), 0);
+        assert_eq!(run(&mut jm, &mut ec, "kill -9 %1; wait %1"), 128 + 9);
+
+        assert_eq!(run(&mut jm, &mut ec, "kill -NOTASIGNAL 1 2>/dev/null"), 1);
+        assert_eq!(run(&mut jm, &mut ec, "kill %5 2>/dev/null"), 1);
+        assert_eq!(run(&mut jm, &mut ec, "kill 2>/dev/null"), 2);
+        assert_eq!(run(&mut jm, &mut ec, "kill -l 143 TERM >/dev/null"), 0);
+    }
+}
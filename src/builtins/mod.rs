@@ -0,0 +1,114 @@
+//! Commands that are run inside the shell process instead of being spawned as a subprocess
+//!
+//! Builtins write to the shell's own standard output and error,
+//! any redirections are applied to the shell process for the duration of the builtin.
+use crate::env::signals;
+use crate::env::variables::Value;
+use crate::lang::{ExecutionContext, JobManager, Result};
+use std::io::{self, Write};
+
+pub(crate) mod command;
+mod declare;
+mod echo;
+mod eval;
+mod exec;
+mod exit;
+mod hash;
+mod kill;
+mod printf;
+mod read;
+mod set;
+mod source;
+pub(crate) mod test;
+mod trap;
+mod unset;
+mod wait;
+
+/// A builtin command.
+///
+/// Builtins are given the full argument list (including the command's name) and return an exit code.
+/// `Err` should only be used for failures of the shell itself, errors caused by the user's input should
+/// be printed and reported in the exit code.
+pub type Builtin = fn(&mut JobManager, &mut ExecutionContext, &[String]) -> Result<i32>;
+
+/// Find the builtin with a given name
+pub fn lookup(name: &str) -> Option<Builtin> {
+    Some(match name {
+        ":" => |_, _, _| Ok(0),
+        "." | "source" => source::source,
+        "command" => command::command,
+        "declare" | "typeset" => declare::declare,
+        "echo" => echo::echo,
+        "eval" => eval::eval,
+        "exec" => exec::exec,
+        "exit" => exit::exit,
+        "hash" => hash::hash,
+        "kill" => kill::kill,
+        "printf" => printf::printf,
+        "read" => read::read,
+        "readonly" => declare::readonly,
+        "return" => exit::r#return,
+        "set" => set::set,
+        "test" | "[" => test::test,
+        "trap" => trap::trap,
+        "type" => command::r#type,
+        "unset" => unset::unset,
+        "wait" => wait::wait,
+        _ => return None,
+    })
+}
+
+/// Print an error message from a builtin to stderr, prefixed with the builtin's name
+pub fn error<T: AsRef<str>>(builtin: &str, message: T) {
+    let _ = writeln!(io::stderr(), "rush: {}: {}", builtin, message.as_ref());
+}
+
+/// Check if `name` can be used as a variable name
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quote a string so it is read back as a single word with the same value
+pub fn quote<T: AsRef<str>>(s: T) -> String {
+    let s = s.as_ref();
+    if !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-+=./:,@%".contains(c))
+    {
+        return s.to_string();
+    }
+
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Quote a variable's value so it can be assigned back, arrays are written as `([subscript]=value ...)`
+pub fn quote_value(value: &Value) -> String {
+    let elements: Vec<String> = match value {
+        Value::Scalar(value) => return quote(value.to_string_lossy()),
+        Value::Indexed(elements) => elements
+            .iter()
+            .map(|(i, value)| format!("[{}]={}", i, quote(value.to_string_lossy())))
+            .collect(),
+        Value::Associative(elements) => elements
+            .iter()
+            .map(|(key, value)| format!("[{}]={}", quote(key), quote(value.to_string_lossy())))
+            .collect(),
+    };
+    format!("({})", elements.join(" "))
+}
+
+/// Print every signal's number and name, in the same table as `kill -l`
+pub fn list_signals() {
+    let mut stdout = io::stdout();
+    for row in signals::all().chunks(5) {
+        let row: Vec<String> = row
+            .iter()
+            .map(|sig| format!("{:2}) {}", sig.number(), sig))
+            .collect();
+        let _ = writeln!(stdout, "{}", row.join("\t"));
+    }
+}
@@ -0,0 +1,599 @@
+use crate::builtins::{error, quote};
+use crate::lang::{ExecutionContext, JobManager, Result};
+use nix::libc;
+use std::ffi::{CString, OsString};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::ptr;
+
+/// `printf [-v var] format [argument ...]`
+///
+/// Print the arguments according to `format`, which is like C's `printf` format with a few additions:
+/// - `%b` expands backslash escapes in its argument, like `echo -e`
+/// - `%q` quotes its argument so the shell reads it back as a single word
+/// - numeric arguments can be a quote followed by a character, which is converted to its code point
+///
+/// The format is reused until all of the arguments have been printed, missing arguments are empty or 0.
+/// Backslash escapes in the format are expanded, `\c` in a `%b` argument stops printing but in the format it's printed as it is.
+/// `-v` assigns the output to `var` instead of printing it.
+pub fn printf(_jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let mut args = &args[1..];
+    let mut var = None;
+    loop {
+        match args.first().map(String::as_str) {
+            Some("--") => {
+                args = &args[1..];
+                break;
+            }
+            Some("-v") if args.len() > 1 => {
+                var = Some(args[1].clone());
+                args = &args[2..];
+            }
+            Some(option) if option.starts_with('-') && option.len() > 1 => {
+                error("printf", format!("{}: invalid option", option));
+                error("printf", "usage: printf [-v var] format [arguments]");
+                return Ok(2);
+            }
+            _ => break,
+        }
+    }
+
+    let (format, args) = match args.split_first() {
+        Some(split) => split,
+        None => {
+            error("printf", "usage: printf [-v var] format [arguments]");
+            return Ok(2);
+        }
+    };
+
+    let mut out = Vec::new();
+    let status = match printf_all(format, args, &mut out) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(message) => {
+            error("printf", message);
+            1
+        }
+    };
+
+    match var {
+        Some(var) => {
+            if let Err(e) = ec.variables_mut().set(var, OsString::from_vec(out)) {
+                error("printf", e.to_string());
+                return Ok(1);
+            }
+        }
+        None => {
+            let _ = io::stdout().write_all(&out);
+        }
+    }
+    Ok(status)
+}
+
+/// Why formatting stopped before the end of the format
+enum Stop {
+    /// `\c` was found in a `%b` argument
+    Escape,
+
+    /// The format is invalid
+    Invalid(String),
+}
+
+/// The arguments, and how many of them have been used
+struct Arguments<'a> {
+    args: &'a [String],
+    next: usize,
+
+    /// Set if a numeric argument couldn't be parsed
+    failed: bool,
+}
+
+impl<'a> Arguments<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let arg = self.args.get(self.next)?;
+        self.next += 1;
+        Some(arg)
+    }
+
+    fn string(&mut self) -> &'a str {
+        self.next().unwrap_or("")
+    }
+
+    fn integer(&mut self) -> i64 {
+        let arg = self.string();
+        let (value, valid) = integer(arg);
+        if !valid {
+            error("printf", format!("{}: invalid number", arg));
+            self.failed = true;
+        }
+        value
+    }
+
+    fn float(&mut self) -> f64 {
+        let arg = self.string();
+        let trimmed = arg.trim_start();
+        if let Ok(value) = trimmed.parse::<f64>() {
+            return value;
+        }
+
+        let (value, valid) = integer(arg);
+        if !valid {
+            error("printf", format!("{}: invalid number", arg));
+            self.failed = true;
+        }
+        value as f64
+    }
+}
+
+/// Format `args`, repeating `format` until all of them are used.
+///
+/// Returns `Ok(false)` if an argument wasn't a valid number, it's printed as the part that could be parsed.
+fn printf_all(
+    format: &str,
+    args: &[String],
+    out: &mut Vec<u8>,
+) -> std::result::Result<bool, String> {
+    let mut args = Arguments {
+        args,
+        next: 0,
+        failed: false,
+    };
+
+    loop {
+        let start = args.next;
+        match printf_once(format, &mut args, out) {
+            Ok(()) => (),
+            Err(Stop::Escape) => break,
+            Err(Stop::Invalid(message)) => return Err(message),
+        }
+
+        // a format without conversions would repeat forever
+        if args.next >= args.args.len() || args.next == start {
+            break;
+        }
+    }
+    Ok(!args.failed)
+}
+
+/// Format the arguments once
+fn printf_once(
+    format: &str,
+    args: &mut Arguments,
+    out: &mut Vec<u8>,
+) -> std::result::Result<(), Stop> {
+    let mut rest = format;
+    while let Some(i) = rest.find(['%', '\\']) {
+        out.extend_from_slice(&rest.as_bytes()[..i]);
+        rest = &rest[i..];
+
+        if rest.starts_with('\\') {
+            let len = escape_len(rest, false);
+            unescape(&rest[..len], false, out);
+            rest = &rest[len..];
+            continue;
+        }
+
+        let (spec, len) = Spec::parse(&rest[1..], args)?;
+        rest = &rest[1 + len..];
+        if !spec.format(args, out) {
+            return Err(Stop::Escape);
+        }
+    }
+    out.extend_from_slice(rest.as_bytes());
+    Ok(())
+}
+
+/// A conversion specification, e.g. `%-08.3f`
+struct Spec {
+    flags: String,
+    width: Option<usize>,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+impl Spec {
+    /// Parse a specification after its `%`, and the length of the text it was parsed from.
+    ///
+    /// A `*` width or precision is taken from the arguments.
+    fn parse(s: &str, args: &mut Arguments) -> std::result::Result<(Spec, usize), Stop> {
+        let mut chars = s.char_indices().peekable();
+        let mut spec = Spec {
+            flags: String::new(),
+            width: None,
+            precision: None,
+            conversion: '%',
+        };
+
+        while let Some((_, c)) = chars.next_if(|(_, c)| "-+ #0".contains(*c)) {
+            if !spec.flags.contains(c) {
+                spec.flags.push(c);
+            }
+        }
+
+        if chars.next_if(|(_, c)| *c == '*').is_some() {
+            // a negative width left-aligns
+            let width = args.integer();
+            if width < 0 && !spec.flags.contains('-') {
+                spec.flags.push('-');
+            }
+            spec.width = Some(width.unsigned_abs() as usize);
+        } else {
+            spec.width = number(&mut chars);
+        }
+
+        if chars.next_if(|(_, c)| *c == '.').is_some() {
+            spec.precision = if chars.next_if(|(_, c)| *c == '*').is_some() {
+                // a negative precision is ignored
+                usize::try_from(args.integer()).ok()
+            } else {
+                Some(number(&mut chars).unwrap_or(0))
+            };
+        }
+
+        // length modifiers don't mean anything here, every integer is 64 bits
+        while chars.next_if(|(_, c)| "hlLjzt".contains(*c)).is_some() {}
+
+        match chars.next() {
+            Some((i, c)) if "diouxXeEfFgGaAcsbq%".contains(c) => {
+                spec.conversion = c;
+                Ok((spec, i + c.len_utf8()))
+            }
+            Some((i, c)) => Err(Stop::Invalid(format!(
+                "%{}: invalid format character",
+                &s[..i + c.len_utf8()]
+            ))),
+            None => Err(Stop::Invalid(format!("%{}: missing format character", s))),
+        }
+    }
+
+    /// Format the next argument, returns `false` if printing should stop
+    fn format(&self, args: &mut Arguments, out: &mut Vec<u8>) -> bool {
+        match self.conversion {
+            '%' => out.push(b'%'),
+            'd' | 'i' | 'o' | 'u' | 'x' | 'X' => {
+                let value = args.integer();
+                out.extend(self.c_format("ll", |buf, len, spec| unsafe {
+                    libc::snprintf(buf, len, spec, value as libc::c_longlong)
+                }));
+            }
+            'e' | 'E' | 'f' | 'F' | 'g' | 'G' | 'a' | 'A' => {
+                let value = args.float();
+                out.extend(self.c_format("", |buf, len, spec| unsafe {
+                    libc::snprintf(buf, len, spec, value as libc::c_double)
+                }));
+            }
+            'c' => {
+                let c = args.string().chars().next().map(String::from);
+                self.pad(c.as_deref().unwrap_or(""), out);
+            }
+            's' => self.pad(self.truncate(args.string()), out),
+            'q' => self.pad(&quote(args.string()), out),
+            _ => {
+                let mut expanded = Vec::new();
+                let complete = unescape(args.string(), true, &mut expanded);
+                let expanded = String::from_utf8_lossy(&expanded).to_string();
+                self.pad(self.truncate(&expanded), out);
+                return complete;
+            }
+        }
+        true
+    }
+
+    /// Cut a string to the precision, in characters
+    fn truncate<'a>(&self, s: &'a str) -> &'a str {
+        match self.precision.and_then(|p| s.char_indices().nth(p)) {
+            Some((i, _)) => &s[..i],
+            None => s,
+        }
+    }
+
+    /// Pad a string to the width, on the left unless the `-` flag was given
+    fn pad(&self, s: &str, out: &mut Vec<u8>) {
+        let padding = " ".repeat(self.width.unwrap_or(0).saturating_sub(s.chars().count()));
+        if self.flags.contains('-') {
+            out.extend_from_slice(s.as_bytes());
+            out.extend_from_slice(padding.as_bytes());
+        } else {
+            out.extend_from_slice(padding.as_bytes());
+            out.extend_from_slice(s.as_bytes());
+        }
+    }
+
+    /// Format a number with the C library, `print` is called with a buffer, its size, and the format specification
+    fn c_format<F>(&self, length: &str, print: F) -> Vec<u8>
+    where
+        F: Fn(*mut libc::c_char, libc::size_t, *const libc::c_char) -> libc::c_int,
+    {
+        let mut spec = format!("%{}", self.flags);
+        if let Some(width) = self.width {
+            spec.push_str(&width.to_string());
+        }
+        if let Some(precision) = self.precision {
+            spec.push_str(&format!(".{}", precision));
+        }
+        spec.push_str(length);
+        spec.push(self.conversion);
+        let spec = CString::new(spec).expect("format specifications don't contain null bytes");
+
+        let len = print(ptr::null_mut(), 0, spec.as_ptr());
+        let len = match usize::try_from(len) {
+            Ok(len) => len,
+            Err(_) => return Vec::new(),
+        };
+        let mut buf = vec![0u8; len + 1];
+        print(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            spec.as_ptr(),
+        );
+        buf.truncate(len);
+        buf
+    }
+}
+
+/// Parse a decimal number from the start of a specification
+fn number<I: Iterator<Item = (usize, char)>>(chars: &mut std::iter::Peekable<I>) -> Option<usize> {
+    let mut n: Option<usize> = None;
+    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+        let digit = c.to_digit(10).unwrap_or(0) as usize;
+        n = Some(n.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+    }
+    n
+}
+
+/// Parse an integer like C's `strtoll` with base 0, `0x` is hexadecimal and a leading `0` is octal.
+///
+/// A quote followed by a character is the character's code point.
+/// Returns the value of the longest valid prefix, and whether the whole argument was valid.
+fn integer(arg: &str) -> (i64, bool) {
+    if let Some(quoted) = arg.strip_prefix(['\'', '"']) {
+        return (quoted.chars().next().map_or(0, |c| c as i64), true);
+    }
+
+    let s = arg.trim_start();
+    let (negative, s) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let (radix, digits) = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        (16, hex)
+    } else if s.starts_with('0') {
+        (8, s)
+    } else {
+        (10, s)
+    };
+
+    let end = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    let mut valid = end > 0 && end == digits.len() || arg.is_empty();
+    let magnitude = match u64::from_str_radix(&digits[..end], radix) {
+        Ok(n) => n,
+        Err(_) if end == 0 => 0,
+        Err(_) => {
+            valid = false;
+            u64::MAX
+        }
+    };
+
+    let value = if negative {
+        0i64.checked_sub_unsigned(magnitude).unwrap_or_else(|| {
+            valid = false;
+            i64::MIN
+        })
+    } else {
+        // values that only fit in 64 bits unsigned are allowed, so `%u` and `%x` can print them
+        magnitude as i64
+    };
+    (value, valid)
+}
+
+/// Get the length of the escape sequence at the start of `s`
+fn escape_len(s: &str, echo: bool) -> usize {
+    let bytes = s.as_bytes();
+    let count = |start: usize, max: usize, valid: fn(&u8) -> bool| {
+        start
+            + bytes[start.min(bytes.len())..]
+                .iter()
+                .take(max)
+                .take_while(|b| valid(b))
+                .count()
+    };
+    let octal = |b: &u8| (b'0'..=b'7').contains(b);
+
+    match bytes.get(1) {
+        None => 1,
+        Some(b'0') if echo => count(2, 3, octal),
+        Some(b'0'..=b'7') if !echo => count(1, 3, octal),
+        Some(b'x') => count(2, 2, u8::is_ascii_hexdigit),
+        Some(b'u') => count(2, 4, u8::is_ascii_hexdigit),
+        Some(b'U') => count(2, 8, u8::is_ascii_hexdigit),
+        Some(_) => 1 + s[1..].chars().next().map_or(0, char::len_utf8),
+    }
+}
+
+/// Expand backslash escapes into `out`, returns `false` if `\c` was found, which ends the output.
+///
+/// `echo` selects `echo -e` and `%b`'s escapes: octal is `\0nnn` instead of the format's `\nnn`, and only they have `\c`.
+pub fn unescape(s: &str, echo: bool, out: &mut Vec<u8>) -> bool {
+    let mut rest = s;
+    while let Some(i) = rest.find('\\') {
+        out.extend_from_slice(&rest.as_bytes()[..i]);
+        rest = &rest[i..];
+        let len = escape_len(rest, echo);
+        let (escape, digits) = (&rest[..len], rest.get(2..len).unwrap_or(""));
+        rest = &rest[len..];
+
+        let hex = |digits: &str| u32::from_str_radix(digits, 16).ok();
+        let byte = match escape.as_bytes().get(1) {
+            // a trailing backslash is printed as it is
+            None => Some(b'\\'),
+            Some(b'a') => Some(7),
+            Some(b'b') => Some(8),
+            Some(b'e') | Some(b'E') => Some(27),
+            Some(b'f') => Some(12),
+            Some(b'n') => Some(b'\n'),
+            Some(b'r') => Some(b'\r'),
+            Some(b't') => Some(b'\t'),
+            Some(b'v') => Some(11),
+            Some(b'\\') => Some(b'\\'),
+            Some(b'"') | Some(b'\'') if !echo => Some(escape.as_bytes()[1]),
+            Some(b'c') if echo => return false,
+            Some(b'0') if echo => Some(u32::from_str_radix(digits, 8).unwrap_or(0) as u8),
+            Some(b'0'..=b'7') if !echo => {
+                Some(u32::from_str_radix(&escape[1..], 8).unwrap_or(0) as u8)
+            }
+            Some(b'x') if !digits.is_empty() => hex(digits).map(|n| n as u8),
+            Some(b'u') | Some(b'U') if !digits.is_empty() => {
+                let c = hex(digits).and_then(char::from_u32).unwrap_or('\u{FFFD}');
+                out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                continue;
+            }
+            _ => None,
+        };
+
+        match byte {
+            Some(byte) => out.push(byte),
+            // unknown escapes are printed as they are
+            None => out.extend_from_slice(escape.as_bytes()),
+        }
+    }
+    out.extend_from_slice(rest.as_bytes());
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::ffi::OsString;
+    use std::fs;
+
+    fn printf(format: &str, args: &[&str]) -> (String, bool) {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let mut out = Vec::new();
+        let valid = super::printf_all(format, &args, &mut out).unwrap_or(false);
+        (String::from_utf8(out).unwrap(), valid)
+    }
+
+    fn output(format: &str, args: &[&str]) -> String {
+        printf(format, args).0
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(output("%s-%s", &["a", "b"]), "a-b");
+        assert_eq!(
+            output("%5s|%-5s|%.2s", &["a", "b", "abc"]),
+            "    a|b    |ab"
+        );
+        assert_eq!(
+            output("%d %i %+d % d", &["1", "-2", "3", "4"]),
+            "1 -2 +3  4"
+        );
+        assert_eq!(
+            output("%05d|%-4d|%.3d", &["42", "7", "5"]),
+            "00042|7   |005"
+        );
+        assert_eq!(
+            output("%o %x %X %#x %u", &["8", "255", "255", "255", "-1"]),
+            "10 ff FF 0xff 18446744073709551615"
+        );
+        assert_eq!(
+            output("%.2f %e %g %G", &["3.14159", "1500", "0.0001", "1e20"]),
+            "3.14 1.500000e+03 0.0001 1E+20"
+        );
+        assert_eq!(output("%a", &["1"]), "0x1p+0");
+        assert_eq!(output("%c%c", &["hello", ""]), "h");
+        assert_eq!(output("%q", &["a b"]), "'a b'");
+        assert_eq!(output("100%%", &[]), "100%");
+        assert_eq!(
+            output("%*d|%-*s|%.*s", &["4", "1", "3", "a", "2", "xyz"]),
+            "   1|a  |xy"
+        );
+        assert_eq!(output("%ld %lld %hhd", &["1", "2", "3"]), "1 2 3");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(output("%d %d %d", &["0x10", "010", " +5"]), "16 8 5");
+        assert_eq!(output("%d %d", &["'A", "\"é"]), "65 233");
+        assert_eq!(output("%d %f", &["", ""]), "0 0.000000");
+        assert_eq!(printf("%d", &["12abc"]), ("12".to_string(), false));
+        assert_eq!(printf("%d", &["x"]), ("0".to_string(), false));
+        assert_eq!(printf("%f", &["0x10"]), ("16.000000".to_string(), true));
+    }
+
+    #[test]
+    fn reuse() {
+        assert_eq!(
+            output("%s=%s\n", &["a", "1", "b", "2", "c"]),
+            "a=1\nb=2\nc=\n"
+        );
+        assert_eq!(output("x\n", &["unused"]), "x\n");
+        assert_eq!(output("%s %d|", &[]), " 0|");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(output("a\\tb\\n", &[]), "a\tb\n");
+        assert_eq!(output("\\101\\x41\\u00e9\\\\\\q", &[]), "AAé\\\\q");
+        assert_eq!(output("%b", &["a\\0101\\101\\n"]), "aA\\101\n");
+        assert_eq!(output("a\\cb", &[]), "a\\cb");
+        assert_eq!(output("%s %b %s\n", &["x", "y\\cz", "w", "v"]), "x y");
+        assert_eq!(output("%5b|", &["a\\tb"]), "  a\tb|");
+    }
+
+    #[test]
+    fn invalid_format() {
+        let mut out = Vec::new();
+        let err = super::printf_all("a%yb", &[], &mut out).unwrap_err();
+        assert_eq!(err, "%y: invalid format character");
+        assert_eq!(out, b"a");
+        assert!(super::printf_all("%", &[], &mut out).is_err());
+    }
+
+    #[test]
+    fn builtin() {
+        forks!();
+
+        let out_file = "test/data/printf-out.txt";
+        let script = format!(
+            "printf -v x '%s-%03d' a 7; printf '%s\\n' \"$x\" > {out}; printf %d 1x 2>/dev/null >> {out}",
+            out = out_file
+        );
+
+        let mut ec = ExecutionContext::new();
+        let status = JobManager::new()
+            .run(&mut ec, Command::parse(script).expect("failed to parse"))
+            .expect("failed to run printf");
+        assert_eq!(status.exit_code, 1);
+        assert_eq!(ec.variables().value(&OsString::from("x")), "a-007");
+
+        let content = fs::read_to_string(out_file).expect("failed to read out file");
+        assert_eq!(content, "a-007\n1");
+    }
+
+    #[test]
+    fn pipeline() {
+        forks!();
+
+        // more output than fits in a pipe, `wc` has to be reading while printf and echo write
+        let out_file = "test/data/printf_pipeline-out.txt";
+        let script = format!(
+            "printf '%070000d' 0 | wc -c > {out}; printf -v x '%070000d' 0; echo \"$x\" | wc -c >> {out}",
+            out = out_file
+        );
+
+        let mut ec = ExecutionContext::new();
+        let status = JobManager::new()
+            .run(&mut ec, Command::parse(script).expect("failed to parse"))
+            .expect("failed to run printf");
+        assert_eq!(status.exit_code, 0);
+
+        let content = fs::read_to_string(out_file).expect("failed to read out file");
+        let counts: Vec<&str> = content.split_whitespace().collect();
+        assert_eq!(counts, vec!["70000", "70001"]);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::builtins::{error, is_name};
+use crate::lang::{ExecutionContext, JobManager, Result};
+use nix::errno::Errno;
+use nix::libc;
+use nix::poll::{poll, EventFlags, PollFd};
+use nix::unistd;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+const USAGE: &str =
+    "usage: read [-r] [-d delim] [-n nchars] [-p prompt] [-t timeout] [-u fd] [name ...]";
+
+/// Characters `IFS` has when it isn't set
+const DEFAULT_IFS: &[u8] = b" \t\n";
+
+/// `read [-r] [-d delim] [-n nchars] [-p prompt] [-t timeout] [-u fd] [name ...]`
+///
+/// Read a line from standard input, or `fd`, and split it into fields on the characters in `IFS`.
+/// Each name is assigned a field, the last one gets the rest of the line. Without any names the whole line is put in `REPLY`.
+/// Input is read one byte at a time, so nothing after the line is consumed.
+///
+/// A backslash escapes the next character, or joins the next line onto this one, unless `-r` is given.
+/// `-d` reads up to `delim` instead of a newline, `-n` stops after `nchars` characters,
+/// `-p` prints `prompt` first if the input is a terminal, and `-t` gives up after `timeout` seconds.
+///
+/// The status is 1 at the end of the input, and greater than 128 if the timeout expired.
+/// Whatever was read is still assigned in both cases.
+pub fn read(_jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let mut options = Options {
+        raw: false,
+        delim: b'\n',
+        nchars: None,
+        timeout: None,
+    };
+    let mut prompt = None;
+    let mut fd: RawFd = 0;
+
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i];
+        if arg == "--" {
+            i += 1;
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            break;
+        }
+
+        // options can be combined, and their values can be part of the same argument, e.g. `-rp>`
+        for (j, flag) in arg.char_indices().skip(1) {
+            if flag == 'r' {
+                options.raw = true;
+                continue;
+            }
+            if !"dnptu".contains(flag) {
+                error("read", format!("-{}: invalid option", flag));
+                error("read", USAGE);
+                return Ok(2);
+            }
+
+            let attached = &arg[j + flag.len_utf8()..];
+            let value = if attached.is_empty() {
+                i += 1;
+                match args.get(i) {
+                    Some(value) => value.as_str(),
+                    None => {
+                        error("read", format!("-{}: option requires an argument", flag));
+                        error("read", USAGE);
+                        return Ok(2);
+                    }
+                }
+            } else {
+                attached
+            };
+
+            let valid = match flag {
+                // an empty delimiter reads up to a null byte
+                'd' => {
+                    options.delim = value.bytes().next().unwrap_or(0);
+                    true
+                }
+                'n' => value.parse().map(|n| options.nchars = Some(n)).is_ok(),
+                'p' => {
+                    prompt = Some(value.to_string());
+                    true
+                }
+                't' => value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|t| t.is_finite() && *t >= 0.0)
+                    .map(|t| options.timeout = Some(Duration::from_secs_f64(t)))
+                    .is_some(),
+                _ => value.parse().map(|n| fd = n).is_ok(),
+            };
+            if !valid {
+                error("read", format!("{}: invalid argument for -{}", value, flag));
+                return Ok(2);
+            }
+            break;
+        }
+        i += 1;
+    }
+    let names = &args[i..];
+
+    if let Some(name) = names.iter().find(|name| !is_name(name)) {
+        error("read", format!("{}: not a valid identifier", name));
+        return Ok(1);
+    }
+
+    // a timeout of 0 only checks if there's input, without reading it
+    if options.timeout == Some(Duration::from_secs(0)) {
+        let mut fds = [PollFd::new(fd, EventFlags::POLLIN)];
+        return Ok(match poll(&mut fds, 0) {
+            Ok(n) if n > 0 => 0,
+            _ => 1,
+        });
+    }
+
+    if let Some(prompt) = prompt {
+        if unistd::isatty(fd).unwrap_or(false) {
+            let mut stderr = io::stderr();
+            let _ = write!(stderr, "{}", prompt).and_then(|_| stderr.flush());
+        }
+    }
+
+    let (input, end) = match read_input(fd, &options) {
+        Ok(result) => result,
+        Err(e) => {
+            error("read", format!("read error: {}: {}", fd, e));
+            return Ok(1);
+        }
+    };
+
+    let mut assigned = Ok(());
+    if names.is_empty() {
+        let line: Vec<u8> = input.iter().map(|(c, _)| *c).collect();
+        assigned = ec.variables_mut().set("REPLY", OsString::from_vec(line));
+    } else {
+        let ifs = if ec.variables().is_set(&OsString::from("IFS")) {
+            ec.variables()
+                .value(&OsString::from("IFS"))
+                .as_bytes()
+                .to_vec()
+        } else {
+            DEFAULT_IFS.to_vec()
+        };
+        let fields = split(&input, &ifs, names.len());
+        for (name, field) in names.iter().zip(fields) {
+            let result = ec
+                .variables_mut()
+                .set(name.as_str(), OsString::from_vec(field));
+            assigned = assigned.and(result);
+        }
+    }
+    if let Err(e) = assigned {
+        error("read", e.to_string());
+        return Ok(1);
+    }
+
+    Ok(match end {
+        End::Delimiter => 0,
+        End::Eof => 1,
+        End::Timeout => 128 + libc::SIGALRM,
+    })
+}
+
+struct Options {
+    /// Don't treat backslashes as escapes
+    raw: bool,
+    delim: u8,
+    nchars: Option<usize>,
+    timeout: Option<Duration>,
+}
+
+/// Why `read_input` stopped reading
+enum End {
+    /// The delimiter was read, or enough characters were
+    Delimiter,
+    Eof,
+    Timeout,
+}
+
+/// Read a line from `fd`, a byte at a time so nothing after it is consumed.
+///
+/// Backslash escapes are removed, bytes that were escaped are marked with `true`.
+fn read_input(fd: RawFd, options: &Options) -> nix::Result<(Vec<(u8, bool)>, End)> {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut input = Vec::new();
+    let mut escaped = false;
+
+    // characters are counted for -n, so multi-byte characters aren't split
+    let mut count = 0;
+    let mut continuation = 0;
+
+    loop {
+        if options.nchars.is_some_and(|n| count >= n) && continuation == 0 {
+            return Ok((input, End::Delimiter));
+        }
+
+        let byte = match read_byte(fd, deadline)? {
+            Ok(byte) => byte,
+            Err(end) => return Ok((input, end)),
+        };
+
+        let was_escaped = escaped;
+        if escaped {
+            escaped = false;
+            if byte == b'\n' {
+                continue;
+            }
+        } else if !options.raw && byte == b'\\' {
+            escaped = true;
+            continue;
+        } else if byte == options.delim {
+            return Ok((input, End::Delimiter));
+        }
+
+        input.push((byte, was_escaped));
+        if continuation > 0 {
+            continuation -= 1;
+        } else {
+            count += 1;
+            continuation = match byte {
+                0xf0..=0xff => 3,
+                0xe0..=0xef => 2,
+                0xc0..=0xdf => 1,
+                _ => 0,
+            };
+        }
+    }
+}
+
+/// Read one byte, `Err` says why there wasn't one
+fn read_byte(fd: RawFd, deadline: Option<Instant>) -> nix::Result<std::result::Result<u8, End>> {
+    let mut buf = [0u8; 1];
+    loop {
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut fds = [PollFd::new(fd, EventFlags::POLLIN)];
+            match poll(&mut fds, remaining.as_millis().min(i32::MAX as u128) as i32) {
+                Ok(0) => return Ok(Err(End::Timeout)),
+                Ok(_) => (),
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        match unistd::read(fd, &mut buf) {
+            Ok(0) => return Ok(Err(End::Eof)),
+            Ok(_) => return Ok(Ok(buf[0])),
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Split `input` into at most `count` fields on the characters in `ifs`, escaped characters never split fields.
+///
+/// Whitespace in `ifs` is trimmed from each field, and runs of it count as a single separator.
+/// The last field is the rest of the input, unless that's a single field followed by a separator.
+fn split(input: &[(u8, bool)], ifs: &[u8], count: usize) -> Vec<Vec<u8>> {
+    let is_ifs = |&(c, escaped): &(u8, bool)| !escaped && ifs.contains(&c);
+    let is_ifs_space = |&(c, escaped): &(u8, bool)| !escaped && ifs.contains(&c) && is_space(c);
+
+    // the end of the field starting at `start`, and the start of the next one
+    let field = |start: usize| {
+        let end = input[start..]
+            .iter()
+            .position(is_ifs)
+            .map_or(input.len(), |i| start + i);
+        let mut next = end;
+        while next < input.len() && is_ifs_space(&input[next]) {
+            next += 1;
+        }
+        if next < input.len() && is_ifs(&input[next]) && !is_ifs_space(&input[next]) {
+            next += 1;
+            while next < input.len() && is_ifs_space(&input[next]) {
+                next += 1;
+            }
+        }
+        (end, next)
+    };
+    let bytes = |range: &[(u8, bool)]| range.iter().map(|(c, _)| *c).collect::<Vec<u8>>();
+
+    let mut start = input
+        .iter()
+        .position(|c| !is_ifs_space(c))
+        .unwrap_or(input.len());
+    let end = input
+        .iter()
+        .rposition(|c| !is_ifs_space(c))
+        .map_or(0, |i| i + 1);
+
+    let mut fields = Vec::with_capacity(count);
+    for _ in 1..count {
+        let (field_end, next) = field(start);
+        fields.push(bytes(&input[start.min(field_end)..field_end]));
+        start = next.min(input.len());
+    }
+
+    let rest = if start >= end {
+        Vec::new()
+    } else {
+        match field(start) {
+            (field_end, next) if next >= end => bytes(&input[start..field_end.min(end)]),
+            _ => bytes(&input[start..end]),
+        }
+    };
+    fields.push(rest);
+    fields
+}
+
+fn is_space(c: u8) -> bool {
+    c == b' ' || c == b'\t' || c == b'\n'
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::ffi::OsString;
+
+    fn run(jm: &mut JobManager, ec: &mut ExecutionContext, script: &str) -> i32 {
+        jm.run(ec, Command::parse(script).unwrap())
+            .expect("failed to run script")
+            .exit_code
+    }
+
+    fn value(ec: &ExecutionContext, name: &str) -> OsString {
+        ec.variables().value(&OsString::from(name))
+    }
+
+    #[test]
+    fn split() {
+        let split = |input: &str, ifs: &str, count: usize| -> Vec<String> {
+            let input: Vec<(u8, bool)> = input.bytes().map(|c| (c, false)).collect();
+            super::split(&input, ifs.as_bytes(), count)
+                .into_iter()
+                .map(|field| String::from_utf8(field).unwrap())
+                .collect()
+        };
+
+        assert_eq!(split("  a  b   c  ", " ", 2), vec!["a", "b   c"]);
+        assert_eq!(split("a b", " ", 3), vec!["a", "b", ""]);
+        assert_eq!(split("x:y:", ":", 2), vec!["x", "y"]);
+        assert_eq!(split("x:y::", ":", 2), vec!["x", "y::"]);
+        assert_eq!(split("y::", ":", 2), vec!["y", ""]);
+        assert_eq!(split("x : y :", " :", 2), vec!["x", "y"]);
+        assert_eq!(split("  x y  ", "", 2), vec!["  x y  ", ""]);
+    }
+
+    #[test]
+    fn read() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        // every read continues where the last one stopped
+        assert_eq!(run(&mut jm, &mut ec, "exec 9<test/data/read.txt"), 0);
+        assert_eq!(run(&mut jm, &mut ec, "read -u 9 a b"), 0);
+        assert_eq!(value(&ec, "a"), "one");
+        assert_eq!(value(&ec, "b"), "two  three");
+
+        assert_eq!(run(&mut jm, &mut ec, "read -u9 a"), 0);
+        assert_eq!(value(&ec, "a"), "back slash continued");
+        assert_eq!(run(&mut jm, &mut ec, "read -r -u 9 a b"), 0);
+        assert_eq!(value(&ec, "a"), "raw\\");
+        assert_eq!(value(&ec, "b"), "line\\");
+
+        ec.variables_mut().define("IFS", ":");
+        assert_eq!(run(&mut jm, &mut ec, "read -d . -u 9 a b c"), 0);
+        assert_eq!(value(&ec, "a"), "x");
+        assert_eq!(value(&ec, "b"), "y ");
+        assert_eq!(value(&ec, "c"), "");
+        ec.variables_mut().remove(&OsString::from("IFS"));
+
+        assert_eq!(run(&mut jm, &mut ec, "read -rn 2 -u 9"), 0);
+        assert_eq!(value(&ec, "REPLY"), "éa");
+        assert_eq!(run(&mut jm, &mut ec, "read -u 9"), 1);
+        assert_eq!(value(&ec, "REPLY"), "nd");
+        assert_eq!(run(&mut jm, &mut ec, "exec 9<&-"), 0);
+
+        assert_eq!(run(&mut jm, &mut ec, "sleep 0.3 | read -t 0.05 a"), 142);
+        assert_eq!(run(&mut jm, &mut ec, "read -t 0 </dev/null"), 0);
+        assert_eq!(run(&mut jm, &mut ec, "read 1a </dev/null 2>/dev/null"), 1);
+        assert_eq!(run(&mut jm, &mut ec, "read -x 2>/dev/null"), 2);
+    }
+}
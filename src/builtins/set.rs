@@ -0,0 +1,123 @@
+use crate::builtins::{error, quote_value};
+use crate::env::options::OPTIONS;
+use crate::lang::{ExecutionContext, JobManager, Result};
+use std::io::{self, Write};
+
+/// `set [-+uno] [-o option|+o option] [--] [arg ...]`
+///
+/// Set shell options and positional parameters.
+/// With no arguments all variables are printed, `-o` alone lists options and `+o` alone prints the commands to restore them.
+pub fn set(_jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let mut stdout = io::stdout();
+
+    if args.len() == 1 {
+        for (name, value) in ec.variables().iter() {
+            let _ = writeln!(stdout, "{}={}", name.to_string_lossy(), quote_value(value));
+        }
+        return Ok(0);
+    }
+
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i];
+        let enable = match arg.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => break,
+        };
+
+        if arg == "--" {
+            i += 1;
+            ec.variables_mut().set_positional(&args[i..]);
+            return Ok(0);
+        }
+
+        if arg == "-" {
+            // `set -` ends options like `--`, but doesn't clear the positional parameters
+            i += 1;
+            break;
+        }
+
+        for flag in arg.chars().skip(1) {
+            if flag == 'o' {
+                i += 1;
+                match args.get(i) {
+                    Some(name) => {
+                        if !ec.variables_mut().options_mut().set(name, enable) {
+                            error("set", format!("{}: invalid option name", name));
+                            return Ok(2);
+                        }
+                    }
+                    None => {
+                        print_options(&mut stdout, ec, enable);
+                        return Ok(0);
+                    }
+                }
+            } else if !ec.variables_mut().options_mut().set_flag(flag, enable) {
+                error("set", format!("{}{}: invalid option", &arg[..1], flag));
+                return Ok(2);
+            }
+        }
+
+        i += 1;
+    }
+
+    if i < args.len() {
+        ec.variables_mut().set_positional(&args[i..]);
+    }
+
+    Ok(0)
+}
+
+/// Print all options, either as a table (`set -o`) or as commands that recreate the current settings (`set +o`)
+fn print_options<W: Write>(out: &mut W, ec: &ExecutionContext, table: bool) {
+    for (_, name) in OPTIONS {
+        let enabled = ec.variables().options().get(name).unwrap_or(false);
+        let _ = if table {
+            writeln!(out, "{:<15} {}", name, if enabled { "on" } else { "off" })
+        } else {
+            writeln!(out, "set {}o {}", if enabled { '-' } else { '+' }, name)
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ExecutionContext, JobManager};
+    use std::ffi::OsString;
+
+    fn set(ec: &mut ExecutionContext, args: &[&str]) -> i32 {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        super::set(&mut JobManager::new(), ec, &args).expect("set failed")
+    }
+
+    #[test]
+    fn options() {
+        let mut ec = ExecutionContext::new();
+
+        assert_eq!(set(&mut ec, &["set", "-u"]), 0);
+        assert!(ec.variables().options().nounset);
+        assert_eq!(set(&mut ec, &["set", "+u", "-o", "noexec"]), 0);
+        assert!(!ec.variables().options().nounset);
+        assert!(ec.variables().options().noexec);
+        assert_eq!(ec.variables().value(&OsString::from("-")), "n");
+
+        assert_eq!(set(&mut ec, &["set", "-X"]), 2);
+        assert_eq!(set(&mut ec, &["set", "-o", "notanoption"]), 2);
+    }
+
+    #[test]
+    fn positional() {
+        let mut ec = ExecutionContext::new();
+
+        assert_eq!(set(&mut ec, &["set", "-u", "a", "b"]), 0);
+        assert_eq!(ec.variables().value(&OsString::from("#")), "2");
+        assert_eq!(ec.variables().value(&OsString::from("2")), "b");
+
+        assert_eq!(set(&mut ec, &["set", "--", "-x"]), 0);
+        assert_eq!(ec.variables().value(&OsString::from("1")), "-x");
+
+        assert_eq!(set(&mut ec, &["set", "--"]), 0);
+        assert_eq!(ec.variables().value(&OsString::from("#")), "0");
+    }
+}
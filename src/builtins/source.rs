@@ -0,0 +1,95 @@
+use crate::builtins::error;
+use crate::lang::{ast::Command, ExecutionContext, JobManager, Result};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+/// `. file [arg ...]` or `source file [arg ...]`
+///
+/// Run the commands in `file` in the current shell, `return` stops running the file early.
+/// A file name without a slash is searched for in `PATH`, then in the current directory.
+/// Any arguments replace the positional parameters until the file has been run.
+pub fn source(jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let name = match args.get(1) {
+        Some(name) => name,
+        None => {
+            error(&args[0], "filename argument required");
+            error(&args[0], format!("usage: {} filename [arguments]", args[0]));
+            return Ok(2);
+        }
+    };
+
+    let path = if name.contains('/') {
+        PathBuf::from(name)
+    } else {
+        env::split_paths(&ec.variables().value(&OsString::from("PATH")))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| PathBuf::from(name))
+    };
+
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            error(&args[0], format!("{}: {}", name, e));
+            return Ok(1);
+        }
+    };
+    let script = match Command::parse(&data) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("{}", e.in_script(name));
+            return Ok(2);
+        }
+    };
+
+    let positional = if args.len() > 2 {
+        Some(ec.variables_mut().set_positional(&args[2..]))
+    } else {
+        None
+    };
+    let script_name = ec.script_name().to_string();
+    ec.set_script_name(name.as_str());
+
+    let result = jm.call(ec, script);
+
+    ec.set_script_name(script_name);
+    if let Some(positional) = positional {
+        ec.variables_mut().set_positional(positional);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::ffi::OsString;
+
+    fn run(jm: &mut JobManager, ec: &mut ExecutionContext, script: &str) -> i32 {
+        jm.run(ec, Command::parse(script).unwrap())
+            .expect("failed to run script")
+            .exit_code
+    }
+
+    #[test]
+    fn source() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        ec.variables_mut().set_positional(vec!["outer"]);
+        let script = ". test/data/source.rush first second";
+        assert_eq!(run(&mut jm, &mut ec, script), 3);
+        assert_eq!(ec.variables().value(&OsString::from("1")), "outer");
+        assert!(ec.functions().value(&"sourced".to_string()).is_some());
+
+        assert_eq!(run(&mut jm, &mut ec, "source test/data/source.rush"), 0);
+        assert_eq!(ec.variables().value(&OsString::from("1")), "outer");
+
+        assert_eq!(run(&mut jm, &mut ec, ". rush-not-a-file 2>/dev/null"), 1);
+        assert_eq!(run(&mut jm, &mut ec, ". 2>/dev/null"), 2);
+    }
+}
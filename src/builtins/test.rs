@@ -0,0 +1,355 @@
+use crate::builtins::error;
+use crate::lang::{ExecutionContext, JobManager, Result};
+use nix::libc;
+use nix::unistd;
+use std::ffi::CString;
+use std::fs::{self, Metadata};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::Path;
+
+/// `test expression` or `[ expression ]`
+///
+/// Check file attributes, compare strings or compare integers. The status is 0 if the expression is true,
+/// 1 if it's false and 2 if it's invalid.
+///
+/// The meaning of up to 4 arguments depends on how many there are, as specified by POSIX,
+/// so e.g. `test -n` and `test ! =` are both string tests. Longer expressions are parsed with
+/// `!` binding tightest, then `-a`, then `-o`, and parentheses for grouping.
+pub fn test(_jm: &mut JobManager, _ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let name = args[0].as_str();
+    let mut args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+    if name == "[" {
+        if args.last() != Some(&"]") {
+            error(name, "missing `]'");
+            return Ok(2);
+        }
+        args.pop();
+    }
+
+    Ok(match evaluate(&args) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(message) => {
+            error(name, message);
+            2
+        }
+    })
+}
+
+pub type Test = std::result::Result<bool, String>;
+
+/// Evaluate an expression, the argument count decides what it means before the general grammar is tried
+fn evaluate(args: &[&str]) -> Test {
+    match *args {
+        [] => Ok(false),
+        [s] => Ok(!s.is_empty()),
+        ["!", a] => evaluate(&[a]).map(|b| !b),
+        [op, a] if is_unary(op) => unary(op, a),
+        [op, _] => Err(format!("{}: unary operator expected", op)),
+        [a, op, b] if is_binary(op) || op == "-a" || op == "-o" => binary(a, op, b),
+        ["!", a, b] => evaluate(&[a, b]).map(|b| !b),
+        ["(", a, ")"] => evaluate(&[a]),
+        ["!", ..] if args.len() == 4 => evaluate(&args[1..]).map(|b| !b),
+        ["(", a, b, ")"] => evaluate(&[a, b]),
+        _ => {
+            let mut parser = Parser { args, pos: 0 };
+            let result = parser.or()?;
+            match parser.peek() {
+                None => Ok(result),
+                Some(token) => Err(format!("{}: unexpected argument", token)),
+            }
+        }
+    }
+}
+
+/// Recursive descent parser for expressions that are too long to be decided by their argument count
+struct Parser<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.args.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> std::result::Result<&'a str, String> {
+        let token = self.peek().ok_or("argument expected")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Test {
+        let mut result = self.and()?;
+        while self.peek() == Some("-o") {
+            self.pos += 1;
+            result = self.and()? || result;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Test {
+        let mut result = self.not()?;
+        while self.peek() == Some("-a") {
+            self.pos += 1;
+            result = self.not()? && result;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Test {
+        if self.peek() == Some("!") && self.args.len() > self.pos + 1 {
+            self.pos += 1;
+            return self.not().map(|b| !b);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Test {
+        let token = self.next()?;
+
+        // a binary operator takes precedence, so e.g. `( = )` compares two parentheses
+        if let (Some(op), Some(b)) = (self.args.get(self.pos), self.args.get(self.pos + 1)) {
+            if is_binary(op) {
+                self.pos += 2;
+                return binary(token, op, b);
+            }
+        }
+
+        if token == "(" {
+            let result = self.or()?;
+            return match self.next() {
+                Ok(")") => Ok(result),
+                _ => Err("`)' expected".to_string()),
+            };
+        }
+
+        if is_unary(token) {
+            if let Some(a) = self.peek() {
+                self.pos += 1;
+                return unary(token, a);
+            }
+        }
+        Ok(!token.is_empty())
+    }
+}
+
+fn is_unary(op: &str) -> bool {
+    matches!(
+        op,
+        "-b" | "-c"
+            | "-d"
+            | "-e"
+            | "-f"
+            | "-g"
+            | "-h"
+            | "-k"
+            | "-L"
+            | "-n"
+            | "-p"
+            | "-r"
+            | "-S"
+            | "-s"
+            | "-t"
+            | "-u"
+            | "-w"
+            | "-x"
+            | "-z"
+    )
+}
+
+/// Check if `op` is a binary operator, other than `-a` and `-o`
+fn is_binary(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "!=" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" | "-nt" | "-ot" | "-ef"
+    )
+}
+
+/// Evaluate a unary primary like `-f file`, `[[ ]]` shares these with `test`
+pub fn unary(op: &str, a: &str) -> Test {
+    let path = Path::new(a);
+    let metadata = || fs::metadata(path).ok();
+    let file_type = |check: fn(&Metadata) -> bool| Ok(metadata().is_some_and(|m| check(&m)));
+
+    match op {
+        "-n" => Ok(!a.is_empty()),
+        "-z" => Ok(a.is_empty()),
+        "-b" => file_type(|m| m.file_type().is_block_device()),
+        "-c" => file_type(|m| m.file_type().is_char_device()),
+        "-d" => file_type(Metadata::is_dir),
+        "-e" => Ok(metadata().is_some()),
+        "-f" => file_type(Metadata::is_file),
+        "-g" => file_type(|m| m.permissions().mode() & libc::S_ISGID as u32 != 0),
+        "-k" => file_type(|m| m.permissions().mode() & libc::S_ISVTX as u32 != 0),
+        "-u" => file_type(|m| m.permissions().mode() & libc::S_ISUID as u32 != 0),
+        "-p" => file_type(|m| m.file_type().is_fifo()),
+        "-S" => file_type(|m| m.file_type().is_socket()),
+        "-s" => file_type(|m| m.len() > 0),
+        "-h" | "-L" => Ok(fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())),
+        "-r" => Ok(access(path, libc::R_OK)),
+        "-w" => Ok(access(path, libc::W_OK)),
+        "-x" => Ok(access(path, libc::X_OK)),
+        _ => {
+            let fd = integer(a)?;
+            Ok(fd >= 0 && fd <= i64::from(i32::MAX) && unistd::isatty(fd as i32).unwrap_or(false))
+        }
+    }
+}
+
+/// Evaluate a binary primary like `a = b` or `1 -lt 2`
+pub fn binary(a: &str, op: &str, b: &str) -> Test {
+    let mtime = |path: &str| fs::metadata(path).ok().map(|m| (m.mtime(), m.mtime_nsec()));
+
+    match op {
+        "=" => Ok(a == b),
+        "!=" => Ok(a != b),
+        "-a" => Ok(!a.is_empty() && !b.is_empty()),
+        "-o" => Ok(!a.is_empty() || !b.is_empty()),
+        // a file that exists is newer than one that doesn't
+        "-nt" => Ok(match (mtime(a), mtime(b)) {
+            (Some(a), Some(b)) => a > b,
+            (a, _) => a.is_some(),
+        }),
+        "-ot" => Ok(match (mtime(a), mtime(b)) {
+            (Some(a), Some(b)) => a < b,
+            (_, b) => b.is_some(),
+        }),
+        "-ef" => Ok(match (fs::metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }),
+        _ => {
+            let (a, b) = (integer(a)?, integer(b)?);
+            Ok(match op {
+                "-eq" => a == b,
+                "-ne" => a != b,
+                "-lt" => a < b,
+                "-le" => a <= b,
+                "-gt" => a > b,
+                _ => a >= b,
+            })
+        }
+    }
+}
+
+/// Parse an integer operand, surrounding blanks are allowed
+fn integer(s: &str) -> std::result::Result<i64, String> {
+    let trimmed = s.trim_matches([' ', '\t']);
+    trimmed
+        .strip_prefix('+')
+        .unwrap_or(trimmed)
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", s))
+}
+
+/// Check if the shell can access `path`, using its real user and group ids like other shells
+fn access(path: &Path, mode: libc::c_int) -> bool {
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod test {
+    use crate::lang::{ExecutionContext, JobManager};
+    use std::fs;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    fn test(args: &[&str]) -> i32 {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        super::test(&mut JobManager::new(), &mut ExecutionContext::new(), &args)
+            .expect("test failed")
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(test(&["test"]), 1);
+        assert_eq!(test(&["test", ""]), 1);
+        assert_eq!(test(&["test", "-n"]), 0);
+        assert_eq!(test(&["test", "!", "-z"]), 1);
+        assert_eq!(test(&["test", "-z", ""]), 0);
+        assert_eq!(test(&["test", "-n", ""]), 1);
+        assert_eq!(test(&["test", "a", "=", "a"]), 0);
+        assert_eq!(test(&["test", "a", "!=", "a"]), 1);
+        assert_eq!(test(&["test", "!", "=", "="]), 1);
+        assert_eq!(test(&["test", "(", "=", ")"]), 1);
+        assert_eq!(test(&["test", "(", "-z", ")"]), 0);
+        assert_eq!(test(&["test", "!", "a", "=", "b"]), 0);
+        assert_eq!(test(&["test", "(", "-n", "", ")"]), 1);
+        assert_eq!(test(&["[", "-n", "x", "]"]), 0);
+        assert_eq!(test(&["[", "-n", "x"]), 2);
+        assert_eq!(test(&["test", "x", "-y"]), 2);
+        assert_eq!(test(&["test", "a", "b", "c"]), 2);
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(test(&["test", "1", "-eq", " +1 "]), 0);
+        assert_eq!(test(&["test", "-3", "-lt", "2"]), 0);
+        assert_eq!(test(&["test", "2", "-le", "2"]), 0);
+        assert_eq!(test(&["test", "2", "-gt", "2"]), 1);
+        assert_eq!(test(&["test", "2", "-ge", "3"]), 1);
+        assert_eq!(test(&["test", "2", "-ne", "3"]), 0);
+        assert_eq!(test(&["test", "x", "-eq", "3"]), 2);
+        assert_eq!(test(&["test", "-t", "x"]), 2);
+    }
+
+    #[test]
+    fn compound() {
+        assert_eq!(test(&["test", "a", "-a", ""]), 1);
+        assert_eq!(test(&["test", "a", "-o", ""]), 0);
+        assert_eq!(test(&["test", "1", "-eq", "1", "-a", "x", "=", "y"]), 1);
+        assert_eq!(test(&["test", "1", "-eq", "1", "-o", "x", "=", "y"]), 0);
+        // -a binds tighter than -o
+        assert_eq!(test(&["test", "x", "-o", "", "-a", ""]), 0);
+        assert_eq!(test(&["test", "(", "x", "-o", "", ")", "-a", ""]), 1);
+        assert_eq!(test(&["test", "!", "!", "-n", "x", "-a", "y"]), 0);
+        assert_eq!(test(&["[", "(", "x", "-o", "y", "]"]), 2);
+    }
+
+    #[test]
+    fn files() {
+        let dir = std::env::temp_dir().join(format!("rush-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("failed to create directory");
+        let (empty, script, link) = (dir.join("empty"), dir.join("script"), dir.join("link"));
+        fs::write(&empty, "").expect("failed to create file");
+        fs::write(&script, "exit 0").expect("failed to create file");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+            .expect("failed to set permissions");
+        symlink(&script, &link).expect("failed to create symlink");
+        let dir = dir.to_str().unwrap();
+        let (empty, script, link) = (
+            empty.to_str().unwrap(),
+            script.to_str().unwrap(),
+            link.to_str().unwrap(),
+        );
+
+        assert_eq!(test(&["test", "-e", empty]), 0);
+        assert_eq!(test(&["test", "-e", "/rush/not/a/file"]), 1);
+        assert_eq!(test(&["test", "-f", empty]), 0);
+        assert_eq!(test(&["test", "-f", dir]), 1);
+        assert_eq!(test(&["test", "-d", dir]), 0);
+        assert_eq!(test(&["test", "-s", empty]), 1);
+        assert_eq!(test(&["test", "-s", script]), 0);
+        assert_eq!(test(&["test", "-x", script]), 0);
+        assert_eq!(test(&["test", "-x", empty]), 1);
+        assert_eq!(test(&["test", "-r", empty]), 0);
+        assert_eq!(test(&["test", "-L", link]), 0);
+        assert_eq!(test(&["test", "-h", script]), 1);
+        assert_eq!(test(&["test", "-f", link]), 0);
+        assert_eq!(test(&["test", "-c", "/dev/null"]), 0);
+        assert_eq!(test(&["test", "-b", "/dev/null"]), 1);
+        assert_eq!(test(&["test", "-p", empty]), 1);
+        assert_eq!(test(&["test", "-S", empty]), 1);
+        assert_eq!(test(&["test", link, "-ef", script]), 0);
+        assert_eq!(test(&["test", empty, "-ef", script]), 1);
+        assert_eq!(test(&["test", empty, "-nt", "/rush/not/a/file"]), 0);
+        assert_eq!(test(&["test", empty, "-ot", "/rush/not/a/file"]), 1);
+
+        fs::remove_dir_all(dir).expect("failed to remove test directory");
+    }
+}
@@ -0,0 +1,135 @@
+use crate::builtins::{error, list_signals, quote};
+use crate::env::traps::{self, Action};
+use crate::lang::{ExecutionContext, JobManager, Result};
+use std::io::{self, Write};
+
+/// `trap [-lp] [action] [condition ...]`
+///
+/// Evaluate `action` when one of the signals is received, after the current command finishes.
+/// Besides signals, traps can be set on `EXIT`, `ERR` (a command failed), `DEBUG` (before each simple command)
+/// and `RETURN` (a function finished).
+/// An empty action ignores the signals and `-` resets them to their default behavior.
+/// With no arguments, or with `-p`, the current traps are printed as commands that recreate them.
+/// `-l` lists the names and numbers of every signal.
+pub fn trap(_jm: &mut JobManager, _ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let mut print = false;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-p" => print = true,
+            "-l" => {
+                list_signals();
+                return Ok(0);
+            }
+            "--" => {
+                i += 1;
+                break;
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+    let args = &args[i..];
+
+    if print || args.is_empty() {
+        return Ok(print_traps(args));
+    }
+
+    // a lone signal, or a list starting with a signal number, resets every signal in the list
+    let (action, signals) = if args.len() == 1 || args[0].parse::<u32>().is_ok() {
+        ("-", args)
+    } else {
+        (args[0].as_str(), &args[1..])
+    };
+
+    let mut status = 0;
+    for name in signals {
+        let condition = match traps::parse_condition(name) {
+            Some(condition) => condition,
+            None => {
+                error("trap", format!("{}: invalid signal specification", name));
+                status = 1;
+                continue;
+            }
+        };
+
+        let result = match action {
+            "-" => traps::release(condition),
+            "" => traps::set(condition, Action::NoOp),
+            command => traps::set(condition, Action::Eval(command.to_string())),
+        };
+        if let Err(e) = result {
+            error("trap", format!("{}: {}", name, e));
+            status = 1;
+        }
+    }
+
+    Ok(status)
+}
+
+/// Print the traps for `signals`, or every trap if no signals are given
+fn print_traps(signals: &[String]) -> i32 {
+    let mut status = 0;
+    let mut filter = Vec::with_capacity(signals.len());
+    for name in signals {
+        match traps::parse_condition(name) {
+            Some(condition) => filter.push(condition),
+            None => {
+                error("trap", format!("{}: invalid signal specification", name));
+                status = 1;
+            }
+        }
+    }
+
+    let mut stdout = io::stdout();
+    for (condition, command) in traps::list() {
+        if filter.is_empty() || filter.contains(&condition) {
+            let _ = writeln!(stdout, "trap -- {} {}", quote(command), condition);
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::traps::{self, Signal};
+    use crate::lang::{ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::ffi::OsString;
+
+    fn trap(ec: &mut ExecutionContext, args: &[&str]) -> i32 {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        super::trap(&mut JobManager::new(), ec, &args).expect("trap failed")
+    }
+
+    #[test]
+    fn deferred() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        assert_eq!(trap(&mut ec, &["trap", "set -- trapped", "USR1"]), 0);
+        assert!(traps::list().contains(&(Signal::SIGUSR1.into(), "set -- trapped".to_string())));
+
+        ec.variables_mut().set_last_status(3);
+        Signal::SIGUSR1.raise().expect("failed to raise SIGUSR1");
+        assert_eq!(ec.variables().value(&OsString::from("1")), "");
+
+        jm.run_traps(&mut ec).expect("failed to run traps");
+        assert_eq!(ec.variables().value(&OsString::from("1")), "trapped");
+        assert_eq!(ec.variables().last_status(), 3);
+
+        assert_eq!(trap(&mut ec, &["trap", "-", "SIGUSR1"]), 0);
+        assert!(!traps::is_trapped(Signal::SIGUSR1));
+    }
+
+    #[test]
+    fn invalid_signal() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        assert_eq!(trap(&mut ec, &["trap", "true", "NOTASIGNAL"]), 1);
+        assert_eq!(trap(&mut ec, &["trap", "-p", "NOTASIGNAL"]), 1);
+    }
+}
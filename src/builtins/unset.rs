@@ -0,0 +1,137 @@
+use crate::builtins::{error, is_name};
+use crate::env::variables::AssignError;
+use crate::lang::{ExecutionContext, JobManager, Result};
+use std::ffi::OsString;
+
+/// `unset [-f | -v] name ...`
+///
+/// Unset variables with `-v`, or functions with `-f`.
+/// Without either, each name is a variable, or a function if there's no variable with that name.
+/// `name[subscript]` unsets one element of an array. Readonly variables can't be unset.
+pub fn unset(_jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let (mut functions, mut variables) = (false, false);
+    let mut names = &args[1..];
+    while let Some(option) = names.first() {
+        if option == "--" {
+            names = &names[1..];
+            break;
+        }
+        if !option.starts_with('-') || option.len() == 1 {
+            break;
+        }
+
+        for flag in option.chars().skip(1) {
+            match flag {
+                'f' => functions = true,
+                'v' => variables = true,
+                _ => {
+                    error("unset", format!("-{}: invalid option", flag));
+                    error("unset", "usage: unset [-f] [-v] [name ...]");
+                    return Ok(2);
+                }
+            }
+        }
+        names = &names[1..];
+    }
+
+    if functions && variables {
+        error(
+            "unset",
+            "cannot simultaneously unset a function and a variable",
+        );
+        return Ok(1);
+    }
+
+    let mut status = 0;
+    for arg in names {
+        // `name[subscript]` is an element of an array
+        let (name, subscript) = match arg.split_once('[') {
+            Some((name, subscript)) if !functions && subscript.ends_with(']') => {
+                (name, Some(&subscript[..subscript.len() - 1]))
+            }
+            _ => (arg.as_str(), None),
+        };
+        let var = OsString::from(name);
+        let is_variable =
+            ec.variables().is_set(&var) || ec.variables().attributes(&var) != Default::default();
+        if functions
+            || (!variables && !is_variable && subscript.is_none() && ec.functions().exists(arg))
+        {
+            ec.functions_mut().remove(arg);
+            continue;
+        }
+
+        if !is_name(name) {
+            error("unset", format!("`{}': not a valid identifier", arg));
+            status = 1;
+            continue;
+        }
+        let result = match subscript {
+            Some(subscript) => ec.variables_mut().unset_element(&var, subscript),
+            None => ec.variables_mut().unset(&var),
+        };
+        match result {
+            Ok(()) => (),
+            Err(AssignError::Readonly(name)) => {
+                error(
+                    "unset",
+                    format!("{}: cannot unset: readonly variable", name),
+                );
+                status = 1;
+            }
+            Err(e) => {
+                error("unset", e.to_string());
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::ffi::OsString;
+
+    fn run(ec: &mut ExecutionContext, script: &str) -> i32 {
+        JobManager::new()
+            .run(ec, Command::parse(script).expect("failed to parse"))
+            .expect("failed to run script")
+            .exit_code
+    }
+
+    #[test]
+    fn unset() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let is_set =
+            |ec: &ExecutionContext, name: &str| ec.variables().is_set(&OsString::from(name));
+
+        ec.variables_mut().define("a", "1");
+        ec.variables_mut().define("b", "2");
+        assert_eq!(run(&mut ec, "unset a; unset -v b"), 0);
+        assert!(!is_set(&ec, "a"));
+        assert!(!is_set(&ec, "b"));
+
+        assert_eq!(run(&mut ec, "declare -n ref=c; declare c=3; unset ref"), 0);
+        assert!(!is_set(&ec, "c"));
+        assert!(ec.variables().get(&"ref".into()).is_some());
+
+        assert_eq!(run(&mut ec, "a=(x y z); unset 'a[1]' a[-1]"), 0);
+        assert_eq!(
+            ec.variables().elements(&"a".into()),
+            vec![OsString::from("x")]
+        );
+
+        assert_eq!(run(&mut ec, "readonly r=1; unset r 2>/dev/null"), 1);
+        assert!(is_set(&ec, "r"));
+        assert_eq!(run(&mut ec, "unset 1a 2>/dev/null"), 1);
+
+        assert_eq!(run(&mut ec, "function f { :; }; unset f"), 0);
+        assert!(!ec.functions().exists(&"f".to_string()));
+        assert_eq!(run(&mut ec, "function g { :; }; unset -v g; unset -f g"), 0);
+        assert!(!ec.functions().exists(&"g".to_string()));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::builtins::error;
+use crate::lang::exec::Jid;
+use crate::lang::{ExecutionContext, JobManager, Result};
+use nix::unistd::Pid;
+
+/// `wait [pid|%job ...]`
+///
+/// Wait for background jobs to finish, and return the status of the last one.
+/// With no arguments every background job is waited for, and the status is 0.
+/// If a trapped signal is received, its trap is run and `wait` returns 128 plus the signal's number.
+pub fn wait(jm: &mut JobManager, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+    let args = match args.get(1).map(String::as_str) {
+        Some("--") => &args[2..],
+        _ => &args[1..],
+    };
+
+    if args.is_empty() {
+        let jids: Vec<Jid> = jm.background_jobs().iter().map(|job| job.jid).collect();
+        if let Some(sig) = jm.await_interruptible(ec, &jids)? {
+            return Ok(128 + sig.number());
+        }
+        for jid in jids {
+            jm.forget_job(jid);
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let jid = if arg.starts_with('%') {
+            jm.find_job(arg).map(|job| job.jid).ok_or("no such job")
+        } else {
+            match arg.parse::<i32>() {
+                Ok(pid) => jm
+                    .find_pid(Pid::from_raw(pid))
+                    .ok_or("not a child of this shell"),
+                Err(_) => {
+                    error("wait", format!("{}: not a pid or valid job spec", arg));
+                    status = 2;
+                    continue;
+                }
+            }
+        };
+
+        let jid = match jid {
+            Ok(jid) => jid,
+            Err(message) => {
+                error("wait", format!("{}: {}", arg, message));
+                status = 127;
+                continue;
+            }
+        };
+
+        if let Some(sig) = jm.await_interruptible(ec, &[jid])? {
+            return Ok(128 + sig.number());
+        }
+        status = jm.r#await(jid)?.exit_code;
+        jm.forget_job(jid);
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::traps::{self, Signal};
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::ffi::OsString;
+
+    fn run(jm: &mut JobManager, ec: &mut ExecutionContext, script: &str) -> i32 {
+        jm.run(ec, Command::parse(script).unwrap())
+            .expect("failed to run script")
+            .exit_code
+    }
+
+    #[test]
+    fn job_status() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        assert_eq!(run(&mut jm, &mut ec, "sh -c 'exit 3' & wait $!"), 3);
+        assert_eq!(run(&mut jm, &mut ec, "sh -c 'exit 4' & wait %sh"), 4);
+        assert_eq!(
+            run(&mut jm, &mut ec, "sleep 5 & kill %+; wait %1"),
+            128 + 15
+        );
+        assert!(jm.background_jobs().is_empty());
+
+        assert_eq!(run(&mut jm, &mut ec, "false & true & wait"), 0);
+        assert!(jm.background_jobs().is_empty());
+
+        // trapped signals interrupt wait
+        let script =
+            "trap 'set -- trapped' USR1; sleep 5 & { sleep 0.1; kill -USR1 $$; } & wait %1";
+        assert_eq!(run(&mut jm, &mut ec, script), 128 + 10);
+        traps::release(Signal::SIGUSR1).expect("failed to release SIGUSR1");
+        assert_eq!(ec.variables().value(&OsString::from("1")), "trapped");
+        assert_eq!(run(&mut jm, &mut ec, "kill %1; wait"), 0);
+
+        assert_eq!(run(&mut jm, &mut ec, "wait %1 2>/dev/null"), 127);
+        assert_eq!(run(&mut jm, &mut ec, "wait notajob 2>/dev/null"), 2);
+    }
+}
@@ -0,0 +1,144 @@
+//! The shell's file descriptor table
+//!
+//! Redirects on `exec` without a command are applied to the shell process itself, so builtins, later commands
+//! and children all see them. `FdTable` keeps track of the file descriptors opened that way.
+//!
+//! Everything the shell opens for its own use is close-on-exec, and moved to `MIN_INTERNAL_FD` or above,
+//! out of the way of the file descriptors scripts usually redirect.
+//! Like other shells, redirecting file descriptors above 9 may conflict with the shell's own.
+use crate::jobs::spawn::{FdOp, OpenMode};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::unistd;
+use std::collections::{btree_map, BTreeMap};
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+
+/// The lowest file descriptor used for the shell's internal files
+pub const MIN_INTERNAL_FD: RawFd = 10;
+
+/// What a file descriptor the user opened refers to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OpenFd {
+    /// A file opened by a redirect, e.g. `exec 3>log.txt`
+    File(PathBuf, OpenMode),
+
+    /// A copy of a file descriptor the shell didn't open itself, usually one it inherited, e.g. `exec 3>&1`
+    Copy(RawFd),
+}
+
+/// File descriptors opened in the shell process with `exec`
+#[derive(Clone, Debug, Default)]
+pub struct FdTable {
+    fds: BTreeMap<RawFd, OpenFd>,
+}
+
+impl FdTable {
+    pub fn new() -> FdTable {
+        FdTable::default()
+    }
+
+    /// Record file descriptor operations that have been applied to the shell process, so they stay open.
+    ///
+    /// The close-on-exec flag is cleared from every file descriptor they opened, so children inherit them.
+    pub fn record(&mut self, ops: &[(RawFd, FdOp)]) {
+        for (fd, op) in ops {
+            let (fd, open) = match op {
+                FdOp::Close => {
+                    self.fds.remove(fd);
+                    continue;
+                }
+                FdOp::Open(path, mode) => (*fd, OpenFd::File(path.clone(), *mode)),
+                FdOp::Redirect(newfd) => {
+                    let open = self.fds.get(fd).cloned().unwrap_or(OpenFd::Copy(*fd));
+                    (*newfd, open)
+                }
+            };
+
+            let _ = fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()));
+            self.fds.insert(fd, open);
+        }
+    }
+
+    /// Get what `fd` refers to, if it was opened by the user
+    pub fn get(&self, fd: RawFd) -> Option<&OpenFd> {
+        self.fds.get(&fd)
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, RawFd, OpenFd> {
+        self.fds.iter()
+    }
+}
+
+/// Move `fd` to a close-on-exec file descriptor at or above `MIN_INTERNAL_FD`, for the shell's own use.
+///
+/// `fd` is closed, even if it couldn't be moved.
+pub fn internal(fd: RawFd) -> nix::Result<RawFd> {
+    let moved = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(MIN_INTERNAL_FD));
+    let _ = unistd::close(fd);
+    moved
+}
+
+/// Create a pipe for the shell's own use, both ends are moved like `internal`
+pub fn internal_pipe(flags: OFlag) -> nix::Result<(RawFd, RawFd)> {
+    let (read, write) = unistd::pipe2(flags | OFlag::O_CLOEXEC)?;
+    let read = match internal(read) {
+        Ok(read) => read,
+        Err(e) => {
+            let _ = unistd::close(write);
+            return Err(e);
+        }
+    };
+    match internal(write) {
+        Ok(write) => Ok((read, write)),
+        Err(e) => {
+            let _ = unistd::close(read);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::fds::{self, FdTable, OpenFd, MIN_INTERNAL_FD};
+    use crate::jobs::spawn::{FdOp, OpenMode};
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+    use nix::unistd;
+    use std::path::PathBuf;
+
+    #[test]
+    fn internal_pipe() {
+        let (read, write) = fds::internal_pipe(OFlag::empty()).expect("failed to create pipe");
+        for fd in &[read, write] {
+            assert!(*fd >= MIN_INTERNAL_FD);
+            let flags = fcntl(*fd, FcntlArg::F_GETFD).expect("failed to get fd flags");
+            assert!(FdFlag::from_bits_truncate(flags).contains(FdFlag::FD_CLOEXEC));
+        }
+
+        unistd::close(read).expect("failed to close pipe");
+        unistd::close(write).expect("failed to close pipe");
+    }
+
+    #[test]
+    fn record() {
+        // negative fds are never open, so the test process' own fds aren't touched
+        let mut table = FdTable::new();
+        let log = PathBuf::from("log.txt");
+        table.record(&[
+            (-1, FdOp::Open(log.clone(), OpenMode::Append)),
+            (-1, FdOp::Redirect(-2)),
+            (1, FdOp::Redirect(-3)),
+        ]);
+        assert_eq!(
+            table.get(-1),
+            Some(&OpenFd::File(log.clone(), OpenMode::Append))
+        );
+        assert_eq!(table.get(-2), Some(&OpenFd::File(log, OpenMode::Append)));
+        assert_eq!(table.get(-3), Some(&OpenFd::Copy(1)));
+
+        table.record(&[(-1, FdOp::Close), (-3, FdOp::Close)]);
+        assert_eq!(
+            table.iter().map(|(fd, _)| *fd).collect::<Vec<_>>(),
+            vec![-2]
+        );
+    }
+}
@@ -1,6 +1,10 @@
+pub mod fds;
 pub mod functions;
+pub mod options;
+pub mod signals;
 pub mod traps;
 pub mod variables;
 
 pub use self::functions::Functions;
+pub use self::options::Options;
 pub use self::variables::Variables;
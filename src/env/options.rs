@@ -0,0 +1,73 @@
+//! Shell options, toggled with `set -o NAME`/`set +o NAME` or their single letter flags
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Options {
+    /// `-u`: treat expansion of an unset variable as an error
+    pub nounset: bool,
+
+    /// `-n`: read commands but don't execute them
+    pub noexec: bool,
+}
+
+/// Every known option as `(flag, long name)`, in the order they are listed by `set -o`
+pub const OPTIONS: &[(char, &str)] = &[('u', "nounset"), ('n', "noexec")];
+
+impl Options {
+    pub fn new() -> Options {
+        Options::default()
+    }
+
+    /// Get the long name of the option associated with a single letter flag
+    pub fn name_of(flag: char) -> Option<&'static str> {
+        OPTIONS.iter().find(|(f, _)| *f == flag).map(|(_, n)| *n)
+    }
+
+    fn option_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "nounset" => Some(&mut self.nounset),
+            "noexec" => Some(&mut self.noexec),
+            _ => None,
+        }
+    }
+
+    /// Get an option's value by its long name
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "nounset" => Some(self.nounset),
+            "noexec" => Some(self.noexec),
+            _ => None,
+        }
+    }
+
+    /// Set an option by its long name, returns false if the option doesn't exist
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        match self.option_mut(name) {
+            Some(v) => {
+                *v = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set an option by its single letter flag, returns false if the flag doesn't exist
+    pub fn set_flag(&mut self, flag: char, value: bool) -> bool {
+        match Options::name_of(flag) {
+            Some(name) => self.set(name, value),
+            None => false,
+        }
+    }
+}
+
+/// Formats the flags of all enabled options, this is the value of `$-`
+impl fmt::Display for Options {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (flag, name) in OPTIONS {
+            if self.get(name).unwrap_or(false) {
+                write!(f, "{}", flag)?;
+            }
+        }
+        Ok(())
+    }
+}
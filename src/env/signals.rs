@@ -0,0 +1,243 @@
+//! Signal names and numbers
+//!
+//! `nix::sys::signal::Signal` only covers the standard signals, so signals are stored as plain numbers here.
+//! Standard signals get their names from nix, real-time signals are named relative to `SIGRTMIN` and `SIGRTMAX`,
+//! whose values depend on the C library.
+use nix;
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::signal::{self as nix_signal, SaFlags, SigHandler};
+use nix::unistd::Pid;
+use std::ffi::CStr;
+use std::fmt;
+use std::mem;
+use std::os::raw::c_int;
+use std::ptr;
+
+/// A signal, which may be a real-time signal
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Signal(c_int);
+
+impl Signal {
+    pub const SIGHUP: Signal = Signal(libc::SIGHUP);
+    pub const SIGINT: Signal = Signal(libc::SIGINT);
+    pub const SIGQUIT: Signal = Signal(libc::SIGQUIT);
+    pub const SIGKILL: Signal = Signal(libc::SIGKILL);
+    pub const SIGUSR1: Signal = Signal(libc::SIGUSR1);
+    pub const SIGUSR2: Signal = Signal(libc::SIGUSR2);
+    pub const SIGPIPE: Signal = Signal(libc::SIGPIPE);
+    pub const SIGTERM: Signal = Signal(libc::SIGTERM);
+    pub const SIGCHLD: Signal = Signal(libc::SIGCHLD);
+    pub const SIGCONT: Signal = Signal(libc::SIGCONT);
+    pub const SIGSTOP: Signal = Signal(libc::SIGSTOP);
+    pub const SIGTSTP: Signal = Signal(libc::SIGTSTP);
+    pub const SIGTTOU: Signal = Signal(libc::SIGTTOU);
+
+    /// Get the signal with number `n`, if there is one
+    pub fn from_number(n: c_int) -> Option<Signal> {
+        if nix_signal::Signal::from_c_int(n).is_ok() || realtime().contains(&n) {
+            Some(Signal(n))
+        } else {
+            None
+        }
+    }
+
+    pub fn number(self) -> c_int {
+        self.0
+    }
+
+    /// Get the signal's name, with the `SIG` prefix
+    pub fn name(self) -> String {
+        if let Ok(sig) = nix_signal::Signal::from_c_int(self.0) {
+            return format!("{:?}", sig);
+        }
+
+        // real-time signals are counted from whichever end of the range they're closest to
+        let range = realtime();
+        let (min, max) = (*range.start(), *range.end());
+        match self.0 {
+            n if n == min => "SIGRTMIN".to_string(),
+            n if n == max => "SIGRTMAX".to_string(),
+            n if n - min <= (max - min) / 2 => format!("SIGRTMIN+{}", n - min),
+            n => format!("SIGRTMAX-{}", max - n),
+        }
+    }
+
+    /// Get the C library's description of the signal, e.g. "Segmentation fault" for `SIGSEGV`
+    pub fn description(self) -> String {
+        extern "C" {
+            fn strsignal(sig: c_int) -> *const libc::c_char;
+        }
+
+        let description = unsafe { strsignal(self.0) };
+        if description.is_null() {
+            return self.name();
+        }
+        unsafe { CStr::from_ptr(description) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Parse a signal's name or number, the name isn't case sensitive and the `SIG` prefix is optional
+    pub fn parse<T: AsRef<str>>(s: T) -> Option<Signal> {
+        let s = s.as_ref().trim();
+        if let Ok(n) = s.parse::<c_int>() {
+            return Signal::from_number(n);
+        }
+
+        let upper = s.to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        let range = realtime();
+        let relative = |prefix: &str| -> Option<c_int> {
+            match name.strip_prefix(prefix)? {
+                "" => Some(0),
+                offset => offset.parse().ok(),
+            }
+        };
+
+        let number = if let Some(offset) = relative("RTMIN") {
+            range.start() + offset
+        } else if let Some(offset) = relative("RTMAX") {
+            range.end() + offset
+        } else {
+            return nix_signal::Signal::iterator()
+                .find(|sig| format!("{:?}", sig)[3..] == *name)
+                .map(Signal::from);
+        };
+
+        if range.contains(&number) {
+            Some(Signal(number))
+        } else {
+            None
+        }
+    }
+
+    /// Get the nix version of this signal, real-time signals don't have one
+    pub fn to_nix(self) -> Option<nix_signal::Signal> {
+        nix_signal::Signal::from_c_int(self.0).ok()
+    }
+
+    /// Set the action taken when the current process receives this signal, like `nix::sys::signal::sigaction` for any signal
+    ///
+    /// # Safety
+    ///
+    /// The same rules as `sigaction` apply, `handler` must only call async-signal-safe functions.
+    pub unsafe fn set_handler(self, handler: SigHandler, flags: SaFlags) -> nix::Result<()> {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = match handler {
+            SigHandler::SigDfl => libc::SIG_DFL,
+            SigHandler::SigIgn => libc::SIG_IGN,
+            SigHandler::Handler(f) => f as libc::sighandler_t,
+            SigHandler::SigAction(f) => f as libc::sighandler_t,
+        };
+        action.sa_flags = match handler {
+            SigHandler::SigAction(_) => (flags | SaFlags::SA_SIGINFO).bits(),
+            _ => (flags - SaFlags::SA_SIGINFO).bits(),
+        };
+        libc::sigemptyset(&mut action.sa_mask);
+
+        Errno::result(libc::sigaction(self.0, &action, ptr::null_mut())).map(drop)
+    }
+
+    /// Check if the current process ignores this signal
+    pub fn is_ignored(self) -> bool {
+        let mut current: libc::sigaction = unsafe { mem::zeroed() };
+        let result = unsafe { libc::sigaction(self.0, ptr::null(), &mut current) };
+        result == 0 && current.sa_sigaction == libc::SIG_IGN
+    }
+
+    /// Send this signal to a process, or a process group if `pid` is negative
+    pub fn send(self, pid: Pid) -> nix::Result<()> {
+        Errno::result(unsafe { libc::kill(pid.into(), self.0) }).map(drop)
+    }
+
+    /// Send this signal to the current process
+    pub fn raise(self) -> nix::Result<()> {
+        Errno::result(unsafe { libc::raise(self.0) }).map(drop)
+    }
+}
+
+impl From<nix_signal::Signal> for Signal {
+    fn from(sig: nix_signal::Signal) -> Signal {
+        Signal(sig as c_int)
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Get every signal, in order of signal number
+pub fn all() -> Vec<Signal> {
+    let mut signals: Vec<Signal> = nix_signal::Signal::iterator()
+        .map(Signal::from)
+        .chain(realtime().map(Signal))
+        .collect();
+    signals.sort();
+    signals
+}
+
+/// Get the highest signal number
+pub fn max() -> c_int {
+    all().last().map_or(0, |sig| sig.number())
+}
+
+/// The range of real-time signal numbers, it's empty if they aren't supported
+#[cfg(target_os = "linux")]
+fn realtime() -> std::ops::RangeInclusive<c_int> {
+    extern "C" {
+        fn __libc_current_sigrtmin() -> c_int;
+        fn __libc_current_sigrtmax() -> c_int;
+    }
+    unsafe { __libc_current_sigrtmin()..=__libc_current_sigrtmax() }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn realtime() -> std::ops::RangeInclusive<c_int> {
+    #[allow(clippy::reversed_empty_ranges)]
+    {
+        1..=0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::signals::{self, Signal};
+
+    #[test]
+    fn names() {
+        assert_eq!(Signal::parse("TERM"), Some(Signal::SIGTERM));
+        assert_eq!(Signal::parse("sigint"), Some(Signal::SIGINT));
+        assert_eq!(Signal::parse("9"), Some(Signal::SIGKILL));
+        assert_eq!(Signal::parse("NOTASIGNAL"), None);
+        assert_eq!(Signal::parse("0"), None);
+        assert_eq!(Signal::SIGTERM.name(), "SIGTERM");
+        assert_eq!(Signal::SIGKILL.description(), "Killed");
+
+        for sig in signals::all() {
+            assert_eq!(Signal::parse(sig.name()), Some(sig));
+            assert_eq!(Signal::from_number(sig.number()), Some(sig));
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn realtime() {
+        let min = Signal::parse("SIGRTMIN").expect("SIGRTMIN should parse");
+        let max = Signal::parse("RTMAX").expect("SIGRTMAX should parse");
+        assert!(min < max);
+        assert!(min.to_nix().is_none());
+
+        let third = Signal::parse("SIGRTMIN+3").expect("SIGRTMIN+3 should parse");
+        assert_eq!(third.number(), min.number() + 3);
+        assert_eq!(third.name(), "SIGRTMIN+3");
+        assert_eq!(Signal::parse("RTMAX-2").unwrap().number(), max.number() - 2);
+        assert_eq!(Signal::parse("SIGRTMAX+1"), None);
+        assert_eq!(
+            Signal::parse(max.number().to_string()).map(Signal::name),
+            Some("SIGRTMAX".to_string())
+        );
+    }
+}
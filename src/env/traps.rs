@@ -1,17 +1,40 @@
+//! Signal traps
+//!
+//! Traps can be set on signals, or on the shell's own events like `EXIT` and `ERR`.
+//! The signal handler only writes the signal's number to a self-pipe, since almost nothing is safe to call from a handler.
+//! Actions run later, when the pipe is drained by `take_pending`.
+pub use crate::env::signals::Signal;
+use crate::env::{fds, signals};
 use nix;
-use nix::sys::signal;
-pub use nix::sys::signal::Signal;
+use nix::errno::{self, Errno};
+use nix::fcntl::OFlag;
+use nix::libc;
+use nix::sys::signal::{SaFlags, SigHandler};
+use nix::unistd;
 use std::collections::HashMap;
+use std::fmt;
 use std::os::raw::c_int;
+use std::os::unix::io::RawFd;
 use std::slice;
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, RwLock};
 
 lazy_static! {
     static ref GLOBAL_TRAPS: RwLock<Traps> = { RwLock::new(Traps::with_capacity(31)) };
+
+    /// Signals that were ignored when the shell started, they stay ignored in the shell and its children
+    static ref IGNORED_ON_ENTRY: Vec<Signal> = signals::all().into_iter().filter(|sig| sig.is_ignored()).collect();
 }
 
-pub type LineFn = Box<dyn FnMut() + Send + Sync + 'static>;
-pub type Traps = HashMap<Signal, Vec<Action>>;
+/// Set once the shell ignores `INTERACTIVE_SIGNALS` for itself
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The read and write ends of the self-pipe, -1 until the first signal is trapped
+static PIPE_READ: AtomicI32 = AtomicI32::new(-1);
+static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+pub type LineFn = Arc<dyn Fn() + Send + Sync + 'static>;
+pub type Traps = HashMap<Condition, Vec<Action>>;
 pub type TrapIter<'a> = slice::Iter<'a, Action>;
 
 pub enum Action {
@@ -20,29 +43,232 @@ pub enum Action {
     Func(LineFn),
 }
 
-pub fn trap(sig: Signal, a: Action) -> nix::Result<()> {
+/// Something a trap can be set on, either a signal or an event in the shell
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Condition {
+    /// The shell is exiting
+    Exit,
+    Signal(Signal),
+    /// A command finished with a non-zero status
+    Err,
+    /// A simple command is about to run
+    Debug,
+    /// A function finished running
+    Return,
+}
+
+/// Signals that kill the shell by default.
+///
+/// They're caught while an `EXIT` trap is set, so the trap can run before the shell exits.
+pub const FATAL_SIGNALS: &[Signal] = &[Signal::SIGHUP, Signal::SIGINT, Signal::SIGTERM];
+
+/// Signals an interactive shell ignores, so they only affect the command running in the foreground.
+///
+/// The shell's children get the default action for these, unless they were ignored when the shell started.
+pub const INTERACTIVE_SIGNALS: &[Signal] = &[
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTSTP,
+    Signal::SIGTTOU,
+];
+
+impl Condition {
+    /// `EXIT` is listed first, then signals by number, then the other events
+    fn sort_key(&self) -> c_int {
+        match self {
+            Condition::Exit => 0,
+            Condition::Signal(sig) => sig.number(),
+            Condition::Debug => 1000,
+            Condition::Err => 1001,
+            Condition::Return => 1002,
+        }
+    }
+}
+
+impl From<Signal> for Condition {
+    fn from(sig: Signal) -> Condition {
+        Condition::Signal(sig)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Exit => write!(f, "EXIT"),
+            Condition::Signal(sig) => write!(f, "{}", sig),
+            Condition::Err => write!(f, "ERR"),
+            Condition::Debug => write!(f, "DEBUG"),
+            Condition::Return => write!(f, "RETURN"),
+        }
+    }
+}
+
+pub fn trap<T: Into<Condition>>(condition: T, a: Action) -> nix::Result<()> {
+    let condition = condition.into();
     let mut mut_traps = GLOBAL_TRAPS.write().unwrap();
-    match mut_traps.get_mut(&sig) {
-        Some(v) => {
-            v.push(a);
-            return Ok(());
+    match condition {
+        // a non-interactive shell can't trap signals that were ignored when it started
+        Condition::Signal(sig)
+            if !INTERACTIVE.load(Ordering::SeqCst) && IGNORED_ON_ENTRY.contains(&sig) =>
+        {
+            return Ok(())
         }
-        None => unsafe {
-            signal::sigaction(
-                sig,
-                &signal::SigAction::new(
-                    signal::SigHandler::Handler(__rush_global_signal_handler),
-                    signal::SaFlags::empty(),
-                    signal::SigSet::empty(),
-                ),
-            )
+        // a trap that only ignores the signal really ignores it, so it stays ignored in children too
+        Condition::Signal(sig) => {
+            let ignored = mut_traps
+                .get(&condition)
+                .is_none_or(|actions| actions.iter().all(Action::is_noop));
+            if ignored && a.is_noop() {
+                ignore(sig)?;
+            } else {
+                catch(sig)?;
+            }
         }
-        .map(|_| ())?,
-    };
-    mut_traps.insert(sig, vec![a]);
+        Condition::Exit if !mut_traps.contains_key(&condition) => {
+            for sig in FATAL_SIGNALS {
+                if exit_catches(&mut_traps, *sig) {
+                    catch(*sig)?;
+                }
+            }
+        }
+        _ => (),
+    }
+    mut_traps.entry(condition).or_default().push(a);
     Ok(())
 }
 
+impl Action {
+    fn is_noop(&self) -> bool {
+        matches!(self, Action::NoOp)
+    }
+}
+
+/// Whether `sig` has to be caught so the `EXIT` trap can run before it kills the shell
+fn exit_catches(traps: &Traps, sig: Signal) -> bool {
+    is_fatal(sig)
+        && !traps.contains_key(&Condition::Signal(sig))
+        && base_handler(sig) == SigHandler::SigDfl
+}
+
+/// Install the global signal handler for `sig`
+fn catch(sig: Signal) -> nix::Result<()> {
+    open_pipe()?;
+    unsafe {
+        sig.set_handler(
+            SigHandler::Handler(__rush_global_signal_handler),
+            SaFlags::SA_RESTART,
+        )
+    }
+}
+
+/// Ignore `sig` in the shell
+fn ignore(sig: Signal) -> nix::Result<()> {
+    set_handler(sig, SigHandler::SigIgn)
+}
+
+/// Restore the shell's normal action for `sig`, see `base_handler`
+fn reset(sig: Signal) -> nix::Result<()> {
+    set_handler(sig, base_handler(sig))
+}
+
+fn set_handler(sig: Signal, handler: SigHandler) -> nix::Result<()> {
+    unsafe { sig.set_handler(handler, SaFlags::empty()) }
+}
+
+/// The action for `sig` when it isn't trapped.
+///
+/// Signals ignored when the shell started stay ignored, and an interactive shell ignores `INTERACTIVE_SIGNALS`.
+fn base_handler(sig: Signal) -> SigHandler {
+    if IGNORED_ON_ENTRY.contains(&sig)
+        || (INTERACTIVE.load(Ordering::SeqCst) && INTERACTIVE_SIGNALS.contains(&sig))
+    {
+        SigHandler::SigIgn
+    } else {
+        SigHandler::SigDfl
+    }
+}
+
+/// Ignore `INTERACTIVE_SIGNALS` in the shell, unless they're trapped
+pub fn ignore_interactive() -> nix::Result<()> {
+    let traps = GLOBAL_TRAPS.write().unwrap();
+    lazy_static::initialize(&IGNORED_ON_ENTRY);
+    INTERACTIVE.store(true, Ordering::SeqCst);
+
+    for sig in INTERACTIVE_SIGNALS {
+        if !traps.contains_key(&Condition::Signal(*sig)) && !exit_catches(&traps, *sig) {
+            ignore(*sig)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the shell is interactive, see `ignore_interactive`. Subshells aren't
+pub fn is_interactive() -> bool {
+    INTERACTIVE.load(Ordering::SeqCst)
+}
+
+/// Put back the shell's actions for `signals` after they were given their defaults, by an `exec` that failed.
+///
+/// `signals` should come from `child_defaults`, so each of them is either caught or ignored because the shell is interactive.
+pub fn restore(signals: &[Signal]) -> nix::Result<()> {
+    let traps = GLOBAL_TRAPS.read().unwrap();
+    for sig in signals {
+        let caught = traps.contains_key(&Condition::Signal(*sig))
+            || (traps.contains_key(&Condition::Exit) && exit_catches(&traps, *sig));
+        if caught {
+            catch(*sig)?;
+        } else {
+            reset(*sig)?;
+        }
+    }
+    Ok(())
+}
+
+/// Signals that need their default action restored in a child process before it runs a command.
+///
+/// Those are the signals the shell catches, and the ones it only ignores because it's interactive.
+/// Signals ignored by `trap ''`, or ignored when the shell started, stay ignored.
+pub fn child_defaults() -> Vec<Signal> {
+    let traps = GLOBAL_TRAPS.read().unwrap();
+    let interactive = INTERACTIVE.load(Ordering::SeqCst);
+
+    signals::all()
+        .into_iter()
+        .filter(|sig| {
+            let condition = Condition::Signal(*sig);
+            match traps.get(&condition) {
+                Some(actions) => !actions.iter().all(Action::is_noop),
+                None => {
+                    (traps.contains_key(&Condition::Exit) && exit_catches(&traps, *sig))
+                        || (interactive
+                            && INTERACTIVE_SIGNALS.contains(sig)
+                            && !IGNORED_ON_ENTRY.contains(sig))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Forget the shell's traps in a subshell, a forked copy of the shell.
+///
+/// Signals ignored by a trap stay ignored, everything else is removed.
+/// The caller is responsible for resetting the subshell's signal actions, see `child_defaults`.
+pub fn reset_subshell() {
+    let mut traps = GLOBAL_TRAPS.write().unwrap();
+    traps.retain(|condition, actions| {
+        matches!(condition, Condition::Signal(_)) && actions.iter().all(Action::is_noop)
+    });
+    INTERACTIVE.store(false, Ordering::SeqCst);
+
+    // the self-pipe is shared with the parent shell, signals sent to the subshell mustn't end up there
+    for pipe in &[&PIPE_WRITE, &PIPE_READ] {
+        let fd = pipe.swap(-1, Ordering::SeqCst);
+        if fd >= 0 {
+            let _ = unistd::close(fd);
+        }
+    }
+}
+
 pub fn trap_s<T: AsRef<str>>(sig: T, a: Action) -> nix::Result<()> {
     trap(
         match parse_signal(sig) {
@@ -53,85 +279,262 @@ pub fn trap_s<T: AsRef<str>>(sig: T, a: Action) -> nix::Result<()> {
     )
 }
 
-pub fn release(sig: Signal) -> nix::Result<()> {
+/// Create the self-pipe, if it hasn't been created yet.
+///
+/// Both ends are non-blocking: the handler can't wait for the pipe to be drained, and it's drained until it's empty.
+/// This must be called while holding `GLOBAL_TRAPS`'s write lock, so the pipe is only created once.
+fn open_pipe() -> nix::Result<()> {
+    if PIPE_READ.load(Ordering::SeqCst) < 0 {
+        let (read, write) = fds::internal_pipe(OFlag::O_NONBLOCK)?;
+        PIPE_WRITE.store(write, Ordering::SeqCst);
+        PIPE_READ.store(read, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+/// Catch `SIGCHLD` while `f` runs, so the self-pipe also becomes readable when a child process exits.
+///
+/// This lets the shell wait for a child and for trapped signals at the same time, by polling `fd`.
+pub fn with_child_wakeups<T, F: FnOnce() -> T>(f: F) -> T {
+    let sig = Signal::SIGCHLD;
+    let (caught, ignored) = {
+        let traps = GLOBAL_TRAPS.write().unwrap();
+        match traps.get(&Condition::Signal(sig)) {
+            Some(actions) if !actions.iter().all(Action::is_noop) => (false, false),
+            actions => (catch(sig).is_ok(), actions.is_some()),
+        }
+    };
+
+    let result = f();
+    if caught {
+        let _ = if ignored { ignore(sig) } else { reset(sig) };
+    }
+    result
+}
+
+/// Get the file descriptor that becomes readable when a trapped signal is received
+pub fn fd() -> Option<RawFd> {
+    let fd = PIPE_READ.load(Ordering::SeqCst);
+    if fd < 0 {
+        None
+    } else {
+        Some(fd)
+    }
+}
+
+/// Replace every action for `condition` with `a`
+pub fn set<T: Into<Condition>>(condition: T, a: Action) -> nix::Result<()> {
+    let condition = condition.into();
+    GLOBAL_TRAPS.write().unwrap().remove(&condition);
+    trap(condition, a)
+}
+
+pub fn release<T: Into<Condition>>(condition: T) -> nix::Result<()> {
+    let condition = condition.into();
     let mut mut_traps = GLOBAL_TRAPS.write().unwrap();
-    mut_traps.remove(&sig);
-    unsafe {
-        signal::sigaction(
-            sig,
-            &signal::SigAction::new(
-                signal::SigHandler::SigDfl,
-                signal::SaFlags::empty(),
-                signal::SigSet::empty(),
-            ),
-        )
+    mut_traps.remove(&condition);
+    let exit_trapped = mut_traps.contains_key(&Condition::Exit);
+
+    match condition {
+        // fatal signals stay caught while there's an `EXIT` trap
+        Condition::Signal(sig) if exit_trapped && exit_catches(&mut_traps, sig) => catch(sig),
+        Condition::Signal(sig) => reset(sig),
+        Condition::Exit => {
+            for sig in FATAL_SIGNALS {
+                if exit_catches(&mut_traps, *sig) {
+                    reset(*sig)?;
+                }
+            }
+            Ok(())
+        }
+        _ => Ok(()),
     }
-    .map(|_| ())
 }
 
-pub fn is_trapped(sig: Signal) -> bool {
-    return GLOBAL_TRAPS.read().unwrap().contains_key(&sig);
+pub fn is_trapped<T: Into<Condition>>(condition: T) -> bool {
+    return GLOBAL_TRAPS.read().unwrap().contains_key(&condition.into());
 }
 
-extern "C" fn __rush_global_signal_handler(sig: c_int) {
-    let mut traps = GLOBAL_TRAPS.write().unwrap();
-    match traps.get_mut(&(Signal::from_c_int(sig).unwrap())) {
-        Some(actions) => {
-            for action in actions {
-                match action {
-                    Action::Eval(ref s) => {
-                        println!("\n==> Signal handler for \"{}\"", s);
-                        unimplemented!();
+/// Whether `sig` kills the shell when it isn't trapped
+pub fn is_fatal(sig: Signal) -> bool {
+    FATAL_SIGNALS.contains(&sig)
+}
+
+/// Drain the self-pipe and get the signals received since the last call, in order of signal number.
+///
+/// `Func` actions are run here, `Eval` actions are left to the caller.
+pub fn take_pending() -> Vec<Signal> {
+    let fd = match fd() {
+        Some(fd) => fd,
+        None => return Vec::new(),
+    };
+
+    let mut received = vec![false; signals::max() as usize + 1];
+    let mut buffer = [0u8; 64];
+    loop {
+        match unistd::read(fd, &mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                for sig in &buffer[..n] {
+                    if let Some(slot) = received.get_mut(*sig as usize) {
+                        *slot = true;
                     }
-                    Action::Func(ref mut f) => f(),
-                    Action::NoOp => (),
                 }
             }
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(_) => break,
         }
-        None => (),
     }
+
+    let signals: Vec<Signal> = (1..received.len())
+        .filter(|n| received[*n])
+        .filter_map(|n| Signal::from_number(n as c_int))
+        .collect();
+
+    // the lock is released before the functions run, so they can change traps themselves
+    let funcs: Vec<LineFn> = {
+        let traps = GLOBAL_TRAPS.read().unwrap();
+        signals
+            .iter()
+            .filter_map(|sig| traps.get(&Condition::Signal(*sig)))
+            .flatten()
+            .filter_map(|action| match action {
+                Action::Func(f) => Some(f.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    for f in funcs {
+        f();
+    }
+
+    signals
 }
 
+/// Get the commands to evaluate when `condition` happens
+pub fn commands<T: Into<Condition>>(condition: T) -> Vec<String> {
+    GLOBAL_TRAPS
+        .read()
+        .unwrap()
+        .get(&condition.into())
+        .map(|actions| {
+            actions
+                .iter()
+                .filter_map(|action| match action {
+                    Action::Eval(s) => Some(s.clone()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// List every trap with the command it evaluates, ignored signals have an empty command
+pub fn list() -> Vec<(Condition, String)> {
+    let traps = GLOBAL_TRAPS.read().unwrap();
+    let mut list: Vec<(Condition, String)> = traps
+        .iter()
+        .filter_map(|(sig, actions)| {
+            actions.iter().rev().find_map(|action| match action {
+                Action::Eval(s) => Some((*sig, s.clone())),
+                Action::NoOp => Some((*sig, String::new())),
+                Action::Func(_) => None,
+            })
+        })
+        .collect();
+    list.sort_by_key(|(condition, _)| condition.sort_key());
+    list
+}
+
+extern "C" fn __rush_global_signal_handler(sig: c_int) {
+    // only async-signal-safe functions can be called here, so no locks, allocation or printing
+    let fd = PIPE_WRITE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let saved_errno = errno::errno();
+        // if the pipe is full there are already signals waiting to be handled, so it's fine to drop this one.
+        // signals are sent as a single byte, one that doesn't fit can't be told apart from others so it's dropped too
+        if let Ok(sig) = u8::try_from(sig) {
+            let _ = unistd::write(fd, &[sig]);
+        }
+        unsafe { *errno_location() = saved_errno };
+    }
+}
+
+/// Where the calling thread's errno is stored, so the signal handler can put it back. nix can read errno but not set it
+#[cfg(target_os = "linux")]
+unsafe fn errno_location() -> *mut c_int {
+    libc::__errno_location()
+}
+
+#[cfg(any(target_os = "freebsd", target_os = "ios", target_os = "macos"))]
+unsafe fn errno_location() -> *mut c_int {
+    libc::__error()
+}
+
+#[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+unsafe fn errno_location() -> *mut c_int {
+    libc::__errno()
+}
+
+/// Parse a signal, or the name of an event: `EXIT` (or `0`), `ERR`, `DEBUG` and `RETURN`
+pub fn parse_condition<T: AsRef<str>>(s: T) -> Option<Condition> {
+    Some(match s.as_ref().to_ascii_uppercase().trim() {
+        "0" | "EXIT" => Condition::Exit,
+        "ERR" => Condition::Err,
+        "DEBUG" => Condition::Debug,
+        "RETURN" => Condition::Return,
+        _ => return parse_signal(s).map(Condition::Signal),
+    })
+}
+
+/// Parse a signal's name or number, see `Signal::parse`
 pub fn parse_signal<T: AsRef<str>>(s: T) -> Option<Signal> {
-    Some(
-        match s
-            .as_ref()
-            .to_ascii_uppercase()
-            .trim()
-            .trim_left_matches("SIG")
-        {
-            "1" | "HUP" => Signal::SIGHUP,
-            "2" | "INT" => Signal::SIGINT,
-            "3" | "QUIT" => Signal::SIGQUIT,
-            "4" | "ILL" => Signal::SIGILL,
-            "5" | "TRAP" => Signal::SIGTRAP,
-            "6" | "ABRT" => Signal::SIGABRT,
-            "7" | "BUS" => Signal::SIGBUS,
-            "8" | "FPE" => Signal::SIGFPE,
-            "9" | "KILL" => Signal::SIGKILL,
-            "10" | "USR1" => Signal::SIGUSR1,
-            "11" | "SEGV" => Signal::SIGSEGV,
-            "12" | "USR2" => Signal::SIGUSR2,
-            "13" | "PIPE" => Signal::SIGPIPE,
-            "14" | "ALRM" => Signal::SIGALRM,
-            "15" | "TERM" => Signal::SIGTERM,
-            "16" | "STKFLT" => Signal::SIGSTKFLT,
-            "17" | "CHLD" => Signal::SIGCHLD,
-            "18" | "CONT" => Signal::SIGCONT,
-            "19" | "STOP" => Signal::SIGSTOP,
-            "20" | "TSTP" => Signal::SIGTSTP,
-            "21" | "TTIN" => Signal::SIGTTIN,
-            "22" | "TTOU" => Signal::SIGTTOU,
-            "23" | "URG" => Signal::SIGURG,
-            "24" | "XCPU" => Signal::SIGXCPU,
-            "25" | "XFSZ" => Signal::SIGXFSZ,
-            "26" | "VTALRM" => Signal::SIGVTALRM,
-            "27" | "PROF" => Signal::SIGPROF,
-            "28" | "WINCH" => Signal::SIGWINCH,
-            "29" | "IO" => Signal::SIGIO,
-            "30" | "PWR" => Signal::SIGPWR,
-            "31" | "SYS" => Signal::SIGSYS,
-            _ => return None,
-        },
-    )
+    Signal::parse(s)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::env::traps::{self, Action, Signal};
+    use crate::test_util::forks;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn deferred_functions() {
+        forks!();
+
+        let called = Arc::new(AtomicBool::new(false));
+        let flag = called.clone();
+        traps::set(
+            Signal::SIGUSR2,
+            Action::Func(Arc::new(move || flag.store(true, Ordering::SeqCst))),
+        )
+        .expect("failed to trap SIGUSR2");
+
+        Signal::SIGUSR2.raise().expect("failed to raise SIGUSR2");
+        assert!(!called.load(Ordering::SeqCst));
+
+        assert!(traps::take_pending().contains(&Signal::SIGUSR2));
+        assert!(called.load(Ordering::SeqCst));
+
+        traps::release(Signal::SIGUSR2).expect("failed to release SIGUSR2");
+    }
+
+    #[test]
+    fn functions_change_traps() {
+        forks!();
+
+        // the traps aren't locked while a function runs, so it can release its own trap
+        traps::set(
+            Signal::SIGUSR2,
+            Action::Func(Arc::new(|| {
+                traps::release(Signal::SIGUSR2).expect("failed to release SIGUSR2")
+            })),
+        )
+        .expect("failed to trap SIGUSR2");
+
+        Signal::SIGUSR2.raise().expect("failed to raise SIGUSR2");
+        assert!(traps::take_pending().contains(&Signal::SIGUSR2));
+        assert!(!traps::is_trapped(Signal::SIGUSR2));
+    }
 }
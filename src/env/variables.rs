@@ -1,20 +1,141 @@
-///! Variables is a wrapper around a `BTreeMap<OsString, OsString>`.
+///! Variables is a wrapper around a `BTreeMap<OsString, Value>`.
 ///! It provides specialized methods for working with shell variables.
+use std::cell::Cell;
 use std::collections::btree_map;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
 use std::ffi::OsString;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::env::options::Options;
+use crate::expr;
 
 pub type Name = OsString;
-pub type Value = OsString;
+
+/// The value of a variable, a string or an array of strings
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Value {
+    Scalar(OsString),
+
+    /// An indexed array, there can be gaps between its elements
+    Indexed(BTreeMap<usize, OsString>),
+
+    /// An associative array, created with `declare -A`
+    Associative(BTreeMap<String, OsString>),
+}
 
 pub type Iter<'a> = btree_map::Iter<'a, Name, Value>;
 pub type IterMut<'a> = btree_map::IterMut<'a, Name, Value>;
 pub type IntoIter = btree_map::IntoIter<Name, Value>;
 
+/// How many name references are followed before giving up, so a reference loop can't hang the shell
+const MAX_NAMEREF_DEPTH: usize = 8;
+
+/// Variables computed each time they're read, they become ordinary variables once they're unset.
+///
+/// Assigning `RANDOM` seeds it and assigning `SECONDS` sets the number it counts from, the others ignore assignments.
+const DYNAMIC: &[&str] = &[
+    "RANDOM",
+    "SECONDS",
+    "EPOCHSECONDS",
+    "EPOCHREALTIME",
+    "PPID",
+    "RUSH_PID",
+];
+
+/// Attributes of a variable, set with `declare`.
+///
+/// A variable can have attributes without being set, they're used when it's assigned.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Attributes {
+    /// The variable can't be assigned or unset
+    pub readonly: bool,
+
+    /// The variable is in the environment of commands the shell runs
+    pub exported: bool,
+
+    /// Assigned values are evaluated as arithmetic expressions
+    pub integer: bool,
+
+    /// Assigned values are converted to lowercase
+    pub lowercase: bool,
+
+    /// Assigned values are converted to uppercase
+    pub uppercase: bool,
+
+    /// The value is the name of another variable, which is used in place of this one
+    pub nameref: bool,
+}
+
+/// Why a variable couldn't be changed, or an element of an array couldn't be found
+#[derive(Debug, Clone, Eq, PartialEq, Fail)]
+pub enum AssignError {
+    #[fail(display = "{}: readonly variable", _0)]
+    Readonly(String),
+
+    /// The value assigned to an integer variable isn't a valid arithmetic expression
+    #[fail(display = "{}: invalid arithmetic expression", _0)]
+    BadInteger(String),
+
+    /// An array index that isn't a valid arithmetic expression, or is before the start of the array
+    #[fail(display = "{}: bad array subscript", _0)]
+    BadSubscript(String),
+
+    /// An element of an associative array assigned without a key
+    #[fail(
+        display = "{}: must use subscript when assigning associative array",
+        _0
+    )]
+    MissingKey(String),
+
+    #[fail(
+        display = "{}: cannot convert between indexed and associative arrays",
+        _0
+    )]
+    CannotConvert(String),
+}
+
+/// An evaluated array subscript
+enum Subscript {
+    Index(i64),
+    Key(String),
+}
+
 #[derive(Debug, Clone)]
 pub struct Variables {
     map: BTreeMap<Name, Value>,
+
+    /// Attributes of variables that have any, set or not
+    attributes: BTreeMap<Name, Attributes>,
+
+    /// The value of `$0`
+    arg0: OsString,
+
+    /// Positional parameters, `$1`, `$2`, ...
+    positional: Vec<OsString>,
+
+    /// Exit code of the last command, the value of `$?`
+    last_status: i32,
+
+    /// Process id of the shell, the value of `$$`
+    shell_pid: i32,
+
+    /// Process id of the last command run in the background, the value of `$!`
+    last_background: Option<i32>,
+
+    /// Process id of the shell's parent, the value of `$PPID`
+    parent_pid: i32,
+
+    /// The names in `DYNAMIC` that haven't been unset
+    dynamic: BTreeSet<Name>,
+
+    /// State of the generator behind `$RANDOM`, it changes every time it's read
+    random: Cell<u32>,
+
+    /// When `$SECONDS` started counting, and the number it started from
+    seconds: (Instant, i64),
+
+    options: Options,
 }
 
 pub enum Entry<'a> {
@@ -22,6 +143,14 @@ pub enum Entry<'a> {
     Occupied(OccupiedEntry<'a>),
 }
 
+/// A copy of a variable, see `Variables::save`
+#[derive(Debug, Clone)]
+pub struct Saved {
+    name: Name,
+    value: Option<Value>,
+    attributes: Attributes,
+}
+
 #[derive(Debug)]
 pub struct OccupiedEntry<'a> {
     entry: btree_map::OccupiedEntry<'a, Name, Value>,
@@ -36,40 +165,670 @@ impl Variables {
     pub fn new() -> Variables {
         Variables {
             map: BTreeMap::new(),
+            attributes: BTreeMap::new(),
+            arg0: OsString::from("rush"),
+            positional: Vec::new(),
+            last_status: 0,
+            shell_pid: nix::unistd::getpid().into(),
+            last_background: None,
+            parent_pid: nix::unistd::getppid().into(),
+            dynamic: DYNAMIC.iter().map(OsString::from).collect(),
+            random: Cell::new(random_seed()),
+            seconds: (Instant::now(), 0),
+            options: Options::new(),
         }
     }
 
+    /// Create variables from the environment, they're all exported
     pub fn from_env() -> Variables {
-        Variables {
-            map: env::vars_os().collect(),
-        }
+        let mut vars = Variables::new();
+        vars.import_env();
+        vars
     }
 
     pub fn import_env(&mut self) {
-        self.map.append(&mut env::vars_os().collect());
+        for (name, value) in env::vars_os() {
+            self.attributes.entry(name.clone()).or_default().exported = true;
+            self.map.insert(name, Value::Scalar(value));
+        }
     }
 
+    /// Set a variable without looking at its attributes, for variables the shell maintains itself
     pub fn define<T: Into<OsString>, U: Into<OsString>>(&mut self, k: T, v: U) {
-        self.map.insert(k.into(), v.into());
+        self.map.insert(k.into(), Value::Scalar(v.into()));
+    }
+
+    /// Set an indexed array without looking at its attributes, like `define`
+    pub fn define_array<T: Into<OsString>, I: IntoIterator<Item = T>>(&mut self, k: T, values: I) {
+        let elements = values.into_iter().map(Into::into).enumerate().collect();
+        self.map.insert(k.into(), Value::Indexed(elements));
+    }
+
+    /// Assign a variable the way the shell language does.
+    ///
+    /// Name references are followed, then the value is transformed by the variable's attributes,
+    /// fails if the variable is readonly or an integer variable is given an invalid expression.
+    pub fn set<T: Into<OsString>, U: Into<OsString>>(
+        &mut self,
+        k: T,
+        v: U,
+    ) -> Result<(), AssignError> {
+        let name = self.resolve(&k.into());
+        self.assign(name, v.into())
+    }
+
+    /// Append to a variable with `+=`, integer variables are added to instead
+    pub fn append<T: Into<OsString>, U: Into<OsString>>(
+        &mut self,
+        k: T,
+        v: U,
+    ) -> Result<(), AssignError> {
+        let name = self.resolve(&k.into());
+        let old = self.value(&name);
+        let value = self.appended(&name, old, v.into())?;
+        self.assign(name, value)
+    }
+
+    /// Assign an element of an array, converting a scalar into an array whose first element is the old value.
+    ///
+    /// `subscript` is evaluated as an arithmetic expression, unless the variable is an associative array.
+    pub fn set_element<T: Into<OsString>, U: Into<OsString>>(
+        &mut self,
+        k: T,
+        subscript: &str,
+        v: U,
+        append: bool,
+    ) -> Result<(), AssignError> {
+        let name = self.resolve(&k.into());
+        self.check_writable(&name)?;
+
+        let subscript = self.subscript(&name, subscript)?;
+        let mut value = v.into();
+        if append {
+            let old = self.element_at(&name, &subscript).unwrap_or_default();
+            value = self.appended(&name, old, value)?;
+        }
+        let value = self.transform(&name, value)?;
+        self.insert_element(&name, subscript, value)
+    }
+
+    /// Assign a whole array with `name=(...)`, or append to it with `name+=(...)`.
+    ///
+    /// Each element has an optional subscript, `[subscript]=value`, other elements follow the previous one.
+    /// A scalar variable becomes an indexed array, unless it's been declared associative.
+    pub fn set_array<T: Into<OsString>>(
+        &mut self,
+        k: T,
+        elements: Vec<(Option<String>, OsString)>,
+        append: bool,
+    ) -> Result<(), AssignError> {
+        let name = self.resolve(&k.into());
+        self.check_writable(&name)?;
+
+        let old = match self.map.remove(&name) {
+            Some(old) if append => Some(old),
+            Some(Value::Associative(_)) => Some(Value::Associative(BTreeMap::new())),
+            Some(Value::Indexed(_)) | Some(Value::Scalar(_)) | None => None,
+        };
+        let mut next = match &old {
+            Some(Value::Indexed(elements)) => elements.keys().next_back().map_or(0, |i| i + 1),
+            Some(Value::Scalar(_)) => 1,
+            _ => 0,
+        };
+        self.map.insert(
+            name.clone(),
+            match old {
+                Some(Value::Scalar(value)) => Value::Indexed(BTreeMap::from([(0, value)])),
+                Some(value) => value,
+                None => Value::Indexed(BTreeMap::new()),
+            },
+        );
+
+        for (subscript, value) in elements {
+            let subscript = match subscript {
+                Some(subscript) => self.subscript(&name, &subscript)?,
+                None if self.is_associative(&name) => {
+                    return Err(AssignError::MissingKey(name.to_string_lossy().into_owned()))
+                }
+                None => Subscript::Index(next as i64),
+            };
+            if let Subscript::Index(i) = subscript {
+                next = i.max(0) as usize + 1;
+            }
+            let value = self.transform(&name, value)?;
+            self.insert_element(&name, subscript, value)?;
+        }
+        Ok(())
+    }
+
+    /// Make a variable an array with `declare -a` or `declare -A`, a scalar becomes the first element of an indexed array.
+    ///
+    /// Indexed and associative arrays can't be converted into each other.
+    pub fn declare_array(&mut self, k: &OsString, associative: bool) -> Result<(), AssignError> {
+        let name = self.resolve(k);
+        let array = match self.map.remove(&name) {
+            None if associative => Value::Associative(BTreeMap::new()),
+            None => Value::Indexed(BTreeMap::new()),
+            Some(Value::Scalar(value)) if associative => {
+                Value::Associative(BTreeMap::from([("0".to_string(), value)]))
+            }
+            Some(Value::Scalar(value)) => Value::Indexed(BTreeMap::from([(0, value)])),
+            Some(old @ Value::Indexed(_)) if !associative => old,
+            Some(old @ Value::Associative(_)) if associative => old,
+            Some(old) => {
+                self.map.insert(name.clone(), old);
+                return Err(AssignError::CannotConvert(
+                    name.to_string_lossy().into_owned(),
+                ));
+            }
+        };
+        self.map.insert(name, array);
+        Ok(())
+    }
+
+    /// Get an element of an array, `None` if there isn't an element at `subscript`.
+    ///
+    /// Negative indices count back from the end of the array, a scalar is an array with one element.
+    pub fn element(
+        &mut self,
+        k: &OsString,
+        subscript: &str,
+    ) -> Result<Option<OsString>, AssignError> {
+        let name = self.resolve(k);
+        let subscript = self.subscript(&name, subscript)?;
+        Ok(self.element_at(&name, &subscript))
+    }
+
+    /// Get every element of an array in order, a scalar is an array with one element
+    pub fn elements(&self, k: &OsString) -> Vec<OsString> {
+        match self.map.get(&self.resolve(k)) {
+            Some(Value::Scalar(value)) => vec![value.clone()],
+            Some(Value::Indexed(elements)) => elements.values().cloned().collect(),
+            Some(Value::Associative(elements)) => elements.values().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Check if a variable is an associative array
+    pub fn is_associative(&self, k: &OsString) -> bool {
+        matches!(self.map.get(&self.resolve(k)), Some(Value::Associative(_)))
+    }
+
+    /// Unset one element of an array
+    pub fn unset_element(&mut self, k: &OsString, subscript: &str) -> Result<(), AssignError> {
+        let name = self.resolve(k);
+        self.check_writable(&name)?;
+
+        let subscript = self.subscript(&name, subscript)?;
+        let index = self.index(&name, &subscript);
+        match (self.map.get_mut(&name), subscript, index) {
+            (Some(Value::Associative(elements)), Subscript::Key(key), _) => {
+                elements.remove(&key);
+            }
+            (Some(Value::Indexed(elements)), _, Some(i)) => {
+                elements.remove(&i);
+            }
+            (Some(Value::Scalar(_)), _, Some(0)) => {
+                self.map.remove(&name);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn check_writable(&self, name: &Name) -> Result<(), AssignError> {
+        match self.attributes(name).readonly {
+            true => Err(AssignError::Readonly(name.to_string_lossy().into_owned())),
+            false => Ok(()),
+        }
+    }
+
+    /// Evaluate a subscript of `name`, an arithmetic expression for an indexed array or a key for an associative one
+    fn subscript(&mut self, name: &Name, subscript: &str) -> Result<Subscript, AssignError> {
+        if self.is_associative(name) {
+            return Ok(Subscript::Key(subscript.to_string()));
+        }
+
+        // an empty index, like `${a[$unset]}`, is the first element
+        if subscript.trim().is_empty() {
+            return Ok(Subscript::Index(0));
+        }
+        let bad_subscript =
+            || AssignError::BadSubscript(format!("{}[{}]", name.to_string_lossy(), subscript));
+        let index = self.arithmetic(subscript, bad_subscript)?;
+        index
+            .parse::<f64>()
+            .map(|i| Subscript::Index(i as i64))
+            .map_err(|_| bad_subscript())
+    }
+
+    /// The index in the array `name` an evaluated subscript refers to, negative indices count back from the end
+    fn index(&self, name: &Name, subscript: &Subscript) -> Option<usize> {
+        let i = match subscript {
+            Subscript::Index(i) => *i,
+            Subscript::Key(_) => return None,
+        };
+        if i >= 0 {
+            return Some(i as usize);
+        }
+
+        let len = match self.map.get(name) {
+            Some(Value::Indexed(elements)) => elements.keys().next_back().map_or(0, |i| i + 1),
+            Some(Value::Scalar(_)) => 1,
+            _ => 0,
+        };
+        usize::try_from(len as i64 + i).ok()
+    }
+
+    fn element_at(&self, name: &Name, subscript: &Subscript) -> Option<OsString> {
+        let index = self.index(name, subscript);
+        match (self.map.get(name)?, subscript) {
+            (Value::Associative(elements), Subscript::Key(key)) => elements.get(key).cloned(),
+            (Value::Indexed(elements), _) => elements.get(&index?).cloned(),
+            (Value::Scalar(value), _) if index? == 0 => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    fn insert_element(
+        &mut self,
+        name: &Name,
+        subscript: Subscript,
+        value: OsString,
+    ) -> Result<(), AssignError> {
+        let index = self.index(name, &subscript);
+        let bad_subscript =
+            |i: i64| AssignError::BadSubscript(format!("{}[{}]", name.to_string_lossy(), i));
+        let old = self.map.remove(name);
+        let array = match (old, subscript) {
+            (Some(Value::Associative(mut elements)), Subscript::Key(key)) => {
+                elements.insert(key, value);
+                Value::Associative(elements)
+            }
+            (old, Subscript::Index(i)) => {
+                let mut elements = match old {
+                    Some(Value::Indexed(elements)) => elements,
+                    Some(Value::Scalar(old)) => BTreeMap::from([(0, old)]),
+                    _ => BTreeMap::new(),
+                };
+                match index {
+                    Some(index) => elements.insert(index, value),
+                    None => {
+                        self.map.insert(name.clone(), Value::Indexed(elements));
+                        return Err(bad_subscript(i));
+                    }
+                };
+                Value::Indexed(elements)
+            }
+            (_, Subscript::Key(_)) => unreachable!("keys are only used for associative arrays"),
+        };
+        self.map.insert(name.clone(), array);
+        Ok(())
     }
 
+    /// The value `name` has after appending `value` to `old`, integer variables add them instead
+    fn appended(
+        &mut self,
+        name: &Name,
+        old: OsString,
+        value: OsString,
+    ) -> Result<OsString, AssignError> {
+        if !self.attributes(name).integer {
+            let mut old = old;
+            old.push(value);
+            return Ok(old);
+        }
+
+        let old = if old.is_empty() { "0".into() } else { old };
+        let expression = format!(
+            "({}) + ({})",
+            old.to_string_lossy(),
+            value.to_string_lossy()
+        );
+        let bad_integer = || AssignError::BadInteger(value.to_string_lossy().into_owned());
+        self.arithmetic(&expression, bad_integer).map(Into::into)
+    }
+
+    /// Evaluate an arithmetic expression, `error` is the failure used for anything but a failed assignment
+    fn arithmetic<F: Fn() -> AssignError>(
+        &mut self,
+        expression: &str,
+        error: F,
+    ) -> Result<String, AssignError> {
+        if expression.trim().is_empty() {
+            return Err(error());
+        }
+        expr::eval(expression, self).map_err(|e| match e.kind() {
+            expr::ErrorKind::Assign(e) => (**e).clone(),
+            _ => error(),
+        })
+    }
+
+    /// Transform a value assigned to `name` according to its attributes
+    fn transform(&mut self, name: &Name, mut value: OsString) -> Result<OsString, AssignError> {
+        let attributes = self.attributes(name);
+        if attributes.integer {
+            let expression = value.to_string_lossy().into_owned();
+            value = match expression.trim() {
+                "" => OsString::from("0"),
+                _ => self
+                    .arithmetic(&expression, || AssignError::BadInteger(expression.clone()))?
+                    .into(),
+            };
+        }
+        if let Some(s) = value.to_str() {
+            if attributes.lowercase {
+                value = s.to_lowercase().into();
+            } else if attributes.uppercase {
+                value = s.to_uppercase().into();
+            }
+        }
+        Ok(value)
+    }
+
+    /// Make `k` a name reference to the variable `target`, replacing any reference it already was
+    pub fn set_nameref<T: Into<OsString>, U: Into<OsString>>(
+        &mut self,
+        k: T,
+        target: U,
+    ) -> Result<(), AssignError> {
+        let name = k.into();
+        let attributes = self.attributes(&name);
+        if attributes.readonly {
+            return Err(AssignError::Readonly(name.to_string_lossy().into_owned()));
+        }
+
+        self.attributes.insert(
+            name.clone(),
+            Attributes {
+                nameref: true,
+                ..attributes
+            },
+        );
+        self.map.insert(name, Value::Scalar(target.into()));
+        Ok(())
+    }
+
+    /// Assign a value to a variable with the name `name`, assigning an array sets its first element
+    fn assign(&mut self, name: Name, value: OsString) -> Result<(), AssignError> {
+        self.check_writable(&name)?;
+        let value = self.transform(&name, value)?;
+        if self.dynamic.contains(&name) {
+            self.assign_dynamic(&name, &value);
+            return Ok(());
+        }
+        match self.map.get(&name) {
+            Some(Value::Indexed(_)) => self.insert_element(&name, Subscript::Index(0), value),
+            Some(Value::Associative(_)) => {
+                self.insert_element(&name, Subscript::Key("0".to_string()), value)
+            }
+            _ => {
+                self.map.insert(name, Value::Scalar(value));
+                Ok(())
+            }
+        }
+    }
+
+    /// Remove a variable without looking at its attributes
     pub fn remove(&mut self, k: &OsString) {
         self.map.remove(k);
     }
 
+    /// Unset a variable the way the shell language does, following name references.
+    ///
+    /// The variable's attributes are forgotten along with its value, fails if it's readonly.
+    pub fn unset(&mut self, k: &OsString) -> Result<(), AssignError> {
+        let name = self.resolve(k);
+        let attributes = self.attributes(&name);
+        if attributes.readonly {
+            return Err(AssignError::Readonly(name.to_string_lossy().into_owned()));
+        }
+
+        self.map.remove(&name);
+        self.attributes.remove(&name);
+        self.dynamic.remove(&name);
+        Ok(())
+    }
+
+    /// Get the attributes of a variable, a variable without any has the default attributes
+    pub fn attributes(&self, k: &OsString) -> Attributes {
+        self.attributes.get(k).copied().unwrap_or_default()
+    }
+
+    /// Replace the attributes of a variable, which doesn't have to be set.
+    ///
+    /// Exported variables are put in the environment of the shell's children, see `environment`.
+    /// A readonly variable's attributes can't be changed.
+    pub fn set_attributes(
+        &mut self,
+        k: &OsString,
+        attributes: Attributes,
+    ) -> Result<(), AssignError> {
+        let old = self.attributes(k);
+        if old.readonly && attributes != old {
+            return Err(AssignError::Readonly(k.to_string_lossy().into_owned()));
+        }
+
+        if attributes == Attributes::default() {
+            self.attributes.remove(k);
+        } else {
+            self.attributes.insert(k.clone(), attributes);
+        }
+        Ok(())
+    }
+
+    /// Copy a variable's value and attributes, following name references, so it can be put back with `restore`
+    pub fn save(&self, k: &OsString) -> Saved {
+        let name = self.resolve(k);
+        Saved {
+            value: self.map.get(&name).cloned(),
+            attributes: self.attributes(&name),
+            name,
+        }
+    }
+
+    /// Put back a variable copied with `save`, without looking at its current attributes
+    pub fn restore(&mut self, saved: Saved) {
+        let Saved {
+            name,
+            value,
+            attributes,
+        } = saved;
+        match value {
+            Some(value) => self.map.insert(name.clone(), value),
+            None => self.map.remove(&name),
+        };
+        if attributes == Attributes::default() {
+            self.attributes.remove(&name);
+        } else {
+            self.attributes.insert(name, attributes);
+        }
+    }
+
+    /// Iterate over the variables that have attributes, whether or not they're set
+    pub fn iter_attributes(&self) -> btree_map::Iter<'_, Name, Attributes> {
+        self.attributes.iter()
+    }
+
+    /// Follow name references starting at `k`, to get the name of the variable it refers to
+    pub fn resolve(&self, k: &OsString) -> Name {
+        let mut name = k;
+        for _ in 0..MAX_NAMEREF_DEPTH {
+            if !self.attributes(name).nameref {
+                break;
+            }
+            match self.map.get(name) {
+                Some(Value::Scalar(target)) if !target.is_empty() => name = target,
+                _ => break,
+            }
+        }
+        name.clone()
+    }
+
     pub fn value(&self, k: &OsString) -> OsString {
-        self.map
-            .get(k)
-            .map(|v| v.clone())
-            .unwrap_or(OsString::new())
+        if let Some(v) = self.special(k) {
+            return v;
+        }
+
+        let name = self.resolve(k);
+        if let Some(v) = self.dynamic(&name) {
+            return v;
+        }
+        self.map.get(&name).map(Value::scalar).unwrap_or_default()
+    }
+
+    /// Compute the value of a variable in `DYNAMIC`, if `name` is one that hasn't been unset
+    fn dynamic(&self, name: &Name) -> Option<OsString> {
+        if !self.dynamic.contains(name) {
+            return None;
+        }
+
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let value = match name.to_str()? {
+            "RANDOM" => self.next_random().to_string(),
+            "SECONDS" => {
+                let (start, from) = self.seconds;
+                (from + start.elapsed().as_secs() as i64).to_string()
+            }
+            "EPOCHSECONDS" => since_epoch.as_secs().to_string(),
+            "EPOCHREALTIME" => format!(
+                "{}.{:06}",
+                since_epoch.as_secs(),
+                since_epoch.subsec_micros()
+            ),
+            "PPID" => self.parent_pid.to_string(),
+            // unlike `$$`, this is the process reading it, which is different in a subshell
+            "RUSH_PID" => i32::from(nix::unistd::getpid()).to_string(),
+            _ => return None,
+        };
+        Some(value.into())
+    }
+
+    /// Assign a variable in `DYNAMIC`, values that aren't integers count as 0
+    fn assign_dynamic(&mut self, name: &Name, value: &OsString) {
+        let number = value.to_string_lossy().trim().parse::<i64>().unwrap_or(0);
+        match name.to_str() {
+            Some("RANDOM") => self.random.set(number as u32),
+            Some("SECONDS") => self.seconds = (Instant::now(), number),
+            _ => (),
+        }
+    }
+
+    /// Step the generator behind `$RANDOM`, a linear congruential generator like C's `rand`, giving a number from 0 to 32767
+    fn next_random(&self) -> u32 {
+        let state = self
+            .random
+            .get()
+            .wrapping_mul(1_103_515_245)
+            .wrapping_add(12345);
+        self.random.set(state);
+        (state >> 16) & 0x7fff
+    }
+
+    /// Get the value of a variable itself, without following name references
+    pub fn get(&self, k: &OsString) -> Option<&Value> {
+        self.map.get(k)
+    }
+
+    /// Get the value of a special parameter (`$?`, `$#`, `$1`, etc.), if `k` names one
+    fn special(&self, k: &OsString) -> Option<OsString> {
+        let name = k.to_str()?;
+        match name {
+            "?" => Some(self.last_status.to_string().into()),
+            "#" => Some(self.positional.len().to_string().into()),
+            "$" => Some(self.shell_pid.to_string().into()),
+            "-" => Some(self.options.to_string().into()),
+            "!" => Some(
+                self.last_background
+                    .map(|pid| pid.to_string().into())
+                    .unwrap_or_default(),
+            ),
+            "0" => Some(self.arg0.clone()),
+            "@" | "*" => Some(self.positional.join(&OsString::from(" "))),
+            _ if name.chars().all(|c| c.is_ascii_digit()) && !name.is_empty() => Some(
+                name.parse::<usize>()
+                    .ok()
+                    .and_then(|n| n.checked_sub(1))
+                    .and_then(|n| self.positional.get(n))
+                    .cloned()
+                    .unwrap_or_default(),
+            ),
+            _ => None,
+        }
     }
 
     pub fn exists<T: Into<OsString>>(&self, k: &OsString) -> bool {
         self.map.contains_key(k)
     }
 
+    /// Check if a variable or special parameter is set
+    ///
+    /// Positional parameters are only set if there are enough arguments, `$@` and `$*` are always set.
+    pub fn is_set(&self, k: &OsString) -> bool {
+        match k.to_str() {
+            Some("?") | Some("#") | Some("$") | Some("-") | Some("0") | Some("@") | Some("*") => {
+                true
+            }
+            Some("!") => self.last_background.is_some(),
+            _ if self.dynamic.contains(&self.resolve(k)) => true,
+            Some(n) if n.chars().all(|c| c.is_ascii_digit()) && !n.is_empty() => n
+                .parse::<usize>()
+                .map(|n| n <= self.positional.len())
+                .unwrap_or(false),
+            _ => self.map.contains_key(&self.resolve(k)),
+        }
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    pub fn set_last_background(&mut self, pid: i32) {
+        self.last_background = Some(pid);
+    }
+
+    pub fn set_last_status(&mut self, status: i32) {
+        self.last_status = status;
+    }
+
+    pub fn arg0(&self) -> &OsString {
+        &self.arg0
+    }
+
+    pub fn set_arg0<T: Into<OsString>>(&mut self, arg0: T) {
+        self.arg0 = arg0.into();
+    }
+
+    pub fn positional(&self) -> &[OsString] {
+        &self.positional
+    }
+
+    /// Replace the positional parameters, returning the old ones
+    pub fn set_positional<T: Into<OsString>, I: IntoIterator<Item = T>>(
+        &mut self,
+        args: I,
+    ) -> Vec<OsString> {
+        std::mem::replace(
+            &mut self.positional,
+            args.into_iter().map(|a| a.into()).collect(),
+        )
+    }
+
     pub fn has_value<T: Into<OsString>>(&self, k: &OsString) -> bool {
-        self.map.get(k).map(|v| v.len() > 0).unwrap_or(false)
+        self.map
+            .get(k)
+            .map(|v| !v.scalar().is_empty())
+            .unwrap_or(false)
     }
 
     pub fn entry<'a, T: Into<Name>>(&'a mut self, key: T) -> Entry<'a> {
@@ -79,8 +838,18 @@ impl Variables {
         }
     }
 
-    pub fn export(&self, k: &OsString) {
-        env::set_var(k, self.value(k));
+    /// Get the exported variables that are set, the environment of the shell's children.
+    ///
+    /// Arrays aren't exported, like in bash.
+    pub fn environment(&self) -> Vec<(OsString, OsString)> {
+        self.attributes
+            .iter()
+            .filter(|(_, attributes)| attributes.exported)
+            .filter_map(|(name, _)| match self.map.get(name) {
+                Some(Value::Scalar(value)) => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
     }
 
     pub fn iter<'a>(&'a self) -> Iter<'a> {
@@ -185,7 +954,7 @@ impl<'a> Entry<'a> {
     pub fn or_insert_null<T: Into<Value>>(self, default: T) -> &'a mut Value {
         match self {
             Entry::Occupied(e) => {
-                let isnull = e.get().len() == 0;
+                let isnull = e.get().scalar().is_empty();
                 let mutref = e.into_mut();
                 if isnull {
                     *mutref = default.into();
@@ -216,3 +985,85 @@ impl IntoIterator for Variables {
         self.map.into_iter()
     }
 }
+
+impl Value {
+    /// The value used where a single string is expected, arrays use the element at index or key `0`
+    pub fn scalar(&self) -> OsString {
+        match self {
+            Value::Scalar(value) => value.clone(),
+            Value::Indexed(elements) => elements.get(&0).cloned().unwrap_or_default(),
+            Value::Associative(elements) => elements.get("0").cloned().unwrap_or_default(),
+        }
+    }
+}
+
+impl From<OsString> for Value {
+    fn from(value: OsString) -> Value {
+        Value::Scalar(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::Scalar(value.into())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::Scalar(value.into())
+    }
+}
+
+/// A seed for `$RANDOM` that's different each time the shell starts
+fn random_seed() -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    nanos ^ i32::from(nix::unistd::getpid()) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::Variables;
+    use std::ffi::OsString;
+
+    #[test]
+    fn dynamic() {
+        let mut vars = Variables::new();
+        let value = |vars: &Variables, name: &str| {
+            vars.value(&OsString::from(name))
+                .into_string()
+                .expect("value isn't unicode")
+        };
+
+        // the same seed gives the same numbers
+        vars.set("RANDOM", "42").unwrap();
+        let first: Vec<_> = (0..3).map(|_| value(&vars, "RANDOM")).collect();
+        vars.set("RANDOM", "42").unwrap();
+        let second: Vec<_> = (0..3).map(|_| value(&vars, "RANDOM")).collect();
+        assert_eq!(first, second);
+        assert_ne!(first[0], first[1]);
+        assert!(first.iter().all(|n| n.parse::<u32>().unwrap() < 32768));
+
+        vars.set("SECONDS", "100").unwrap();
+        assert_eq!(value(&vars, "SECONDS"), "100");
+        assert!(vars.get(&"SECONDS".into()).is_none());
+
+        let realtime = value(&vars, "EPOCHREALTIME");
+        let (seconds, micros) = realtime.split_once('.').expect("no fraction");
+        assert!(seconds.parse::<u64>().unwrap() >= 1_500_000_000);
+        assert_eq!(micros.len(), 6);
+        assert!(value(&vars, "EPOCHSECONDS").parse::<u64>().is_ok());
+        assert_eq!(value(&vars, "RUSH_PID"), std::process::id().to_string());
+        assert!(vars.is_set(&"PPID".into()));
+
+        // unsetting one makes it an ordinary variable
+        vars.unset(&"RANDOM".into()).unwrap();
+        assert!(!vars.is_set(&"RANDOM".into()));
+        vars.set("RANDOM", "7").unwrap();
+        assert_eq!(value(&vars, "RANDOM"), "7");
+        assert_eq!(value(&vars, "RANDOM"), "7");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::env::variables::AssignError;
 use failure;
 use std::{fmt, result};
 
@@ -46,6 +47,10 @@ pub enum ErrorKind {
 
     #[fail(display = "unexpected end-of-expression")]
     UnexpectedEof,
+
+    /// A variable couldn't be assigned, like a readonly variable
+    #[fail(display = "{}", _0)]
+    Assign(Box<AssignError>),
 }
 
 impl Error {
@@ -1,13 +1,19 @@
+use crate::env::{fds::MIN_INTERNAL_FD, signals::Signal};
 use nix::{
     self,
+    errno::Errno,
+    libc,
+    sys::signal::{self, SaFlags, SigHandler, SigSet, SigmaskHow},
     unistd::{ForkResult, Pid},
 };
 use std::{
     env,
-    ffi::CString,
-    fmt,
+    ffi::{CString, OsStr, OsString},
+    fmt, mem,
+    os::unix::{ffi::OsStrExt, io::RawFd},
     path::{Path, PathBuf},
     process::exit,
+    ptr,
 };
 
 /// An error that occurs in a subprocess during setup, before `exec` is called.
@@ -47,6 +53,12 @@ pub enum SubprocessSetupError {
     /// Failed to chdir to the process' working directory
     SetWorkDirFailed { source: nix::Error, path: PathBuf },
 
+    /// Failed to change the action taken when a signal is received
+    SetSignalFailed { source: nix::Error, signal: Signal },
+
+    /// Failed to set the process' signal mask
+    SetSignalMaskFailed { source: nix::Error },
+
     /// A call to exec() failed
     ExecFailed {
         args: Vec<String>,
@@ -75,6 +87,7 @@ impl fmt::Display for SubprocessSetupError {
             } => {
                 let action = match mode {
                     OpenMode::Read => "reading",
+                    OpenMode::ReadWrite => "reading and writing",
                     _ => "writing",
                 };
                 write!(
@@ -102,6 +115,32 @@ impl fmt::Display for SubprocessSetupError {
                     path, source
                 )
             }
+            Self::SetSignalFailed { source, signal } => {
+                write!(f, "failed to set the action for {}: {}", signal, source)
+            }
+            Self::SetSignalMaskFailed { source } => {
+                write!(f, "failed to set the signal mask: {}", source)
+            }
+        }
+    }
+}
+
+impl SubprocessSetupError {
+    /// The exit status of a child process that failed with this error.
+    ///
+    /// Like other shells, it's 127 if the executable doesn't exist, 126 if it can't be executed, and 1 for anything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::ExecFailed {
+                source: nix::Error::Sys(Errno::ENOENT),
+                ..
+            }
+            | Self::ExecFailed {
+                source: nix::Error::Sys(Errno::ENOTDIR),
+                ..
+            } => 127,
+            Self::ExecFailed { .. } => 126,
+            _ => 1,
         }
     }
 }
@@ -112,21 +151,245 @@ impl std::error::Error for SubprocessSetupError {
             Self::CloseFailed { source, .. } => Some(source),
             Self::DupFailed { source, .. } => Some(source),
             Self::OpenFailed { source, .. } => Some(source),
+            Self::SetSignalFailed { source, .. } => Some(source),
+            Self::SetSignalMaskFailed { source } => Some(source),
 
             _ => None,
         }
     }
 }
 
+impl SubprocessSetupError {
+    /// Serialize the error, so a child process can send it to its parent
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::CloseFailed { source, fd } => {
+                buf.push(0);
+                encode_nix_error(buf, source);
+                encode_int(buf, *fd as i64);
+            }
+            Self::DupFailed {
+                source,
+                oldfd,
+                newfd,
+            } => {
+                buf.push(1);
+                encode_nix_error(buf, source);
+                encode_int(buf, *oldfd as i64);
+                encode_int(buf, *newfd as i64);
+            }
+            Self::OpenFailed {
+                source,
+                file,
+                flags,
+                permissions,
+            } => {
+                buf.push(2);
+                encode_nix_error(buf, source);
+                encode_bytes(buf, file.as_os_str().as_bytes());
+                encode_int(buf, flags.bits() as i64);
+                encode_int(buf, permissions.bits() as i64);
+            }
+            Self::OpenAndDupFailed {
+                source,
+                file,
+                mode,
+                fd,
+            } => {
+                buf.push(3);
+                source.encode(buf);
+                encode_bytes(buf, file.as_os_str().as_bytes());
+                encode_int(buf, *mode as i64);
+                encode_int(buf, *fd as i64);
+            }
+            Self::ArgContainsNull { arg_number, arg } => {
+                buf.push(4);
+                encode_int(buf, *arg_number as i64);
+                encode_bytes(buf, arg.as_bytes());
+            }
+            Self::SetWorkDirFailed { source, path } => {
+                buf.push(5);
+                encode_nix_error(buf, source);
+                encode_bytes(buf, path.as_os_str().as_bytes());
+            }
+            Self::SetSignalFailed { source, signal } => {
+                buf.push(6);
+                encode_nix_error(buf, source);
+                encode_int(buf, signal.number() as i64);
+            }
+            Self::SetSignalMaskFailed { source } => {
+                buf.push(7);
+                encode_nix_error(buf, source);
+            }
+            Self::ExecFailed {
+                args,
+                executable,
+                source,
+            } => {
+                buf.push(8);
+                encode_int(buf, args.len() as i64);
+                for arg in args {
+                    encode_bytes(buf, arg.as_bytes());
+                }
+                encode_bytes(buf, executable.as_bytes());
+                encode_nix_error(buf, source);
+            }
+        }
+    }
+
+    /// Read an error written by `encode` from the start of `buf`, `None` if it's malformed
+    fn decode(buf: &mut &[u8]) -> Option<SubprocessSetupError> {
+        use nix::fcntl::OFlag;
+        use nix::sys::stat::Mode;
+
+        let (tag, rest) = buf.split_first()?;
+        *buf = rest;
+        Some(match tag {
+            0 => Self::CloseFailed {
+                source: decode_nix_error(buf)?,
+                fd: decode_int(buf)? as i32,
+            },
+            1 => Self::DupFailed {
+                source: decode_nix_error(buf)?,
+                oldfd: decode_int(buf)? as i32,
+                newfd: decode_int(buf)? as i32,
+            },
+            2 => Self::OpenFailed {
+                source: decode_nix_error(buf)?,
+                file: decode_path(buf)?,
+                flags: OFlag::from_bits_truncate(decode_int(buf)? as libc::c_int),
+                permissions: Mode::from_bits_truncate(decode_int(buf)? as libc::mode_t),
+            },
+            3 => Self::OpenAndDupFailed {
+                source: Box::new(Self::decode(buf)?),
+                file: decode_path(buf)?,
+                mode: match decode_int(buf)? {
+                    0 => OpenMode::Read,
+                    1 => OpenMode::Write,
+                    2 => OpenMode::Append,
+                    3 => OpenMode::ReadWrite,
+                    _ => return None,
+                },
+                fd: decode_int(buf)? as i32,
+            },
+            4 => Self::ArgContainsNull {
+                arg_number: decode_int(buf)? as usize,
+                arg: decode_string(buf)?,
+            },
+            5 => Self::SetWorkDirFailed {
+                source: decode_nix_error(buf)?,
+                path: decode_path(buf)?,
+            },
+            6 => Self::SetSignalFailed {
+                source: decode_nix_error(buf)?,
+                signal: Signal::from_number(decode_int(buf)? as libc::c_int)?,
+            },
+            7 => Self::SetSignalMaskFailed {
+                source: decode_nix_error(buf)?,
+            },
+            8 => {
+                let count = decode_int(buf)?;
+                let mut args = Vec::new();
+                for _ in 0..count {
+                    args.push(decode_string(buf)?);
+                }
+                Self::ExecFailed {
+                    args,
+                    executable: decode_string(buf)?,
+                    source: decode_nix_error(buf)?,
+                }
+            }
+            _ => return None,
+        })
+    }
+}
+
+fn encode_int(buf: &mut Vec<u8>, n: i64) {
+    buf.extend_from_slice(&n.to_ne_bytes());
+}
+
+fn encode_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    encode_int(buf, bytes.len() as i64);
+    buf.extend_from_slice(bytes);
+}
+
+/// System errors are stored as their errno, nix's other errors as negative numbers
+fn encode_nix_error(buf: &mut Vec<u8>, e: &nix::Error) {
+    let n = match e {
+        nix::Error::Sys(errno) => *errno as i64,
+        nix::Error::InvalidPath => -1,
+        nix::Error::InvalidUtf8 => -2,
+        nix::Error::UnsupportedOperation => -3,
+    };
+    encode_int(buf, n);
+}
+
+fn decode_int(buf: &mut &[u8]) -> Option<i64> {
+    const SIZE: usize = mem::size_of::<i64>();
+    if buf.len() < SIZE {
+        return None;
+    }
+
+    let (n, rest) = buf.split_at(SIZE);
+    *buf = rest;
+    n.try_into().ok().map(i64::from_ne_bytes)
+}
+
+fn decode_bytes<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = decode_int(buf)? as usize;
+    if buf.len() < len {
+        return None;
+    }
+
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Some(bytes)
+}
+
+fn decode_string(buf: &mut &[u8]) -> Option<String> {
+    String::from_utf8(decode_bytes(buf)?.to_vec()).ok()
+}
+
+fn decode_path(buf: &mut &[u8]) -> Option<PathBuf> {
+    Some(PathBuf::from(OsStr::from_bytes(decode_bytes(buf)?)))
+}
+
+fn decode_nix_error(buf: &mut &[u8]) -> Option<nix::Error> {
+    Some(match decode_int(buf)? {
+        -1 => nix::Error::InvalidPath,
+        -2 => nix::Error::InvalidUtf8,
+        -3 => nix::Error::UnsupportedOperation,
+        errno => nix::Error::Sys(Errno::from_i32(errno as i32)),
+    })
+}
+
 #[derive(Debug, Clone)]
 pub enum SpawnError {
-    ForkFailed { source: nix::Error },
+    ForkFailed {
+        source: nix::Error,
+    },
+
+    /// Failed to create or read the pipe the child reports setup errors through
+    ErrorPipeFailed {
+        source: nix::Error,
+    },
+
+    /// The child process was created, but it failed before it could exec.
+    ///
+    /// The child has already exited and been waited for.
+    SetupFailed {
+        source: SubprocessSetupError,
+    },
 }
 
 impl fmt::Display for SpawnError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::ForkFailed { source } => write!(f, "fork failed: {}", source),
+            Self::ErrorPipeFailed { source } => {
+                write!(f, "failed to read errors from the child: {}", source)
+            }
+            Self::SetupFailed { source } => write!(f, "{}", source),
         }
     }
 }
@@ -134,6 +397,8 @@ impl std::error::Error for SpawnError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::ForkFailed { source, .. } => Some(source),
+            Self::ErrorPipeFailed { source } => Some(source),
+            Self::SetupFailed { source } => Some(source),
         }
     }
 }
@@ -166,6 +431,19 @@ pub enum OpenMode {
 
     /// Open a file for writing, appending to the content in the file. If the file does not exist create it
     Append,
+
+    /// Open a file for reading and writing, create it if it doesn't exist
+    ReadWrite,
+}
+
+/// Action a process takes when it receives a signal, these are the ones that survive `exec`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Disposition {
+    /// The signal's default action, usually terminating the process
+    Default,
+
+    /// Ignore the signal
+    Ignore,
 }
 
 /// Description of a process to be spawned
@@ -174,19 +452,31 @@ pub struct ProcessOptions {
     /// Process working directory, `None` means inherit from parent process
     wd: Option<PathBuf>,
 
+    /// The whole environment of the process, `None` means inherit the current process' environment
+    environment: Option<Vec<(OsString, OsString)>>,
+
     /// *Additional* environment variables to be set for this process, it will inherit all variables from the current process
     env: Vec<(String, String)>,
 
     /// List of file descriptors and actions to perform on them
     fd: Vec<(i32, FdOp)>,
+
+    /// Signal actions to set, later entries for the same signal win
+    signals: Vec<(Signal, Disposition)>,
+
+    /// Signals blocked in the process, `None` means inherit the parent's signal mask
+    mask: Option<Vec<Signal>>,
 }
 
 impl Default for ProcessOptions {
     fn default() -> ProcessOptions {
         ProcessOptions {
+            environment: None,
             env: vec![],
             fd: vec![],
             wd: None,
+            signals: vec![],
+            mask: None,
         }
     }
 }
@@ -201,6 +491,29 @@ impl ProcessOptions {
         self
     }
 
+    /// Add all of `other`'s environment variables, file descriptor operations and signal actions, after the ones in `self`
+    pub fn extend(mut self, other: &ProcessOptions) -> Self {
+        if other.environment.is_some() {
+            self.environment = other.environment.clone();
+        }
+        self.env.extend(other.env.iter().cloned());
+        self.fd.extend(other.fd.iter().cloned());
+        self.signals.extend(other.signals.iter().cloned());
+        if other.wd.is_some() {
+            self.wd = other.wd.clone();
+        }
+        if other.mask.is_some() {
+            self.mask = other.mask.clone();
+        }
+        self
+    }
+
+    /// Replace the process' environment with `vars`, variables set with `env` are still added to it
+    pub fn environment(mut self, vars: Vec<(OsString, OsString)>) -> Self {
+        self.environment = Some(vars);
+        self
+    }
+
     pub fn env(mut self, k: &str, v: &str) -> Self {
         self.env.push((k.into(), v.into()));
         self
@@ -223,6 +536,10 @@ impl ProcessOptions {
         self.add_fd_op(fd, FdOp::Open(file.into(), OpenMode::Append))
     }
 
+    pub fn read_write<I: Into<PathBuf>>(self, fd: i32, file: I) -> Self {
+        self.add_fd_op(fd, FdOp::Open(file.into(), OpenMode::ReadWrite))
+    }
+
     pub fn close(self, fd: i32) -> Self {
         self.add_fd_op(fd, FdOp::Close)
     }
@@ -231,26 +548,275 @@ impl ProcessOptions {
         self.add_fd_op(source_fd, FdOp::Redirect(target_fd))
     }
 
+    /// Get the file descriptor operations, in the order they're applied
+    pub fn fd_ops(&self) -> &[(i32, FdOp)] {
+        &self.fd
+    }
+
+    /// Restore the default action for `sig` in the process
+    pub fn default_signal(mut self, sig: Signal) -> Self {
+        self.signals.push((sig, Disposition::Default));
+        self
+    }
+
+    /// Ignore `sig` in the process
+    pub fn ignore_signal(mut self, sig: Signal) -> Self {
+        self.signals.push((sig, Disposition::Ignore));
+        self
+    }
+
+    /// Block exactly the signals in `signals`, instead of inheriting the parent's signal mask
+    pub fn signal_mask<I: IntoIterator<Item = Signal>>(mut self, signals: I) -> Self {
+        self.mask = Some(signals.into_iter().collect());
+        self
+    }
+
+    /// Start `executable` in a new process with these options.
+    ///
+    /// Errors setting up the child or calling `exec` are sent back over a close-on-exec pipe,
+    /// so they're returned as `SpawnError::SetupFailed` instead of being mistaken for the command failing.
     pub fn spawn<S: AsRef<str>>(&self, executable: &str, args: &[S]) -> Result<Pid, SpawnError> {
-        match nix::unistd::fork() {
-            Err(source) => Err(SpawnError::ForkFailed { source }),
-            Ok(ForkResult::Child) => {
-                if let Err(e) = setup_subprocess(self) {
-                    eprintln!("could not spawn {:?}: {}", executable, e);
-                    exit(1);
+        let (read_end, write_end) = self.error_pipe()?;
+
+        let child = match fork_blocked() {
+            Ok((ForkResult::Child, mask)) => {
+                let _ = nix::unistd::close(read_end);
+                let e = match setup_subprocess(self, mask) {
+                    Ok(()) => match exec_subprocess(executable, args) {
+                        Err(e) => e,
+                        Ok(()) => unreachable!(),
+                    },
+                    Err(e) => e,
+                };
+
+                let mut buf = Vec::new();
+                e.encode(&mut buf);
+                let mut written = 0;
+                while written < buf.len() {
+                    match nix::unistd::write(write_end, &buf[written..]) {
+                        Ok(n) => written += n,
+                        Err(nix::Error::Sys(Errno::EINTR)) => (),
+                        Err(_) => break,
+                    }
                 }
+                exit(e.exit_code());
+            }
+            Ok((ForkResult::Parent { child }, _)) => child,
+            Err(e) => {
+                let _ = nix::unistd::close(read_end);
+                let _ = nix::unistd::close(write_end);
+                return Err(e);
+            }
+        };
+
+        // the write end is closed when the child execs, so this reads nothing if it succeeded
+        let _ = nix::unistd::close(write_end);
+        let result = read_all(read_end);
+        let _ = nix::unistd::close(read_end);
+        let buf = result.map_err(|source| SpawnError::ErrorPipeFailed { source })?;
+        if buf.is_empty() {
+            return Ok(child);
+        }
+
+        let _ = nix::sys::wait::waitpid(child, None);
+        match SubprocessSetupError::decode(&mut buf.as_slice()) {
+            Some(source) => Err(SpawnError::SetupFailed { source }),
+            None => Err(SpawnError::ErrorPipeFailed {
+                source: nix::Error::Sys(Errno::EINVAL),
+            }),
+        }
+    }
+
+    /// Create the pipe `spawn` uses to report errors from the child.
+    ///
+    /// Both ends are internal fds above every fd these options touch, so the child's redirects can't clobber it.
+    fn error_pipe(&self) -> Result<(RawFd, RawFd), SpawnError> {
+        use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+        let lowest = self
+            .fd
+            .iter()
+            .map(|(fd, op)| match op {
+                FdOp::Redirect(newfd) => (*fd).max(*newfd),
+                _ => *fd,
+            })
+            .max()
+            .map_or(MIN_INTERNAL_FD, |fd| (fd + 1).max(MIN_INTERNAL_FD));
+
+        let (read_end, write_end) = nix::unistd::pipe2(OFlag::O_CLOEXEC)
+            .map_err(|source| SpawnError::ErrorPipeFailed { source })?;
+        let mut fds = [read_end, write_end];
+        for fd in &mut fds {
+            if *fd >= lowest {
+                continue;
+            }
+
+            let moved = fcntl(*fd, FcntlArg::F_DUPFD_CLOEXEC(lowest));
+            let _ = nix::unistd::close(*fd);
+            match moved {
+                Ok(moved) => *fd = moved,
+                Err(source) => {
+                    for fd in &fds {
+                        let _ = nix::unistd::close(*fd);
+                    }
+                    return Err(SpawnError::ErrorPipeFailed { source });
+                }
+            }
+        }
 
-                if let Err(e) = exec_subprocess(executable, args) {
-                    // don't mention the executable here because its in the error message
-                    eprintln!("{}", e);
+        Ok((fds[0], fds[1]))
+    }
+
+    /// Fork a copy of the current process, and apply these options to it.
+    ///
+    /// Returns the child's pid in the parent, and `None` in the child.
+    pub fn fork(&self) -> Result<Option<Pid>, SpawnError> {
+        match fork_blocked()? {
+            (ForkResult::Child, mask) => {
+                if let Err(e) = setup_subprocess(self, mask) {
+                    eprintln!("could not start subshell: {}", e);
                     exit(1);
                 }
+                Ok(None)
+            }
+            (ForkResult::Parent { child }, _) => Ok(Some(child)),
+        }
+    }
+
+    /// Apply these options to the current process, then replace it with `executable`.
+    ///
+    /// This only returns if something went wrong.
+    pub fn exec<S: AsRef<str>>(
+        &self,
+        executable: &str,
+        args: &[S],
+    ) -> Result<(), SubprocessSetupError> {
+        let mask = SigSet::thread_get_mask()
+            .map_err(|source| SubprocessSetupError::SetSignalMaskFailed { source })?;
+        setup_subprocess(self, mask)?;
+        exec_subprocess(executable, args)
+    }
+}
+
+/// Fork with every signal blocked, so the parent's signal handlers can't run in the child before its signal actions are set.
+///
+/// The parent's signal mask is restored, the child gets a copy of it to restore after setting up.
+fn fork_blocked() -> Result<(ForkResult, SigSet), SpawnError> {
+    let mut mask = SigSet::empty();
+    signal::sigprocmask(
+        SigmaskHow::SIG_SETMASK,
+        Some(&SigSet::all()),
+        Some(&mut mask),
+    )
+    .map_err(|source| SpawnError::ForkFailed { source })?;
+
+    let result = nix::unistd::fork();
+    if let Ok(ForkResult::Parent { .. }) | Err(_) = result {
+        let _ = mask.thread_set_mask();
+    }
+
+    result
+        .map(|result| (result, mask))
+        .map_err(|source| SpawnError::ForkFailed { source })
+}
+
+/// Read from `fd` until end of file
+fn read_all(fd: RawFd) -> nix::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 512];
+    loop {
+        match nix::unistd::read(fd, &mut chunk) {
+            Ok(0) => return Ok(buf),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(nix::Error::Sys(Errno::EINTR)) => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// File descriptors replaced by `ProcessOptions::redirect_in_place`, and the copies needed to restore them
+#[derive(Debug)]
+pub struct SavedFds {
+    /// Pairs of (fd, copy of the original fd), the copy is `None` if the fd wasn't open
+    saved: Vec<(i32, Option<i32>)>,
+}
+
+impl SavedFds {
+    fn save(&mut self, fd: i32) {
+        use nix::fcntl::{fcntl, FcntlArg};
+
+        if self.saved.iter().any(|(saved_fd, _)| *saved_fd == fd) {
+            return;
+        }
+
+        // keep copies out of the way of low numbered fds the user may redirect, and don't leak them to children
+        let copy = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(MIN_INTERNAL_FD)).ok();
+        self.saved.push((fd, copy));
+    }
+
+    /// Keep the redirected file descriptors, and close the copies of the originals
+    pub fn forget(self) {
+        for (_, copy) in self.saved {
+            if let Some(copy) = copy {
+                let _ = nix::unistd::close(copy);
+            }
+        }
+    }
 
-                unreachable!();
+    /// Put every file descriptor back the way it was before the redirect
+    pub fn restore(self) {
+        for (fd, copy) in self.saved.into_iter().rev() {
+            match copy {
+                Some(copy) => {
+                    let _ = nix::unistd::dup2(copy, fd);
+                    let _ = nix::unistd::close(copy);
+                }
+                None => {
+                    let _ = nix::unistd::close(fd);
+                }
             }
+        }
+    }
+}
+
+impl ProcessOptions {
+    /// Apply this process' file descriptor operations to the current process, instead of a child.
+    ///
+    /// This is used to redirect the input and output of builtins, the returned `SavedFds` should be used to undo the changes.
+    /// Environment variables, the working directory and signal actions are ignored.
+    pub fn redirect_in_place(&self) -> Result<SavedFds, SubprocessSetupError> {
+        let mut saved = SavedFds { saved: Vec::new() };
+
+        for (fd, op) in &self.fd {
+            let result = match op {
+                FdOp::Close => {
+                    saved.save(*fd);
+                    close(*fd)
+                }
+                FdOp::Redirect(newfd) => {
+                    saved.save(*newfd);
+                    dup(*fd, *newfd)
+                }
+                FdOp::Open(path, mode) => {
+                    saved.save(*fd);
+                    open_and_dup(path, *mode, *fd).map_err(|source| {
+                        SubprocessSetupError::OpenAndDupFailed {
+                            file: path.clone(),
+                            fd: *fd,
+                            mode: *mode,
+                            source: Box::new(source),
+                        }
+                    })
+                }
+            };
 
-            Ok(ForkResult::Parent { child }) => Ok(child),
+            if let Err(e) = result {
+                saved.restore();
+                return Err(e);
+            }
         }
+
+        Ok(saved)
     }
 }
 
@@ -277,6 +843,7 @@ fn close(fd: i32) -> Result<(), SubprocessSetupError> {
 /// - Read: O_RDONLY
 /// - Write: O_WRONLY | O_CREAT | O_TRUNC
 /// - Append: O_WRONLY | O_CREAT | O_APPEND
+/// - ReadWrite: O_RDWR | O_CREAT
 fn open<P: AsRef<Path>>(path: P, mode: OpenMode) -> Result<i32, SubprocessSetupError> {
     use nix::fcntl::OFlag;
     use nix::sys::stat::Mode;
@@ -288,6 +855,7 @@ fn open<P: AsRef<Path>>(path: P, mode: OpenMode) -> Result<i32, SubprocessSetupE
         OpenMode::Read => OFlag::O_RDONLY,
         OpenMode::Write => OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC,
         OpenMode::Append => OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND,
+        OpenMode::ReadWrite => OFlag::O_RDWR | OFlag::O_CREAT,
     };
 
     nix::fcntl::open(path.as_ref(), flags, permissions).map_err(|source| {
@@ -307,10 +875,38 @@ fn open_and_dup<P: AsRef<Path>>(
     fd: i32,
 ) -> Result<(), SubprocessSetupError> {
     let oldfd = open(path, mode)?;
-    dup(oldfd, fd)
+    if oldfd == fd {
+        return Ok(());
+    }
+
+    let result = dup(oldfd, fd);
+    let _ = nix::unistd::close(oldfd);
+    result
 }
 
-fn setup_subprocess(opts: &ProcessOptions) -> Result<(), SubprocessSetupError> {
+/// Set up the current process according to `opts`, `mask` is the signal mask to use if `opts` doesn't have one
+fn setup_subprocess(opts: &ProcessOptions, mask: SigSet) -> Result<(), SubprocessSetupError> {
+    for (sig, disposition) in &opts.signals {
+        let handler = match disposition {
+            Disposition::Default => SigHandler::SigDfl,
+            Disposition::Ignore => SigHandler::SigIgn,
+        };
+        unsafe { sig.set_handler(handler, SaFlags::empty()) }.map_err(|source| {
+            SubprocessSetupError::SetSignalFailed {
+                source,
+                signal: *sig,
+            }
+        })?;
+    }
+
+    if let Some(vars) = &opts.environment {
+        for (key, _) in env::vars_os() {
+            env::remove_var(key);
+        }
+        for (key, value) in vars {
+            env::set_var(key, value);
+        }
+    }
     for (key, value) in &opts.env {
         env::set_var(key, value);
     }
@@ -337,6 +933,25 @@ fn setup_subprocess(opts: &ProcessOptions) -> Result<(), SubprocessSetupError> {
         })?;
     }
 
+    // nix's SigSet can't hold real-time signals, so masks are built with libc
+    let result = match &opts.mask {
+        Some(signals) => unsafe {
+            let mut set: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            for sig in signals {
+                libc::sigaddset(&mut set, sig.number());
+            }
+            Errno::result(libc::pthread_sigmask(
+                libc::SIG_SETMASK,
+                &set,
+                ptr::null_mut(),
+            ))
+            .map(drop)
+        },
+        None => mask.thread_set_mask(),
+    };
+    result.map_err(|source| SubprocessSetupError::SetSignalMaskFailed { source })?;
+
     Ok(())
 }
 
@@ -370,10 +985,12 @@ fn exec_subprocess<S: AsRef<str>>(exe: &str, args: &[S]) -> Result<(), Subproces
 #[cfg(test)]
 mod test {
     use crate::{
-        jobs::spawn::{OpenMode, ProcessOptions, SubprocessSetupError},
+        env::signals::Signal,
+        jobs::spawn::{OpenMode, ProcessOptions, SpawnError, SubprocessSetupError},
         test_util::forks,
     };
     use nix::{
+        errno::Errno,
         sys::wait::{waitpid, WaitStatus},
         unistd::Pid,
     };
@@ -426,6 +1043,8 @@ mod test {
 
     #[test]
     fn setup_process_dup_close() {
+        forks!();
+
         let fd = super::open("test/data/hello.txt", OpenMode::Read).unwrap();
         let newfd = 11;
         super::dup(fd, newfd).unwrap();
@@ -530,6 +1149,83 @@ mod test {
         assert_eq!(content, "olleh");
     }
 
+    #[test]
+    fn signal_actions() {
+        forks!();
+
+        let pid = ProcessOptions::new()
+            .ignore_signal(Signal::SIGTERM)
+            .spawn("/bin/sh", &["sh", "-c", "kill -TERM $$; exit 3"])
+            .expect("failed to spawn sh");
+        match waitpid(pid, None).expect("failed to wait for sh") {
+            WaitStatus::Exited(_, 3) => (),
+            status => panic!("SIGTERM should have been ignored, got {:?}", status),
+        }
+
+        // blocked signals stay pending until exec, and sh doesn't unblock them
+        let pid = ProcessOptions::new()
+            .signal_mask(vec![Signal::SIGUSR1])
+            .spawn("/bin/sh", &["sh", "-c", "kill -USR1 $$; exit 4"])
+            .expect("failed to spawn sh");
+        match waitpid(pid, None).expect("failed to wait for sh") {
+            WaitStatus::Exited(_, 4) => (),
+            status => panic!("SIGUSR1 should have been blocked, got {:?}", status),
+        }
+    }
+
+    #[test]
+    fn setup_errors() {
+        forks!();
+
+        match ProcessOptions::new()
+            .read(0, "test/data/DOES NOT EXIST")
+            .spawn("/bin/true", &["true"])
+            .expect_err("opening a missing file should fail")
+        {
+            SpawnError::SetupFailed {
+                source: SubprocessSetupError::OpenAndDupFailed { source, fd: 0, .. },
+            } => match *source {
+                SubprocessSetupError::OpenFailed {
+                    source: nix::Error::Sys(Errno::ENOENT),
+                    file,
+                    ..
+                } => assert_eq!(file, PathBuf::from("test/data/DOES NOT EXIST")),
+                e => panic!("expected OpenFailed, got {:?}", e),
+            },
+            e => panic!("expected OpenAndDupFailed, got {:?}", e),
+        }
+
+        // the error pipe is moved out of the way of redirects
+        match ProcessOptions::new()
+            .redirect(1, 10)
+            .redirect(2, 11)
+            .spawn("/rush/not/a/real/command", &["command", "arg"])
+            .expect_err("executing a missing command should fail")
+        {
+            SpawnError::SetupFailed { source } => {
+                assert_eq!(source.exit_code(), 127);
+                assert_eq!(
+                    source,
+                    SubprocessSetupError::ExecFailed {
+                        args: vec!["command".to_string(), "arg".to_string()],
+                        executable: "/rush/not/a/real/command".to_string(),
+                        source: nix::Error::Sys(Errno::ENOENT),
+                    }
+                );
+            }
+            e => panic!("expected ExecFailed, got {:?}", e),
+        }
+
+        let e = SubprocessSetupError::SetSignalFailed {
+            source: nix::Error::InvalidPath,
+            signal: Signal::SIGTERM,
+        };
+        let mut buf = Vec::new();
+        e.encode(&mut buf);
+        assert_eq!(SubprocessSetupError::decode(&mut buf.as_slice()), Some(e));
+        assert_eq!(SubprocessSetupError::decode(&mut &buf[..4]), None);
+    }
+
     #[test]
     fn modify_environment() {
         forks!();
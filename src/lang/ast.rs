@@ -1,4 +1,6 @@
+use crate::lang::span::Span;
 use crate::lang::word::Word;
+use crate::lang::{parser, ParseError};
 use std::os::unix::io::RawFd;
 use std::vec::Vec;
 
@@ -9,6 +11,9 @@ pub enum Command {
     FileRedirect(Box<FileRedirect>),
     ConditionalPair(Box<ConditionalPair>),
 
+    /// A `[[ expression ]]` test
+    Conditional(Box<Conditional>),
+
     Group(Box<CommandGroup>),
     BraceGroup(Box<CommandGroup>),
     SubShell(Box<CommandGroup>),
@@ -19,7 +24,10 @@ pub enum Command {
     For(Box<For>),
     Until(Box<Until>),
     Function(Box<Function>),
-    Comment(String),
+    Comment(Box<Comment>),
+
+    /// A command followed by `&`, run without waiting for it to finish
+    Background(Box<Background>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -50,12 +58,34 @@ pub enum IoOperation {
 
 #[derive(Debug, Clone)]
 pub struct SimpleCommand {
+    /// Assignments before the command name, they're set in the shell when there's no command
+    pub assignments: Vec<Assignment>,
     pub arguments: Vec<Word>,
+    pub span: Span,
+}
+
+/// A `name=value`, `name[subscript]=value` or `name=(value ...)` assignment, with `+=` to append
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub name: String,
+    pub subscript: Option<Word>,
+    pub append: bool,
+    pub value: AssignmentValue,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum AssignmentValue {
+    Word(Word),
+
+    /// The elements of an array, each with an optional `[subscript]=`
+    Array(Vec<(Option<Word>, Word)>),
 }
 
 #[derive(Debug, Clone)]
 pub struct CommandGroup {
     pub commands: Vec<Command>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -63,36 +93,42 @@ pub struct If {
     pub condition: Command,
     pub success: Command,
     pub failure: Command,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct While {
     pub condition: Command,
     pub body: Command,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Until {
     pub condition: Command,
     pub body: Command,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct For {
     pub condition: Command,
     pub body: Command,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Function {
     pub name: Word,
     pub body: Command,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Case {
     pub input: Word,
     pub cases: Vec<(Word, Command)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -100,6 +136,7 @@ pub struct Pipeline {
     pub bang: bool,
     pub from: Command,
     pub to: Command,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -107,6 +144,45 @@ pub struct ConditionalPair {
     pub left: Command,
     pub operator: ConditionOperator,
     pub right: Command,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Conditional {
+    pub expression: ConditionalExpression,
+    pub span: Span,
+}
+
+/// An expression inside `[[ ]]`, words in it are expanded but not split
+#[derive(Debug, Clone)]
+pub enum ConditionalExpression {
+    /// True if the word isn't empty
+    Word(Word),
+
+    /// A unary operator like `-f` and its operand
+    Unary(String, Word),
+
+    /// Two operands and the operator between them, like `==` or `-lt`
+    Binary(Word, String, Word),
+
+    Not(Box<ConditionalExpression>),
+    And(Box<ConditionalExpression>, Box<ConditionalExpression>),
+    Or(Box<ConditionalExpression>, Box<ConditionalExpression>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Background {
+    pub command: Command,
+
+    /// The command's source code, used to describe the job. Empty until spans are resolved
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -114,12 +190,14 @@ pub struct RedirectDestination {
     pub operation: IoOperation,
     pub fd: Option<RawFd>,
     pub file: Word,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct FileRedirect {
     pub left: Command,
     pub redirects: Vec<RedirectDestination>,
+    pub span: Span,
 }
 
 impl RedirectDestination {
@@ -132,24 +210,23 @@ impl RedirectDestination {
             operation: operation,
             fd: fd,
             file: file.unwrap_or(Word::new()),
+            span: Span::default(),
         }
     }
 }
 
-impl<T> From<T> for Command
-where
-    T: AsRef<str>,
-{
-    fn from(s: T) -> Command {
-        use crate::lang::parser::commandline;
-        use nom::types::CompleteStr;
-        commandline(CompleteStr(s.as_ref())).unwrap().1
+impl Command {
+    /// Parse a complete script
+    pub fn parse<T: AsRef<str>>(s: T) -> Result<Command, ParseError> {
+        parser::parse(s)
     }
-}
 
-impl Command {
     pub fn simple(args: Vec<Word>) -> Command {
-        Command::SimpleCommand(SimpleCommand { arguments: args })
+        Command::SimpleCommand(SimpleCommand {
+            assignments: Vec::new(),
+            arguments: args,
+            span: Span::default(),
+        })
     }
 
     pub fn pipeline(bang: bool, source: Command, dest: Command) -> Command {
@@ -157,6 +234,7 @@ impl Command {
             bang: bang,
             from: source,
             to: dest,
+            span: Span::default(),
         }))
     }
 
@@ -165,6 +243,7 @@ impl Command {
             left: left,
             operator: infix,
             right: right,
+            span: Span::default(),
         }))
     }
 
@@ -172,10 +251,168 @@ impl Command {
         Command::FileRedirect(Box::new(FileRedirect {
             left: source,
             redirects: redir,
+            span: Span::default(),
+        }))
+    }
+
+    pub fn background(command: Command) -> Command {
+        let span = command.span();
+        Command::Background(Box::new(Background {
+            command,
+            text: String::new(),
+            span,
         }))
     }
 
     pub fn group(source: Vec<Command>) -> Command {
-        Command::Group(Box::new(CommandGroup { commands: source }))
+        Command::Group(Box::new(CommandGroup {
+            commands: source,
+            span: Span::default(),
+        }))
+    }
+
+    /// Get the range of source code this command was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            Command::SimpleCommand(c) => c.span,
+            Command::Pipeline(c) => c.span,
+            Command::FileRedirect(c) => c.span,
+            Command::ConditionalPair(c) => c.span,
+            Command::Conditional(c) => c.span,
+            Command::Group(c) | Command::BraceGroup(c) | Command::SubShell(c) => c.span,
+            Command::If(c) => c.span,
+            Command::Case(c) => c.span,
+            Command::While(c) => c.span,
+            Command::For(c) => c.span,
+            Command::Until(c) => c.span,
+            Command::Function(c) => c.span,
+            Command::Comment(c) => c.span,
+            Command::Background(c) => c.span,
+        }
+    }
+
+    pub fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Command::SimpleCommand(c) => &mut c.span,
+            Command::Pipeline(c) => &mut c.span,
+            Command::FileRedirect(c) => &mut c.span,
+            Command::ConditionalPair(c) => &mut c.span,
+            Command::Conditional(c) => &mut c.span,
+            Command::Group(c) | Command::BraceGroup(c) | Command::SubShell(c) => &mut c.span,
+            Command::If(c) => &mut c.span,
+            Command::Case(c) => &mut c.span,
+            Command::While(c) => &mut c.span,
+            Command::For(c) => &mut c.span,
+            Command::Until(c) => &mut c.span,
+            Command::Function(c) => &mut c.span,
+            Command::Comment(c) => &mut c.span,
+            Command::Background(c) => &mut c.span,
+        }
+    }
+
+    /// Set the range of source code this command was parsed from
+    pub fn with_span(mut self, span: Span) -> Command {
+        *self.span_mut() = span;
+        self
+    }
+
+    /// Resolve the spans of this command and every node below it, see `Span::resolve`
+    pub fn resolve_spans(&mut self, source: &str) {
+        self.span_mut().resolve(source);
+        match self {
+            Command::SimpleCommand(c) => {
+                for assignment in &mut c.assignments {
+                    assignment.span.resolve(source);
+                    match &mut assignment.value {
+                        AssignmentValue::Word(word) => word.span_mut().resolve(source),
+                        AssignmentValue::Array(elements) => {
+                            for (_, word) in elements {
+                                word.span_mut().resolve(source);
+                            }
+                        }
+                    }
+                }
+                for arg in &mut c.arguments {
+                    arg.span_mut().resolve(source);
+                }
+            }
+            Command::Pipeline(c) => {
+                c.from.resolve_spans(source);
+                c.to.resolve_spans(source);
+            }
+            Command::FileRedirect(c) => {
+                c.left.resolve_spans(source);
+                for redirect in &mut c.redirects {
+                    redirect.span.resolve(source);
+                    redirect.file.span_mut().resolve(source);
+                }
+            }
+            Command::ConditionalPair(c) => {
+                c.left.resolve_spans(source);
+                c.right.resolve_spans(source);
+            }
+            Command::Conditional(c) => c.expression.resolve_spans(source),
+            Command::Group(c) | Command::BraceGroup(c) | Command::SubShell(c) => {
+                for command in &mut c.commands {
+                    command.resolve_spans(source);
+                }
+            }
+            Command::If(c) => {
+                c.condition.resolve_spans(source);
+                c.success.resolve_spans(source);
+                c.failure.resolve_spans(source);
+            }
+            Command::Case(c) => {
+                c.input.span_mut().resolve(source);
+                for (pattern, command) in &mut c.cases {
+                    pattern.span_mut().resolve(source);
+                    command.resolve_spans(source);
+                }
+            }
+            Command::While(c) => {
+                c.condition.resolve_spans(source);
+                c.body.resolve_spans(source);
+            }
+            Command::For(c) => {
+                c.condition.resolve_spans(source);
+                c.body.resolve_spans(source);
+            }
+            Command::Until(c) => {
+                c.condition.resolve_spans(source);
+                c.body.resolve_spans(source);
+            }
+            Command::Function(c) => {
+                c.name.span_mut().resolve(source);
+                c.body.resolve_spans(source);
+            }
+            Command::Comment(_) => (),
+            Command::Background(c) => {
+                c.command.resolve_spans(source);
+                c.text = source
+                    .get(c.span.start.offset..c.span.end.offset)
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string();
+            }
+        }
+    }
+}
+
+impl ConditionalExpression {
+    fn resolve_spans(&mut self, source: &str) {
+        match self {
+            ConditionalExpression::Word(word) | ConditionalExpression::Unary(_, word) => {
+                word.span_mut().resolve(source)
+            }
+            ConditionalExpression::Binary(left, _, right) => {
+                left.span_mut().resolve(source);
+                right.span_mut().resolve(source);
+            }
+            ConditionalExpression::Not(expression) => expression.resolve_spans(source),
+            ConditionalExpression::And(left, right) | ConditionalExpression::Or(left, right) => {
+                left.resolve_spans(source);
+                right.resolve_spans(source);
+            }
+        }
     }
 }
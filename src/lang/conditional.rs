@@ -0,0 +1,134 @@
+//! Evaluation of `[[ ]]` expressions
+use crate::builtins::test::{binary, unary};
+use crate::lang::ast::ConditionalExpression;
+use crate::lang::{pattern, ErrorKind, ExecutionContext, Result};
+use regex::Regex;
+
+/// The array holding the text matched by the last `=~` followed by its capture groups
+const REMATCH: &str = "BASH_REMATCH";
+
+impl ConditionalExpression {
+    /// Check if the expression is true, words are expanded as they're needed, so `&&` and `||` short-circuit
+    pub fn evaluate(&self, ec: &mut ExecutionContext) -> Result<bool> {
+        match self {
+            ConditionalExpression::Word(word) => Ok(!word.compile(ec.variables_mut())?.is_empty()),
+            ConditionalExpression::Unary(op, word) => {
+                let operand = word.compile(ec.variables_mut())?;
+                unary(op, &operand).map_err(|e| ErrorKind::BadCondition(e).into())
+            }
+            ConditionalExpression::Binary(left, op, right) => {
+                let left = left.compile(ec.variables_mut())?;
+                match op.as_str() {
+                    "==" | "=" => Ok(pattern::matches(
+                        &right.compile_pattern(ec.variables_mut())?,
+                        &left,
+                    )),
+                    "!=" => Ok(!pattern::matches(
+                        &right.compile_pattern(ec.variables_mut())?,
+                        &left,
+                    )),
+                    "=~" => {
+                        let regex = right.compile_regex(ec.variables_mut())?;
+                        rematch(ec, &regex, &left)
+                    }
+                    "<" => Ok(left < right.compile(ec.variables_mut())?),
+                    ">" => Ok(left > right.compile(ec.variables_mut())?),
+                    _ => {
+                        let right = right.compile(ec.variables_mut())?;
+                        binary(&left, op, &right).map_err(|e| ErrorKind::BadCondition(e).into())
+                    }
+                }
+            }
+            ConditionalExpression::Not(expression) => expression.evaluate(ec).map(|b| !b),
+            ConditionalExpression::And(left, right) => {
+                Ok(left.evaluate(ec)? && right.evaluate(ec)?)
+            }
+            ConditionalExpression::Or(left, right) => Ok(left.evaluate(ec)? || right.evaluate(ec)?),
+        }
+    }
+}
+
+/// Match `text` against `regex`, and put the match and its capture groups in `BASH_REMATCH`
+fn rematch(ec: &mut ExecutionContext, regex: &str, text: &str) -> Result<bool> {
+    let regex = Regex::new(regex)
+        .map_err(|_| ErrorKind::BadCondition(format!("{}: invalid regular expression", regex)))?;
+
+    let captures = match regex.captures(text) {
+        Some(captures) => captures,
+        None => {
+            ec.variables_mut().remove(&REMATCH.into());
+            return Ok(false);
+        }
+    };
+    let groups = captures
+        .iter()
+        .map(|group| group.map_or("", |m| m.as_str()));
+    ec.variables_mut().define_array(REMATCH, groups);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::{ast::Command, ExecutionContext, JobManager};
+    use crate::test_util::forks;
+    use std::ffi::OsString;
+
+    fn run(ec: &mut ExecutionContext, script: &str) -> i32 {
+        JobManager::new()
+            .run(ec, Command::parse(script).expect("failed to parse"))
+            .expect("failed to run script")
+            .exit_code
+    }
+
+    #[test]
+    fn patterns() {
+        let mut ec = ExecutionContext::new();
+        ec.variables_mut().define("x", "a b*");
+        ec.variables_mut().define("glob", "a*");
+
+        assert_eq!(run(&mut ec, "[[ $x == a* ]]"), 0);
+        assert_eq!(run(&mut ec, "[[ $x == $glob ]]"), 0);
+        assert_eq!(run(&mut ec, "[[ $x == \"$glob\" ]]"), 1);
+        assert_eq!(run(&mut ec, "[[ $x = 'a b*' ]]"), 0);
+        assert_eq!(run(&mut ec, "[[ $x != *b ]]"), 0);
+        assert_eq!(run(&mut ec, "[[ abc < abd && ! b > c ]]"), 0);
+    }
+
+    #[test]
+    fn operators() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        assert_eq!(run(&mut ec, "[[ -d / && ( -z x || 2 -gt 1 ) ]]"), 0);
+        assert_eq!(run(&mut ec, "[[ -n $RUSH_NOT_SET ]]"), 1);
+        assert_eq!(run(&mut ec, "[[ ! -f /rush/not/a/file ]]"), 0);
+        assert_eq!(run(&mut ec, "[[ x -eq 1 ]] 2>/dev/null"), 2);
+
+        // the right side isn't expanded if the left side decides the result
+        ec.variables_mut().options_mut().nounset = true;
+        assert_eq!(run(&mut ec, "[[ a || $RUSH_NOT_SET ]]"), 0);
+    }
+
+    #[test]
+    fn regex() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let value = |ec: &ExecutionContext, name: &str| ec.variables().value(&OsString::from(name));
+
+        assert_eq!(run(&mut ec, "[[ foo-12 =~ ^([a-z]+)-([0-9]+)(x)?$ ]]"), 0);
+        assert_eq!(value(&ec, "BASH_REMATCH"), "foo-12");
+        assert_eq!(
+            ec.variables().elements(&"BASH_REMATCH".into()),
+            vec!["foo-12", "foo", "12", ""]
+        );
+
+        // quoted text only matches itself
+        assert_eq!(run(&mut ec, "[[ axb =~ \"a.b\" ]]"), 1);
+        assert!(!ec.variables().is_set(&"BASH_REMATCH".into()));
+        assert_eq!(run(&mut ec, "[[ a.b =~ x|\"a.\"b ]]"), 0);
+
+        ec.variables_mut().define("re", "(");
+        assert_eq!(run(&mut ec, "[[ a =~ $re ]] 2>/dev/null"), 2);
+    }
+}
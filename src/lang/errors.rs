@@ -1,4 +1,6 @@
+use crate::env::variables::AssignError;
 use crate::lang::exec;
+use crate::lang::span::Position;
 use failure;
 use std::os::unix::io::RawFd;
 use std::{fmt, result};
@@ -6,9 +8,18 @@ use std::{fmt, result};
 pub type Result<T> = result::Result<T, Error>;
 #[derive(Debug)]
 pub struct Error {
+    location: Option<Box<Location>>,
     inner: failure::Context<ErrorKind>,
 }
 
+/// The place in a script where an error occurred
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Location {
+    /// Name of the script, or the shell's name for interactive input
+    pub source: String,
+    pub position: Position,
+}
+
 #[derive(Eq, PartialEq, Debug, Fail)]
 pub enum ErrorKind {
     #[fail(display = "failed to evaluate expression")]
@@ -35,6 +46,9 @@ pub enum ErrorKind {
     #[fail(display = "failed to execute child process")]
     ExecFailed,
 
+    #[fail(display = "{}", _0)]
+    SetupFailed(String),
+
     #[fail(display = "failed to create a pipeline")]
     PipelineCreationFailed,
 
@@ -52,12 +66,85 @@ pub enum ErrorKind {
 
     #[fail(display = "failed to wait for signal")]
     SigWaitFailed,
+
+    #[fail(display = "{}: unbound variable", _0)]
+    UnboundVariable(String),
+
+    /// A variable couldn't be assigned or unset, like a readonly variable
+    #[fail(display = "{}", _0)]
+    Assign(AssignError),
+
+    #[fail(display = "{}", _0)]
+    SyntaxError(ParseError),
+
+    #[fail(display = "bad file descriptor {:?}", _0)]
+    BadFileDescriptor(String),
+
+    /// An operand of `[[ ]]` that doesn't fit its operator, like an invalid number or regex
+    #[fail(display = "{}", _0)]
+    BadCondition(String),
+
+    #[fail(display = "here-documents are not supported")]
+    UnsupportedRedirect,
+
+    /// Not a failure, `exit` unwinds the shell with this, so the `EXIT` trap runs before the shell exits with the status
+    #[fail(display = "exit {}", _0)]
+    Exit(i32),
+
+    /// Not a failure, `return` unwinds to the function or sourced script being run with this
+    #[fail(display = "return {}", _0)]
+    Return(i32),
+}
+
+/// A syntax error, with enough context to point at the offending token
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+
+    /// The token that couldn't be parsed, empty at end-of-input
+    pub token: String,
+
+    /// The full line the error occurred on
+    pub input: String,
+
+    /// 1-based line number
+    pub line: usize,
+
+    /// 1-based column, in characters
+    pub column: usize,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Fail)]
+pub enum ParseErrorKind {
+    #[fail(display = "unexpected token")]
+    UnexpectedToken,
+
+    #[fail(display = "unexpected end of input")]
+    UnexpectedEof,
+
+    #[fail(display = "unterminated quote")]
+    UnterminatedQuote,
 }
 
 impl Error {
     pub fn kind(&self) -> &ErrorKind {
         self.inner.get_context()
     }
+
+    pub fn location(&self) -> Option<&Location> {
+        self.location.as_deref()
+    }
+
+    /// Set where this error occurred, if it doesn't have a location yet.
+    ///
+    /// Errors are located by the innermost command, so a failure inside a function points to the function body, not the call.
+    /// Locations of commands that weren't parsed from a script are ignored.
+    pub fn at(mut self, location: Location) -> Self {
+        if self.location.is_none() && location.position.is_resolved() {
+            self.location = Some(Box::new(location));
+        }
+        self
+    }
 }
 
 impl failure::Fail for Error {
@@ -72,13 +159,23 @@ impl failure::Fail for Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}: ", location)?;
+        }
         fmt::Display::fmt(&self.inner, f)
     }
 }
 
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.source, self.position)
+    }
+}
+
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Error {
         Error {
+            location: None,
             inner: failure::Context::new(kind),
         }
     }
@@ -86,6 +183,125 @@ impl From<ErrorKind> for Error {
 
 impl From<failure::Context<ErrorKind>> for Error {
     fn from(inner: failure::Context<ErrorKind>) -> Error {
-        Error { inner: inner }
+        Error {
+            location: None,
+            inner: inner,
+        }
+    }
+}
+
+impl ParseError {
+    /// Create an error for `source`, pointing to the start of `rest`.
+    ///
+    /// `rest` must be a suffix of `source`, usually it's the input a parser failed to consume.
+    pub fn new(source: &str, rest: &str) -> ParseError {
+        let rest = rest.trim_start_matches([' ', '\t']);
+        let offset = source.len() - rest.len();
+        let line_start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[offset..]
+            .find('\n')
+            .map(|i| i + offset)
+            .unwrap_or(source.len());
+
+        let (kind, token) = match rest.chars().next() {
+            None => (ParseErrorKind::UnexpectedEof, String::new()),
+            // a trailing backslash escapes the newline that hasn't been read yet
+            Some('\\') if rest.len() == 1 => (ParseErrorKind::UnexpectedEof, String::new()),
+            Some(c) if c == '"' || c == '\'' => (ParseErrorKind::UnterminatedQuote, c.to_string()),
+            Some(_) => (ParseErrorKind::UnexpectedToken, token_at(rest).to_string()),
+        };
+
+        ParseError {
+            kind,
+            token,
+            input: source[line_start..line_end].to_string(),
+            line: source[..offset].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+        }
+    }
+
+    /// Display the error as being in the script `name`, like errors found while running it
+    pub fn in_script<'a>(&'a self, name: &'a str) -> InScript<'a> {
+        InScript { error: self, name }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, script: Option<&str>) -> fmt::Result {
+        match script {
+            Some(script) => write!(f, "{}:{}:{}: syntax error", script, self.line, self.column)?,
+            None => write!(f, "syntax error at {}:{}", self.line, self.column)?,
+        }
+        match self.kind {
+            ParseErrorKind::UnexpectedToken => writeln!(f, ": unexpected {:?}", self.token)?,
+            _ => writeln!(f, ": {}", self.kind)?,
+        }
+
+        let prefix = format!("{} |", " ".repeat(self.line.to_string().len()));
+        writeln!(f, "{}", prefix)?;
+        writeln!(f, "{} |  {}", self.line, self.input)?;
+        write!(
+            f,
+            "{}  {}{}",
+            prefix,
+            " ".repeat(self.column - 1),
+            "^".repeat(self.token.chars().count().max(1))
+        )
+    }
+
+    /// Whether the input ended before a command was complete, so reading more input could make it parse
+    pub fn is_incomplete(&self) -> bool {
+        match self.kind {
+            ParseErrorKind::UnexpectedEof | ParseErrorKind::UnterminatedQuote => true,
+            ParseErrorKind::UnexpectedToken => false,
+        }
+    }
+}
+
+/// Get the first shell token at the start of a string, either an operator or a run of non-blank characters
+fn token_at(s: &str) -> &str {
+    const OPERATORS: &[&str] = &[
+        "<<-", "&&", "||", ";;", ">>", "<<", ">|", "<&", ">&", "<>", "|", "&", ";", "<", ">", "(",
+        ")", "{", "}",
+    ];
+
+    if let Some(op) = OPERATORS.iter().find(|op| s.starts_with(*op)) {
+        return op;
+    }
+
+    let end = s
+        .find(|c: char| c.is_whitespace() || "|&;<>(){}".contains(c))
+        .unwrap_or(s.len());
+    match end {
+        0 => s.chars().next().map(|c| &s[..c.len_utf8()]).unwrap_or(s),
+        _ => &s[..end],
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, None)
+    }
+}
+
+/// A syntax error displayed with the name of the script it's in, see `ParseError::in_script`
+pub struct InScript<'a> {
+    error: &'a ParseError,
+    name: &'a str,
+}
+
+impl fmt::Display for InScript<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.write(f, Some(self.name))
+    }
+}
+
+impl From<AssignError> for Error {
+    fn from(e: AssignError) -> Error {
+        Error::from(ErrorKind::Assign(e))
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::from(ErrorKind::SyntaxError(e))
     }
 }
@@ -1,33 +1,74 @@
 use crate::{
-    env::{functions::Functions, variables::Variables},
-    jobs::spawn::ProcessOptions,
+    builtins,
+    env::{
+        fds::{self, FdTable},
+        functions::Functions,
+        signals::Signal,
+        traps::{self, Condition},
+        variables::{Attributes, Variables},
+    },
+    jobs::spawn::{ProcessOptions, SpawnError, SubprocessSetupError},
     lang::{
-        ast::{Command, ConditionOperator},
-        Error, ErrorKind, Result,
+        ast::{
+            Assignment, AssignmentValue, Command, ConditionOperator, IoOperation,
+            RedirectDestination, SimpleCommand,
+        },
+        span::Span,
+        Error, ErrorKind, Location, Result,
     },
 };
 use failure::ResultExt;
 use nix::{
+    errno::Errno,
+    fcntl::OFlag,
     libc,
-    sys::signal,
-    sys::wait::{wait, WaitStatus},
+    poll::{poll, EventFlags, PollFd},
     unistd,
 };
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    cell::{Ref, RefCell, RefMut},
+    collections::{btree_map, BTreeMap, BTreeSet},
     env,
-    ffi::{OsStr, OsString},
-    path::PathBuf,
+    ffi::{CString, OsStr, OsString},
+    fmt, fs,
+    io::{self, Write},
+    mem,
+    os::unix::{ffi::OsStrExt, io::RawFd},
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
 };
 
 #[derive(Debug, Copy, Clone, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Jid(u32);
 
+/// What running a command name does, see `ExecutionContext::resolve`
+#[derive(Clone)]
+pub enum Resolution {
+    Function(Command),
+    Builtin(builtins::Builtin),
+
+    /// An executable file, names containing a `/` are always paths to one
+    File(PathBuf),
+}
+
 #[derive(Debug, Clone)]
 pub struct ExecutionContext {
     pub cwd: PathBuf,
     vars: Variables,
     funcs: Functions,
+
+    /// Name of the script being run, used to report the location of errors
+    script_name: String,
+
+    /// File descriptors opened in the shell process by `exec`, shared with the contexts of groups run in the same process
+    fds: Rc<RefCell<FdTable>>,
+
+    /// Commands found by searching `PATH`, and where they were found
+    hashed: BTreeMap<OsString, PathBuf>,
+
+    /// The value of `PATH` the hashed commands were found with, the table is cleared when it changes
+    hashed_path: OsString,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -35,7 +76,35 @@ pub struct ExitStatus {
     pub pid: unistd::Pid,
     pub exit_code: i32,
     pub core_dumped: bool,
-    pub signal: Option<signal::Signal>,
+    pub signal: Option<Signal>,
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.signal {
+            Some(sig) => {
+                write!(f, "{}: {}", sig.description(), sig)?;
+                if self.core_dumped {
+                    write!(f, " (core dumped)")?;
+                }
+                Ok(())
+            }
+            None if self.exit_code == 0 => write!(f, "Done"),
+            None => write!(f, "Exit {}", self.exit_code),
+        }
+    }
+}
+
+/// A job started in the background with `&`
+#[derive(Debug, Clone)]
+pub struct BackgroundJob {
+    /// Number used to refer to the job as `%n`
+    pub number: usize,
+    pub jid: Jid,
+    pub pid: unistd::Pid,
+
+    /// The command's source code
+    pub command: String,
 }
 
 pub enum JobStatus {
@@ -47,6 +116,24 @@ pub struct JobManager {
     next_jid: u32,
     running_jobs: BTreeMap<libc::pid_t, Jid>,
     completed_jobs: BTreeMap<Jid, ExitStatus>,
+
+    /// Set while a trap's command is running, so traps don't trigger each other
+    in_trap: bool,
+
+    /// Set in a subshell running a single simple command, the command replaces the subshell instead of running as its child
+    exec_in_place: bool,
+
+    /// Jobs started with `&` that haven't been waited for, oldest first
+    background_jobs: Vec<BackgroundJob>,
+
+    /// Number of functions and sourced scripts being run, `return` can only be used inside one
+    call_depth: usize,
+
+    /// Set by `exec` without a command, so its redirects aren't undone when it finishes
+    keep_redirects: bool,
+
+    /// Set while the left side of `&&` or `||` runs, its status is tested so failures in it don't trigger ERR
+    in_condition: bool,
 }
 
 impl Default for JobManager {
@@ -55,6 +142,12 @@ impl Default for JobManager {
             next_jid: 0,
             running_jobs: BTreeMap::new(),
             completed_jobs: BTreeMap::new(),
+            in_trap: false,
+            exec_in_place: false,
+            background_jobs: Vec::new(),
+            call_depth: 0,
+            keep_redirects: false,
+            in_condition: false,
         }
     }
 }
@@ -67,7 +160,7 @@ impl JobManager {
     pub fn run(&mut self, ec: &mut ExecutionContext, command: Command) -> Result<ExitStatus> {
         let jids = self.spawn_procs_from_ast(&ProcessOptions::default(), ec, &command)?;
         self.await_all(&jids)?;
-        Ok(jids
+        let status = jids
             .last()
             .map(|id| *self.completed_jobs.get(id).unwrap())
             .unwrap_or(ExitStatus {
@@ -75,44 +168,26 @@ impl JobManager {
                 core_dumped: false,
                 pid: unistd::getpid(),
                 signal: None,
-            }))
+            });
+        ec.variables_mut().set_last_status(status.exit_code);
+        Ok(status)
+    }
+
+    /// Get the exit code of the last job in a list, the list should have already been awaited
+    fn exit_code(&self, jids: &[Jid]) -> i32 {
+        jids.last()
+            .map(|r| self.completed_jobs.get(r).unwrap().exit_code)
+            .unwrap_or(0)
     }
 
     fn next(&mut self) -> Result<(Jid, ExitStatus)> {
-        let mut status = None;
-        while status.is_none() {
-            match wait().context(ErrorKind::WaitFailed)? {
-                WaitStatus::Exited(pid, code) => {
-                    status = self.running_jobs.get(&pid.into()).map(|jid| {
-                        (
-                            *jid,
-                            ExitStatus {
-                                pid,
-                                exit_code: code,
-                                core_dumped: false,
-                                signal: None,
-                            },
-                        )
-                    });
-                }
-                WaitStatus::Signaled(pid, sig, core_dump) => {
-                    status = self.running_jobs.get(&pid.into()).map(|jid| {
-                        (
-                            *jid,
-                            ExitStatus {
-                                pid,
-                                exit_code: -1,
-                                core_dumped: core_dump,
-                                signal: Some(sig),
-                            },
-                        )
-                    });
+        loop {
+            if let Some(status) = wait_any(0).context(ErrorKind::WaitFailed)? {
+                if let Some(jid) = self.running_jobs.get(&status.pid.into()) {
+                    return Ok((*jid, status));
                 }
-                _ => (),
             }
         }
-
-        Ok(status.unwrap())
     }
 
     fn add_job(&mut self, pid: unistd::Pid) -> Jid {
@@ -122,6 +197,59 @@ impl JobManager {
         jid
     }
 
+    /// Add a job that ran inside the shell process, and has already completed
+    fn add_completed_job(&mut self, exit_code: i32) -> Jid {
+        let jid = Jid(self.next_jid);
+        self.completed_jobs.insert(
+            jid,
+            ExitStatus {
+                pid: unistd::getpid(),
+                exit_code,
+                core_dumped: false,
+                signal: None,
+            },
+        );
+        self.next_jid += 1;
+        jid
+    }
+
+    /// Run a builtin in the shell process, with `opts`' file descriptor operations applied for its duration
+    fn run_builtin(
+        &mut self,
+        builtin: builtins::Builtin,
+        opts: &ProcessOptions,
+        ec: &mut ExecutionContext,
+        args: &[String],
+        span: Span,
+    ) -> Result<Jid> {
+        let saved = match opts.redirect_in_place() {
+            Ok(saved) => saved,
+            Err(e) => return Ok(self.setup_failed(ec, &e, span)),
+        };
+        let result = builtin(self, ec, args);
+
+        // builtins write through rust's buffered stdout, make sure it ends up in the redirected file
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        if mem::take(&mut self.keep_redirects) {
+            saved.forget();
+            ec.fds_mut().record(opts.fd_ops());
+        } else {
+            saved.restore();
+        }
+
+        Ok(self.add_completed_job(result?))
+    }
+
+    /// Report a command that couldn't be set up, it fails like any other command and the script keeps going
+    fn setup_failed(&mut self, ec: &ExecutionContext, e: &SubprocessSetupError, span: Span) -> Jid {
+        eprintln!(
+            "{}",
+            Error::from(ErrorKind::SetupFailed(e.to_string())).at(ec.location(span))
+        );
+        self.add_completed_job(e.exit_code())
+    }
+
     // spawn 0 or more processes based on a shell-language abstract syntax tree in a given execution context
     fn spawn_procs_from_ast(
         &mut self,
@@ -131,45 +259,54 @@ impl JobManager {
     ) -> Result<Vec<Jid>> {
         match command {
             Command::SimpleCommand(cmd) => {
-                // TODO: make sure theres at least 1 argument
-                let argv0 = cmd.arguments[0]
-                    .compile(ec.variables_mut())
-                    .context(ErrorKind::ExecFailed)?;
-
-                if let Some(body) = ec.functions().value(&argv0) {
-                    self.spawn_procs_from_ast(opts, ec, &body)
-                } else {
-                    let executable = if argv0.starts_with("./") {
-                        argv0.clone()
-                    } else {
-                        ec.find_executable(&argv0)?.to_string_lossy().to_string()
-                    };
-
-                    let mut args = Vec::with_capacity(cmd.arguments.len());
-
-                    // The first argument is the command used to run the executable
-                    // Avoid compiling it again since that can have side effects (e.g. "./exe$(exe += 1))")
-                    args.push(argv0);
-                    for arg in cmd.arguments.iter().skip(1) {
-                        args.push(
-                            arg.compile(ec.variables_mut())
-                                .context(ErrorKind::ExecFailed)?,
-                        );
-                    }
+                // traps are parsed separately, their line numbers would be meaningless
+                if cmd.span.start.is_resolved() && !self.in_trap {
+                    ec.variables_mut()
+                        .define("LINENO", cmd.span.start.line.to_string());
+                }
+                self.run_trap(ec, Condition::Debug)?;
 
-                    let pid = opts
-                        .spawn(&executable, &args)
-                        .context(ErrorKind::ExecFailed)?;
+                self.spawn_simple_command(opts, ec, cmd)
+                    .map_err(|e| e.at(ec.location(cmd.span)))
+            }
+            Command::FileRedirect(redirect) => {
+                let mut redirect_opts = ProcessOptions::new();
+                for dest in &redirect.redirects {
+                    redirect_opts = self
+                        .add_redirect(redirect_opts, ec, dest)
+                        .map_err(|e| e.at(ec.location(dest.span)))?;
+                }
 
-                    Ok(vec![self.add_job(pid)])
+                match &redirect.left {
+                    // redirects are applied to the shell for everything else, so the files are only opened once
+                    Command::SimpleCommand(_) => self.spawn_procs_from_ast(
+                        &opts.clone().extend(&redirect_opts),
+                        ec,
+                        &redirect.left,
+                    ),
+                    left => {
+                        let saved = match redirect_opts.redirect_in_place() {
+                            Ok(saved) => saved,
+                            Err(e) => return Ok(vec![self.setup_failed(ec, &e, redirect.span)]),
+                        };
+                        let result = self
+                            .spawn_procs_from_ast(opts, ec, left)
+                            .and_then(|jids| self.await_all(&jids).map(|_| jids));
+                        saved.restore();
+                        result
+                    }
                 }
             }
             Command::Pipeline(pipe) => {
-                let (stdin, stdout) = unistd::pipe().context(ErrorKind::PipelineCreationFailed)?;
+                let (stdin, stdout) = fds::internal_pipe(OFlag::empty())
+                    .context(ErrorKind::PipelineCreationFailed)?;
                 let left_opts = opts.clone().redirect(stdout, 1).close(stdout).close(stdin);
                 let right_opts = opts.clone().redirect(stdin, 0).close(stdout).close(stdin);
 
-                let mut jids = self.spawn_procs_from_ast(&left_opts, ec, &pipe.from)?;
+                // only the last command runs in the shell, a builtin or group on the left would block
+                // on a full pipe before the command reading it was started
+                let pid = self.spawn_subshell(&left_opts, ec, &pipe.from)?;
+                let mut jids = vec![self.add_job(pid)];
                 jids.extend(self.spawn_procs_from_ast(&right_opts, ec, &pipe.to)?);
 
                 unistd::close(stdin).context(ErrorKind::ExecFailed)?;
@@ -179,46 +316,629 @@ impl JobManager {
             }
             Command::BraceGroup(group) => {
                 let mut subenv = ec.clone();
-                for cmd in &group.commands {
-                    let jids = self.spawn_procs_from_ast(opts, &mut subenv, cmd)?;
-                    self.await_all(&jids)?;
-                }
-                Ok(Vec::new())
-            }
-            Command::Group(group) => {
-                for cmd in &group.commands {
-                    let jids = self.spawn_procs_from_ast(opts, ec, cmd)?;
-                    self.await_all(&jids)?;
-                }
-                Ok(Vec::new())
+                self.run_group(opts, &mut subenv, &group.commands)
             }
+            Command::Group(group) => self.run_group(opts, ec, &group.commands),
             Command::ConditionalPair(cond) => {
-                let jobs_left = self.spawn_procs_from_ast(opts, ec, &cond.left)?;
-                self.await_all(&jobs_left)?;
-                let exit_code = jobs_left
-                    .last()
-                    .map(|r| self.completed_jobs.get(r).unwrap().exit_code)
-                    .unwrap_or(0);
+                let in_condition = mem::replace(&mut self.in_condition, true);
+                let jobs_left = self
+                    .spawn_procs_from_ast(opts, ec, &cond.left)
+                    .and_then(|jobs| self.await_all(&jobs).map(|_| jobs));
+                self.in_condition = in_condition;
+                let jobs_left = jobs_left?;
+
+                let exit_code = self.exit_code(&jobs_left);
+                ec.variables_mut().set_last_status(exit_code);
                 if (exit_code == 0 && cond.operator == ConditionOperator::AndIf)
                     || (exit_code != 0 && cond.operator == ConditionOperator::OrIf)
                 {
                     let jobs_right = self.spawn_procs_from_ast(opts, ec, &cond.right)?;
                     self.await_all(&jobs_right)?;
+
+                    // only the last command of the list triggers ERR, a group on the right triggers it itself
+                    let exit_code = self.exit_code(&jobs_right);
+                    if exit_code != 0 && !is_compound(&cond.right) {
+                        ec.variables_mut().set_last_status(exit_code);
+                        self.run_err_trap(ec)?;
+                    }
                     Ok(jobs_right)
                 } else {
                     Ok(jobs_left)
                 }
             }
+            Command::Conditional(cond) => {
+                if cond.span.start.is_resolved() && !self.in_trap {
+                    ec.variables_mut()
+                        .define("LINENO", cond.span.start.line.to_string());
+                }
+                self.run_trap(ec, Condition::Debug)?;
+
+                // operands that don't fit their operator are reported like `test` reports them, the script keeps going
+                let status = match cond.expression.evaluate(ec) {
+                    Ok(true) => 0,
+                    Ok(false) => 1,
+                    Err(e) => match e.kind() {
+                        ErrorKind::BadCondition(_) => {
+                            eprintln!("{}", e.at(ec.location(cond.span)));
+                            2
+                        }
+                        _ => return Err(e.at(ec.location(cond.span))),
+                    },
+                };
+                Ok(vec![self.add_completed_job(status)])
+            }
             Command::Function(func) => {
                 let str_name = func.name.compile(ec.variables_mut())?;
                 ec.functions_mut().insert(str_name, func.body.clone());
                 Ok(vec![])
             }
             Command::Comment(_s) => Ok(vec![]),
+            Command::Background(background) => {
+                // without job control there's no way to tell background jobs apart from the foreground in the terminal
+                let background_opts = opts
+                    .clone()
+                    .ignore_signal(Signal::SIGINT)
+                    .ignore_signal(Signal::SIGQUIT);
+                let pid = self.spawn_subshell(&background_opts, ec, &background.command)?;
+                let jid = self.add_job(pid);
+                let number = self.background_jobs.last().map_or(1, |job| job.number + 1);
+                self.background_jobs.push(BackgroundJob {
+                    number,
+                    jid,
+                    pid,
+                    command: background.text.clone(),
+                });
+                ec.variables_mut().set_last_background(pid.into());
+                Ok(vec![])
+            }
             _ => unimplemented!(),
         }
     }
 
+    fn spawn_simple_command(
+        &mut self,
+        opts: &ProcessOptions,
+        ec: &mut ExecutionContext,
+        cmd: &SimpleCommand,
+    ) -> Result<Vec<Jid>> {
+        let exec_in_place = mem::take(&mut self.exec_in_place);
+        let mut args = Vec::with_capacity(cmd.arguments.len());
+        for arg in &cmd.arguments {
+            args.extend(arg.compile_fields(ec.variables_mut())?);
+        }
+
+        // without a command, the assignments are made in the shell
+        if args.is_empty() {
+            let mut status = 0;
+            for assignment in &cmd.assignments {
+                if !Self::assign(ec, assignment)? {
+                    status = 1;
+                }
+            }
+            return Ok(vec![self.add_completed_job(status)]);
+        }
+
+        // otherwise they're exported for the command, and put back once it's started
+        let mut saved = Vec::with_capacity(cmd.assignments.len());
+        let mut result = Ok(true);
+        for assignment in &cmd.assignments {
+            let vars = ec.variables_mut();
+            let name = OsString::from(&assignment.name);
+            saved.push(vars.save(&name));
+            result = Self::assign(ec, assignment);
+            if !matches!(result, Ok(true)) {
+                break;
+            }
+
+            let vars = ec.variables_mut();
+            let name = vars.resolve(&name);
+            let attributes = vars.attributes(&name);
+            let _ = vars.set_attributes(
+                &name,
+                Attributes {
+                    exported: true,
+                    ..attributes
+                },
+            );
+        }
+        let result = match result {
+            Ok(true) => self.spawn_command(opts, ec, cmd, &args, exec_in_place),
+            Ok(false) => Ok(vec![self.add_completed_job(1)]),
+            Err(e) => Err(e),
+        };
+        for saved in saved.into_iter().rev() {
+            ec.variables_mut().restore(saved);
+        }
+        result
+    }
+
+    /// Expand an assignment and set the variable, returns false after printing the error if it couldn't be set.
+    ///
+    /// Errors expanding the value are returned, like they are for a command's arguments.
+    fn assign(ec: &mut ExecutionContext, assignment: &Assignment) -> Result<bool> {
+        let name = assignment.name.as_str();
+        let append = assignment.append;
+        let result = match (&assignment.subscript, &assignment.value) {
+            (Some(subscript), AssignmentValue::Word(value)) => {
+                let subscript = subscript.compile(ec.variables_mut())?;
+                let value = value.compile(ec.variables_mut())?;
+                ec.variables_mut()
+                    .set_element(name, &subscript, value, append)
+            }
+            (None, AssignmentValue::Word(value)) => {
+                let value = value.compile(ec.variables_mut())?;
+                if append {
+                    ec.variables_mut().append(name, value)
+                } else {
+                    ec.variables_mut().set(name, value)
+                }
+            }
+            (_, AssignmentValue::Array(elements)) => {
+                let mut values = Vec::with_capacity(elements.len());
+                for (subscript, value) in elements {
+                    match subscript {
+                        Some(subscript) => values.push((
+                            Some(subscript.compile(ec.variables_mut())?),
+                            value.compile(ec.variables_mut())?.into(),
+                        )),
+                        None => values.extend(
+                            value
+                                .compile_fields(ec.variables_mut())?
+                                .into_iter()
+                                .map(|value| (None, value.into())),
+                        ),
+                    }
+                }
+                ec.variables_mut().set_array(name, values, append)
+            }
+        };
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                eprintln!("{}", Error::from(e).at(ec.location(assignment.span)));
+                Ok(false)
+            }
+        }
+    }
+
+    /// Run a command once its arguments are expanded
+    fn spawn_command(
+        &mut self,
+        opts: &ProcessOptions,
+        ec: &mut ExecutionContext,
+        cmd: &SimpleCommand,
+        args: &[String],
+        exec_in_place: bool,
+    ) -> Result<Vec<Jid>> {
+        // `command name ...` runs `name`, without looking for a function
+        let mut args = args;
+        let mut skip_functions = false;
+        while let Some(command_args) = builtins::command::command_args(args) {
+            args = command_args;
+            skip_functions = true;
+        }
+
+        let executable = match ec.resolve(&args[0], skip_functions) {
+            Some(Resolution::Function(body)) => {
+                self.call_depth += 1;
+                let result = self.spawn_procs_from_ast(opts, ec, &body);
+                self.call_depth -= 1;
+                let jids = match result {
+                    Err(e) => match e.kind() {
+                        ErrorKind::Return(status) => vec![self.add_completed_job(*status)],
+                        _ => return Err(e),
+                    },
+                    Ok(jids) => jids,
+                };
+
+                // waiting for the function would stop it from running alongside the rest of a pipeline, so only wait when needed
+                if traps::is_trapped(Condition::Return) {
+                    self.await_all(&jids)?;
+                    let exit_code = self.exit_code(&jids);
+                    ec.variables_mut().set_last_status(exit_code);
+                    self.run_trap(ec, Condition::Return)?;
+                }
+                return Ok(jids);
+            }
+            Some(Resolution::Builtin(builtin)) => {
+                return Ok(vec![self.run_builtin(builtin, opts, ec, args, cmd.span)?]);
+            }
+            Some(Resolution::File(path)) => path.to_string_lossy().to_string(),
+            // a missing command is reported like a failed command, so the script keeps going
+            None => {
+                let e = Error::from(ErrorKind::MissingExecutable(args[0].clone()));
+                eprintln!("{}", e.at(ec.location(cmd.span)));
+                return Ok(vec![self.add_completed_job(127)]);
+            }
+        };
+
+        let child_opts = Self::child_options(opts, ec);
+        let e = if exec_in_place {
+            // exec only returns if it failed
+            child_opts.exec(&executable, args).unwrap_err()
+        } else {
+            match child_opts.spawn(&executable, args) {
+                Ok(pid) => return Ok(vec![self.add_job(pid)]),
+                Err(SpawnError::SetupFailed { source }) => source,
+                Err(e) => Err(e).context(ErrorKind::ExecFailed)?,
+            }
+        };
+
+        // the system can't run files without `#!`, so they're assumed to be shell scripts
+        if let SubprocessSetupError::ExecFailed {
+            source: nix::Error::Sys(Errno::ENOEXEC),
+            ..
+        } = e
+        {
+            if exec_in_place {
+                Self::exec_script(&executable, &args[1..]);
+            }
+            let pid = self.spawn_script(opts, ec, &executable, &args[1..])?;
+            return Ok(vec![self.add_job(pid)]);
+        }
+
+        let jid = self.setup_failed(ec, &e, cmd.span);
+        if exec_in_place {
+            process::exit(e.exit_code());
+        }
+        Ok(vec![jid])
+    }
+
+    /// Get the options for a child process, the shell's traps are reset to their default actions before `opts` is applied.
+    ///
+    /// The child's environment is made from `ec`'s exported variables, the shell's own environment isn't kept up to date.
+    fn child_options(opts: &ProcessOptions, ec: &ExecutionContext) -> ProcessOptions {
+        traps::child_defaults()
+            .into_iter()
+            .fold(ProcessOptions::new(), |child, sig| {
+                child.default_signal(sig)
+            })
+            .environment(ec.variables().environment())
+            .extend(opts)
+    }
+
+    /// Run the shell script at `path` in a new process, like `rush path args...`
+    fn spawn_script(
+        &mut self,
+        opts: &ProcessOptions,
+        ec: &ExecutionContext,
+        path: &str,
+        args: &[String],
+    ) -> Result<unistd::Pid> {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+
+        match Self::child_options(opts, ec)
+            .fork()
+            .context(ErrorKind::ExecFailed)?
+        {
+            Some(pid) => Ok(pid),
+            None => Self::exec_script(path, args),
+        }
+    }
+
+    /// Replace the current shell with a fresh one running the script at `path`.
+    ///
+    /// Nothing is inherited from the current shell except exported variables, and signals it ignores.
+    fn exec_script(path: &str, args: &[String]) -> ! {
+        traps::reset_subshell();
+
+        let data = match fs::read(path).map(String::from_utf8) {
+            Ok(Ok(data)) => data,
+            Ok(Err(_)) => {
+                eprintln!("rush: {}: cannot execute binary file", path);
+                process::exit(126);
+            }
+            Err(e) => {
+                eprintln!("rush: {}: {}", path, e);
+                process::exit(126);
+            }
+        };
+        let script = match Command::parse(&data) {
+            Ok(script) => script,
+            Err(e) => {
+                eprintln!("{}", e.in_script(path));
+                process::exit(2);
+            }
+        };
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+        ec.set_script_name(path);
+        ec.variables_mut().set_arg0(path);
+        ec.variables_mut().set_positional(args);
+
+        let result = jm.run(&mut ec, script).map(|status| status.exit_code);
+        jm.exit_shell(&mut ec, result)
+    }
+
+    /// Replace the shell with the command `args`, for `exec`.
+    ///
+    /// If the command can't be run the shell exits, the shell's signal actions may already have been reset for the command.
+    pub fn replace_shell(&mut self, ec: &mut ExecutionContext, args: &[String]) -> Result<i32> {
+        let executable = if args[0].contains('/') {
+            args[0].clone()
+        } else {
+            match ec.find_executable(&args[0]) {
+                Ok(path) => path.to_string_lossy().to_string(),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return Self::exec_failed(127);
+                }
+            }
+        };
+
+        // an interactive shell keeps running, so its signals aren't reset for a file that can't be run
+        let interactive = traps::is_interactive();
+        let e = if interactive && !is_executable(Path::new(&executable)) {
+            let errno = match Path::new(&executable).exists() {
+                true => Errno::EACCES,
+                false => Errno::ENOENT,
+            };
+            SubprocessSetupError::ExecFailed {
+                args: args.to_vec(),
+                executable,
+                source: nix::Error::Sys(errno),
+            }
+        } else {
+            let _ = io::stdout().flush();
+            let _ = io::stderr().flush();
+            let e = Self::child_options(&ProcessOptions::new(), ec)
+                .exec(&executable, args)
+                .unwrap_err();
+            if let SubprocessSetupError::ExecFailed {
+                source: nix::Error::Sys(Errno::ENOEXEC),
+                ..
+            } = e
+            {
+                Self::exec_script(&executable, &args[1..]);
+            }
+            if interactive {
+                let _ = traps::restore(&traps::child_defaults());
+            }
+            e
+        };
+
+        eprintln!("{}", Error::from(ErrorKind::SetupFailed(e.to_string())));
+        Self::exec_failed(e.exit_code())
+    }
+
+    /// The result of an `exec` that couldn't run its command: a non-interactive shell exits with `status`.
+    ///
+    /// POSIX only requires non-interactive shells to exit, an interactive one would be closed by a typo.
+    fn exec_failed(status: i32) -> Result<i32> {
+        if traps::is_interactive() {
+            Ok(status)
+        } else {
+            Err(ErrorKind::Exit(status).into())
+        }
+    }
+
+    /// Run `command` like a function: `return` ends it early
+    pub fn call(&mut self, ec: &mut ExecutionContext, command: Command) -> Result<i32> {
+        self.call_depth += 1;
+        let result = self.run(ec, command);
+        self.call_depth -= 1;
+        match result {
+            Ok(status) => Ok(status.exit_code),
+            Err(e) => match e.kind() {
+                ErrorKind::Return(status) => {
+                    ec.variables_mut().set_last_status(*status);
+                    Ok(*status)
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Number of functions and sourced scripts being run
+    pub fn call_depth(&self) -> usize {
+        self.call_depth
+    }
+
+    /// Keep the redirects applied to the running builtin after it finishes, instead of restoring the original file descriptors
+    pub fn keep_redirects(&mut self) {
+        self.keep_redirects = true;
+    }
+
+    /// Exit the shell after running a script with `result`, the `EXIT` trap is run first.
+    ///
+    /// A script ended by `exit` exits with its status, other errors are printed and the status is 1.
+    pub fn exit_shell(&mut self, ec: &mut ExecutionContext, result: Result<i32>) -> ! {
+        let status = script_status(result);
+        ec.variables_mut().set_last_status(status);
+
+        let trap_result = self.run_exit_trap(ec).map(|_| ec.variables().last_status());
+        let status = script_status(trap_result);
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        process::exit(status)
+    }
+
+    /// Run `command` in a subshell, a forked copy of the shell, and return the subshell's pid without waiting for it.
+    ///
+    /// Traps aren't inherited by the subshell, except for ignored signals.
+    fn spawn_subshell(
+        &mut self,
+        opts: &ProcessOptions,
+        ec: &mut ExecutionContext,
+        command: &Command,
+    ) -> Result<unistd::Pid> {
+        // anything left in the buffers would be written by both processes
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+
+        if let Some(pid) = Self::child_options(opts, ec)
+            .fork()
+            .context(ErrorKind::ExecFailed)?
+        {
+            return Ok(pid);
+        }
+
+        traps::reset_subshell();
+        self.in_trap = false;
+        self.background_jobs.clear();
+        self.exec_in_place = matches!(command, Command::SimpleCommand(_));
+
+        let result = self
+            .spawn_procs_from_ast(&ProcessOptions::new(), ec, command)
+            .and_then(|jids| self.await_all(&jids).map(|_| self.exit_code(&jids)));
+        self.exit_shell(ec, result)
+    }
+
+    /// Add the file descriptor operation for a single redirect (e.g. `2>&1`) to `opts`
+    fn add_redirect(
+        &mut self,
+        opts: ProcessOptions,
+        ec: &mut ExecutionContext,
+        dest: &RedirectDestination,
+    ) -> Result<ProcessOptions> {
+        let file = dest.file.compile(ec.variables_mut())?;
+        Ok(match dest.operation {
+            IoOperation::Input => opts.read(dest.fd.unwrap_or(0), file),
+            IoOperation::ReadWrite => opts.read_write(dest.fd.unwrap_or(0), file),
+            IoOperation::OutputCreate | IoOperation::Output => {
+                opts.write(dest.fd.unwrap_or(1), file)
+            }
+            IoOperation::OutputAppend => opts.append(dest.fd.unwrap_or(1), file),
+            IoOperation::InputDupFd | IoOperation::OutputDupFd => {
+                let default_fd = match dest.operation {
+                    IoOperation::InputDupFd => 0,
+                    _ => 1,
+                };
+                let fd = dest.fd.unwrap_or(default_fd);
+                if file == "-" {
+                    opts.close(fd)
+                } else {
+                    match file.parse::<RawFd>() {
+                        Ok(source) => opts.redirect(source, fd),
+                        Err(_) => return Err(ErrorKind::BadFileDescriptor(file).into()),
+                    }
+                }
+            }
+            IoOperation::HereDocument | IoOperation::HereDocumentStrip => {
+                return Err(ErrorKind::UnsupportedRedirect.into())
+            }
+        })
+    }
+
+    /// Run a list of commands one after another, returning the jobs of the last command
+    fn run_group(
+        &mut self,
+        opts: &ProcessOptions,
+        ec: &mut ExecutionContext,
+        commands: &[Command],
+    ) -> Result<Vec<Jid>> {
+        let mut last = Vec::new();
+        for cmd in commands {
+            // noexec stops execution, but commands are still parsed
+            if ec.variables().options().noexec {
+                break;
+            }
+            // comments don't change `$?`
+            if let Command::Comment(_) = cmd {
+                continue;
+            }
+
+            last = self.spawn_procs_from_ast(opts, ec, cmd)?;
+            self.await_all(&last)?;
+            let exit_code = self.exit_code(&last);
+            ec.variables_mut().set_last_status(exit_code);
+
+            // failures inside of groups trigger ERR themselves, and report their own signals
+            let compound = is_compound(cmd);
+            if let Some(status) = last.last().map(|jid| self.completed_jobs[jid]) {
+                // the user already knows about ^C, and SIGPIPE is how pipelines normally end early
+                let quiet = [None, Some(Signal::SIGINT), Some(Signal::SIGPIPE)];
+                if !compound && !quiet.contains(&status.signal) {
+                    eprintln!("{}", status);
+                }
+            }
+            // `&&` and `||` lists trigger ERR themselves, only if their last command fails
+            if exit_code != 0 && !compound && !matches!(cmd, Command::ConditionalPair(_)) {
+                self.run_err_trap(ec)?;
+            }
+            self.run_traps(ec)?;
+        }
+        Ok(last)
+    }
+
+    /// Run the commands trapped for any signals received since the last call.
+    ///
+    /// Traps run between commands, `$?` is restored afterwards so the interrupted script sees the same status.
+    /// If a signal that would normally kill the shell was only caught for the `EXIT` trap, the trap is run and the shell exits.
+    pub fn run_traps(&mut self, ec: &mut ExecutionContext) -> Result<()> {
+        // signals received while a trap is running are left in the pipe until it's finished
+        if self.in_trap {
+            return Ok(());
+        }
+
+        self.run_signal_traps(ec, &traps::take_pending())
+    }
+
+    /// Run the traps for signals taken from `traps::take_pending`
+    fn run_signal_traps(&mut self, ec: &mut ExecutionContext, signals: &[Signal]) -> Result<()> {
+        for sig in signals {
+            if traps::is_fatal(*sig) && !traps::is_trapped(*sig) {
+                self.exit_on_signal(ec, *sig);
+            }
+            self.run_trap(ec, Condition::Signal(*sig))?;
+        }
+        Ok(())
+    }
+
+    /// Run the commands trapped for `condition`, keeping `$?` the same
+    fn run_trap(&mut self, ec: &mut ExecutionContext, condition: Condition) -> Result<()> {
+        if self.in_trap {
+            return Ok(());
+        }
+        self.eval_trap(ec, &traps::commands(condition))
+    }
+
+    /// Run the `ERR` trap for a command that failed, unless its status is being tested by `&&` or `||`
+    fn run_err_trap(&mut self, ec: &mut ExecutionContext) -> Result<()> {
+        if self.in_condition {
+            return Ok(());
+        }
+        self.run_trap(ec, Condition::Err)
+    }
+
+    /// Run the `EXIT` trap, the shell should exit afterwards.
+    ///
+    /// The trap is removed before it runs, so it only runs once.
+    pub fn run_exit_trap(&mut self, ec: &mut ExecutionContext) -> Result<()> {
+        let commands = traps::commands(Condition::Exit);
+        let _ = traps::release(Condition::Exit);
+        self.eval_trap(ec, &commands)
+    }
+
+    fn eval_trap(&mut self, ec: &mut ExecutionContext, commands: &[String]) -> Result<()> {
+        let status = ec.variables().last_status();
+        self.in_trap = true;
+        let result = commands.iter().try_for_each(|command| {
+            Command::parse(command)
+                .map_err(Error::from)
+                .and_then(|command| self.run(ec, command))
+                .map(|_| ())
+        });
+        self.in_trap = false;
+        ec.variables_mut().set_last_status(status);
+        result
+    }
+
+    /// Run the `EXIT` trap, then let `sig` kill the shell
+    fn exit_on_signal(&mut self, ec: &mut ExecutionContext, sig: Signal) -> ! {
+        if let Err(e) = self.run_exit_trap(ec) {
+            eprintln!("{}", e);
+        }
+        let _ = io::stdout().flush();
+
+        let _ = traps::release(sig);
+        let _ = sig.raise();
+        process::exit(128 + sig.number())
+    }
+
     pub fn stat(&mut self, jid: Jid) -> Result<JobStatus> {
         if let Some(status) = self.completed_jobs.get(&jid) {
             Ok(JobStatus::Complete(*status))
@@ -247,6 +967,107 @@ impl JobManager {
         Ok(completed.1)
     }
 
+    /// Wait for several jobs to complete, like `await_all`, but stop early if a trapped signal is received.
+    ///
+    /// The signal's trap is run before it's returned. Inside of a trap this can't be interrupted.
+    pub fn await_interruptible(
+        &mut self,
+        ec: &mut ExecutionContext,
+        jids: &[Jid],
+    ) -> Result<Option<Signal>> {
+        if self.in_trap {
+            return self.await_all(jids).map(|_| None);
+        }
+
+        traps::with_child_wakeups(|| loop {
+            self.reap()?;
+            if jids.iter().all(|jid| self.completed_jobs.contains_key(jid)) {
+                return Ok(None);
+            }
+
+            // the self-pipe is readable once a child exits, or a trapped signal is received
+            if let Some(fd) = traps::fd() {
+                let mut fds = [PollFd::new(fd, EventFlags::POLLIN)];
+                match poll(&mut fds, -1) {
+                    Ok(_) | Err(nix::Error::Sys(Errno::EINTR)) => (),
+                    Err(e) => return Err(e).context(ErrorKind::WaitFailed).map_err(Error::from),
+                }
+            }
+
+            let signals = traps::take_pending();
+            self.run_signal_traps(ec, &signals)?;
+            if let Some(sig) = signals
+                .into_iter()
+                .find(|sig| *sig != Signal::SIGCHLD || traps::is_trapped(*sig))
+            {
+                return Ok(Some(sig));
+            }
+        })
+    }
+
+    /// Collect the status of every child that has exited, without blocking
+    fn reap(&mut self) -> Result<()> {
+        loop {
+            let status = match wait_any(libc::WNOHANG) {
+                Ok(Some(status)) => status,
+                Ok(None) | Err(nix::Error::Sys(Errno::ECHILD)) => return Ok(()),
+                Err(e) => return Err(e).context(ErrorKind::WaitFailed).map_err(Error::from),
+            };
+            if let Some(jid) = self.running_jobs.get(&status.pid.into()) {
+                self.completed_jobs.insert(*jid, status);
+            }
+        }
+    }
+
+    /// Get the jobs started in the background that haven't been waited for, oldest first
+    pub fn background_jobs(&self) -> &[BackgroundJob] {
+        &self.background_jobs
+    }
+
+    /// Remove a job from the list of background jobs, after it has been waited for
+    pub fn forget_job(&mut self, jid: Jid) {
+        self.background_jobs.retain(|job| job.jid != jid);
+    }
+
+    /// Find the job started with process id `pid`
+    pub fn find_pid(&self, pid: unistd::Pid) -> Option<Jid> {
+        self.running_jobs.get(&pid.into()).copied()
+    }
+
+    /// Find a background job from a job spec.
+    ///
+    /// - `%n`: job number n
+    /// - `%+` or `%%`: the current job, the most recent one
+    /// - `%-`: the previous job
+    /// - `%string`: the job whose command starts with string
+    /// - `%?string`: the job whose command contains string
+    ///
+    /// `None` is returned if there's no job, or if several jobs match a string.
+    pub fn find_job(&self, spec: &str) -> Option<&BackgroundJob> {
+        let spec = spec.strip_prefix('%')?;
+        let jobs = &self.background_jobs;
+        let matching = |pred: &dyn Fn(&BackgroundJob) -> bool| {
+            let mut found = jobs.iter().filter(|job| pred(job));
+            match (found.next(), found.next()) {
+                (Some(job), None) => Some(job),
+                _ => None,
+            }
+        };
+
+        match spec {
+            "" | "+" | "%" => jobs.last(),
+            "-" => jobs.iter().rev().nth(1),
+            n if n.chars().all(|c| c.is_ascii_digit()) => {
+                let n: usize = n.parse().ok()?;
+                jobs.iter().find(|job| job.number == n)
+            }
+            _ => match spec.strip_prefix('?') {
+                Some(text) => matching(&|job| job.command.contains(text)),
+                None => matching(&|job| job.command.starts_with(spec)),
+            },
+        }
+    }
+
     /// Wait for several jobs to complete
     pub fn await_all(&mut self, jids: &[Jid]) -> Result<()> {
         let mut incomplete: BTreeSet<Jid> = jids
@@ -266,12 +1087,69 @@ impl JobManager {
     }
 }
 
+/// Wait for any child process to exit, returns `None` for other changes in a child's state.
+///
+/// `options` are passed to `waitpid`, with `WNOHANG` `None` is also returned if no child has exited yet.
+/// This replaces `nix::sys::wait::wait`, which can't represent a child killed by a real-time signal.
+fn wait_any(options: libc::c_int) -> nix::Result<Option<ExitStatus>> {
+    let mut status: libc::c_int = 0;
+    let pid = Errno::result(unsafe { libc::waitpid(-1, &mut status, options) })?;
+    if pid == 0 {
+        return Ok(None);
+    }
+    let pid = unistd::Pid::from_raw(pid);
+
+    // the W* macros just pick apart the status' bits, they're only unsafe because they're foreign functions
+    Ok(unsafe {
+        if libc::WIFEXITED(status) {
+            Some(ExitStatus {
+                pid,
+                exit_code: libc::WEXITSTATUS(status),
+                core_dumped: false,
+                signal: None,
+            })
+        } else if libc::WIFSIGNALED(status) {
+            Some(ExitStatus {
+                pid,
+                exit_code: 128 + libc::WTERMSIG(status),
+                core_dumped: libc::WCOREDUMP(status),
+                signal: Signal::from_number(libc::WTERMSIG(status)),
+            })
+        } else {
+            None
+        }
+    })
+}
+
+/// Check if `cmd` is a group, the commands in it report their own failures
+fn is_compound(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::Group(_) | Command::BraceGroup(_) | Command::SubShell(_)
+    )
+}
+
+/// Get the status a script finished with, `exit` ends a script early with its status and other errors are printed and fail with status 1
+fn script_status(result: Result<i32>) -> i32 {
+    result.unwrap_or_else(|e| match e.kind() {
+        ErrorKind::Exit(status) => *status,
+        _ => {
+            eprintln!("{}", e);
+            1
+        }
+    })
+}
+
 impl Default for ExecutionContext {
     fn default() -> Self {
         ExecutionContext {
             vars: Variables::from_env(),
             funcs: Functions::new(),
             cwd: env::current_dir().unwrap(),
+            script_name: String::from("rush"),
+            fds: Rc::new(RefCell::new(FdTable::new())),
+            hashed: BTreeMap::new(),
+            hashed_path: OsString::new(),
         }
     }
 }
@@ -297,31 +1175,151 @@ impl ExecutionContext {
         &mut self.funcs
     }
 
-    pub fn find_executable<S: AsRef<OsStr>>(&self, prog: S) -> Result<PathBuf> {
+    pub fn script_name(&self) -> &str {
+        &self.script_name
+    }
+
+    pub fn set_script_name<S: Into<String>>(&mut self, name: S) {
+        self.script_name = name.into();
+    }
+
+    pub fn fds(&self) -> Ref<'_, FdTable> {
+        self.fds.borrow()
+    }
+
+    pub fn fds_mut(&mut self) -> RefMut<'_, FdTable> {
+        self.fds.borrow_mut()
+    }
+
+    /// Get the location of the start of `span` in the current script
+    pub fn location(&self, span: Span) -> Location {
+        Location {
+            source: self.script_name.clone(),
+            position: span.start,
+        }
+    }
+
+    /// Find the executable file for the command `prog` in `PATH`.
+    ///
+    /// The result is remembered in the hash table, and only searched for again if it's no longer executable or `PATH` changes.
+    pub fn find_executable<S: AsRef<OsStr>>(&mut self, prog: S) -> Result<PathBuf> {
         let prog_ref = prog.as_ref();
-        for path in env::split_paths(&self.vars.value(&OsString::from("PATH"))) {
-            let p = path.join(prog_ref);
-            if p.exists() {
+        let path = self.sync_hashed();
+        if let Some(p) = self.hashed.get(prog_ref) {
+            if is_executable(p) {
+                return Ok(p.clone());
+            }
+        }
+
+        for dir in env::split_paths(&path) {
+            let p = dir.join(prog_ref);
+            if is_executable(&p) {
+                self.hashed.insert(prog_ref.to_os_string(), p.clone());
                 return Ok(p);
             }
         }
 
+        self.hashed.remove(prog_ref);
         let owned_prog = prog_ref.to_os_string().to_string_lossy().to_string();
         Err(Error::from(ErrorKind::MissingExecutable(owned_prog)))
     }
+
+    /// Find what running the command `name` does, a function, then a builtin, then an executable file in `PATH`.
+    ///
+    /// The executor and builtins like `type` all use this, so they never disagree.
+    /// Functions are skipped with `skip_functions`, like `command` does.
+    pub fn resolve(&mut self, name: &str, skip_functions: bool) -> Option<Resolution> {
+        self.resolutions(name, skip_functions, false)
+            .into_iter()
+            .next()
+    }
+
+    /// Find everything `name` can refer to, in the order `resolve` checks them, including every match in `PATH`
+    pub fn resolve_all(&mut self, name: &str) -> Vec<Resolution> {
+        self.resolutions(name, false, true)
+    }
+
+    fn resolutions(&mut self, name: &str, skip_functions: bool, all: bool) -> Vec<Resolution> {
+        if name.contains('/') {
+            return vec![Resolution::File(PathBuf::from(name))];
+        }
+
+        let mut found = Vec::new();
+        if !skip_functions {
+            found.extend(
+                self.funcs
+                    .value(&name.to_string())
+                    .map(Resolution::Function),
+            );
+        }
+        found.extend(builtins::lookup(name).map(Resolution::Builtin));
+
+        if !all {
+            if found.is_empty() {
+                found.extend(self.find_executable(name).ok().map(Resolution::File));
+            }
+            return found;
+        }
+
+        let path = self.vars.value(&OsString::from("PATH"));
+        for dir in env::split_paths(&path) {
+            let p = dir.join(name);
+            if is_executable(&p) {
+                found.push(Resolution::File(p));
+            }
+        }
+        found
+    }
+
+    /// Get the hashed commands and their paths
+    pub fn hashed_commands(&mut self) -> btree_map::Iter<'_, OsString, PathBuf> {
+        self.sync_hashed();
+        self.hashed.iter()
+    }
+
+    /// Forget every hashed command, so they're searched for again
+    pub fn forget_hashed_commands(&mut self) {
+        self.hashed.clear();
+    }
+
+    /// Clear the hash table if `PATH` changed since it was filled, and return the current `PATH`
+    fn sync_hashed(&mut self) -> OsString {
+        let path = self.vars.value(&OsString::from("PATH"));
+        if path != self.hashed_path {
+            self.hashed.clear();
+            self.hashed_path = path.clone();
+        }
+        path
+    }
+}
+
+/// Check if `path` is a regular file the shell is allowed to execute
+pub fn is_executable(path: &Path) -> bool {
+    let is_file = path.metadata().map(|m| m.is_file()).unwrap_or(false);
+    let c_path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => c_path,
+        Err(_) => return false,
+    };
+    is_file && unsafe { libc::access(c_path.as_ptr(), libc::X_OK) } == 0
 }
 
 #[cfg(test)]
 mod test {
     use std::{
+        ffi::OsString,
         fs::File,
         io::{self, Read},
     };
 
+    use nix::libc;
+
     use crate::{
+        env::traps::{self, Condition, Signal},
         lang::{
             ast::{Command, CommandGroup, ConditionOperator, Function},
+            span::Span,
             word::Word,
+            ErrorKind,
         },
         test_util::forks,
     };
@@ -335,7 +1333,7 @@ mod test {
         let mut ec = ExecutionContext::new();
         let mut jm = JobManager::new();
         let status = jm
-            .run(&mut ec, Command::simple(vec![Word::parse("true")]))
+            .run(&mut ec, Command::simple(vec![Word::parse("true").unwrap()]))
             .expect("failed to execute 'true'");
 
         assert_eq!(status.exit_code, 0);
@@ -360,14 +1358,14 @@ mod test {
                 Command::pipeline(
                     false,
                     Command::simple(vec![
-                        Word::parse("printf"),
-                        Word::parse("%s"),
-                        Word::parse("hello"),
+                        Word::parse("printf").unwrap(),
+                        Word::parse("%s").unwrap(),
+                        Word::parse("hello").unwrap(),
                     ]),
                     Command::simple(vec![
-                        Word::parse("cp"),
-                        Word::parse("/dev/stdin"),
-                        Word::parse(out_file),
+                        Word::parse("cp").unwrap(),
+                        Word::parse("/dev/stdin").unwrap(),
+                        Word::parse(out_file).unwrap(),
                     ]),
                 ),
             )
@@ -393,9 +1391,9 @@ mod test {
             .run(
                 &mut ec,
                 Command::conditional(
-                    Command::simple(vec![Word::parse("true")]),
+                    Command::simple(vec![Word::parse("true").unwrap()]),
                     ConditionOperator::AndIf,
-                    Command::simple(vec![Word::parse("true")]),
+                    Command::simple(vec![Word::parse("true").unwrap()]),
                 ),
             )
             .expect("failed to execute true && true");
@@ -405,9 +1403,9 @@ mod test {
             .run(
                 &mut ec,
                 Command::conditional(
-                    Command::simple(vec![Word::parse("true")]),
+                    Command::simple(vec![Word::parse("true").unwrap()]),
                     ConditionOperator::AndIf,
-                    Command::simple(vec![Word::parse("false")]),
+                    Command::simple(vec![Word::parse("false").unwrap()]),
                 ),
             )
             .expect("failed to execute true && false");
@@ -424,9 +1422,9 @@ mod test {
             .run(
                 &mut ec,
                 Command::conditional(
-                    Command::simple(vec![Word::parse("true")]),
+                    Command::simple(vec![Word::parse("true").unwrap()]),
                     ConditionOperator::OrIf,
-                    Command::simple(vec![Word::parse("true")]),
+                    Command::simple(vec![Word::parse("true").unwrap()]),
                 ),
             )
             .expect("failed to execute true || true");
@@ -436,9 +1434,9 @@ mod test {
             .run(
                 &mut ec,
                 Command::conditional(
-                    Command::simple(vec![Word::parse("true")]),
+                    Command::simple(vec![Word::parse("true").unwrap()]),
                     ConditionOperator::OrIf,
-                    Command::simple(vec![Word::parse("false")]),
+                    Command::simple(vec![Word::parse("false").unwrap()]),
                 ),
             )
             .expect("failed to execute true || false");
@@ -448,15 +1446,38 @@ mod test {
             .run(
                 &mut ec,
                 Command::conditional(
-                    Command::simple(vec![Word::parse("false")]),
+                    Command::simple(vec![Word::parse("false").unwrap()]),
                     ConditionOperator::OrIf,
-                    Command::simple(vec![Word::parse("false")]),
+                    Command::simple(vec![Word::parse("false").unwrap()]),
                 ),
             )
             .expect("failed to execute false || false");
         assert_eq!(status.exit_code, 1);
     }
 
+    #[test]
+    fn builtin_pipeline() {
+        forks!();
+
+        // more than the pipe buffer, which a builtin on the left would fill before `wc` started
+        let out_file = "test/data/builtin_pipeline-out.txt";
+        let script = format!(
+            "{{ printf '%070000d' 0; x=1; }} | wc -c > {out}; set | cat > /dev/null",
+            out = out_file
+        );
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+        let status = jm
+            .run(&mut ec, Command::parse(script).expect("failed to parse"))
+            .expect("failed to run pipeline");
+        assert_eq!(status.exit_code, 0);
+        assert!(!ec.variables().is_set(&OsString::from("x")));
+
+        let content = std::fs::read_to_string(out_file).expect("failed to read out file");
+        assert_eq!(content.trim(), "70000");
+    }
+
     #[test]
     fn group_pipeline() {
         forks!();
@@ -476,13 +1497,19 @@ mod test {
                 Command::pipeline(
                     false,
                     Command::group(vec![
-                        Command::simple(vec![Word::parse("printf"), Word::parse("hello\\n")]),
-                        Command::simple(vec![Word::parse("printf"), Word::parse("world")]),
+                        Command::simple(vec![
+                            Word::parse("printf").unwrap(),
+                            Word::parse("hello\\n").unwrap(),
+                        ]),
+                        Command::simple(vec![
+                            Word::parse("printf").unwrap(),
+                            Word::parse("world").unwrap(),
+                        ]),
                     ]),
                     Command::simple(vec![
-                        Word::parse("cp"),
-                        Word::parse("/dev/stdin"),
-                        Word::parse(out_file),
+                        Word::parse("cp").unwrap(),
+                        Word::parse("/dev/stdin").unwrap(),
+                        Word::parse(out_file).unwrap(),
                     ]),
                 ),
             )
@@ -509,10 +1536,10 @@ mod test {
             .run(
                 &mut ec,
                 Command::simple(vec![
-                    Word::parse("test"),
-                    Word::parse("$EXPAND_ENV_VARS_TEST"),
-                    Word::parse("="),
-                    Word::parse("helloworld"),
+                    Word::parse("test").unwrap(),
+                    Word::parse("$EXPAND_ENV_VARS_TEST").unwrap(),
+                    Word::parse("=").unwrap(),
+                    Word::parse("helloworld").unwrap(),
                 ]),
             )
             .expect("failed to execute printf");
@@ -524,10 +1551,10 @@ mod test {
             .run(
                 &mut ec,
                 Command::simple(vec![
-                    Word::parse("test"),
-                    Word::parse("$EXPAND_ENV_VARS_TEST"),
-                    Word::parse("="),
-                    Word::parse("shadowed"),
+                    Word::parse("test").unwrap(),
+                    Word::parse("$EXPAND_ENV_VARS_TEST").unwrap(),
+                    Word::parse("=").unwrap(),
+                    Word::parse("shadowed").unwrap(),
                 ]),
             )
             .expect("failed to execute printf");
@@ -552,14 +1579,25 @@ mod test {
             .run(
                 &mut ec,
                 Command::Function(Box::new(Function {
-                    name: Word::parse("write_hello_3"),
+                    name: Word::parse("write_hello_3").unwrap(),
                     body: Command::BraceGroup(Box::new(CommandGroup {
                         commands: vec![
-                            Command::simple(vec![Word::parse("printf"), Word::parse("hello\\n")]),
-                            Command::simple(vec![Word::parse("printf"), Word::parse("hello\\n")]),
-                            Command::simple(vec![Word::parse("printf"), Word::parse("hello\\n")]),
+                            Command::simple(vec![
+                                Word::parse("printf").unwrap(),
+                                Word::parse("hello\\n").unwrap(),
+                            ]),
+                            Command::simple(vec![
+                                Word::parse("printf").unwrap(),
+                                Word::parse("hello\\n").unwrap(),
+                            ]),
+                            Command::simple(vec![
+                                Word::parse("printf").unwrap(),
+                                Word::parse("hello\\n").unwrap(),
+                            ]),
                         ],
+                        span: Span::default(),
                     })),
+                    span: Span::default(),
                 })),
             )
             .expect("failed to execute function statement");
@@ -572,11 +1610,11 @@ mod test {
                 &mut ec,
                 Command::pipeline(
                     false,
-                    Command::simple(vec![Word::parse("write_hello_3")]),
+                    Command::simple(vec![Word::parse("write_hello_3").unwrap()]),
                     Command::simple(vec![
-                        Word::parse("cp"),
-                        Word::parse("/dev/stdin"),
-                        Word::parse(out_file),
+                        Word::parse("cp").unwrap(),
+                        Word::parse("/dev/stdin").unwrap(),
+                        Word::parse(out_file).unwrap(),
                     ]),
                 ),
             )
@@ -590,4 +1628,291 @@ mod test {
             .expect("failed to read out file");
         assert_eq!(content, "hello\nhello\nhello\n");
     }
+
+    #[test]
+    fn nounset() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+        ec.variables_mut().options_mut().nounset = true;
+
+        let err = jm
+            .run(
+                &mut ec,
+                Command::simple(vec![
+                    Word::parse("true").unwrap(),
+                    Word::parse("$RUSH_NOUNSET_TEST").unwrap(),
+                ]),
+            )
+            .expect_err("expanding an unset variable should fail with nounset");
+        assert_eq!(
+            err.kind(),
+            &ErrorKind::UnboundVariable("RUSH_NOUNSET_TEST".into())
+        );
+
+        ec.variables_mut().set_positional(vec!["a"]);
+        let status = jm
+            .run(
+                &mut ec,
+                Command::simple(vec![
+                    Word::parse("true").unwrap(),
+                    Word::parse("$1").unwrap(),
+                    Word::parse("$@").unwrap(),
+                    Word::parse("$*").unwrap(),
+                ]),
+            )
+            .expect("set positional parameters, $@ and $* should expand with nounset");
+        assert_eq!(status.exit_code, 0);
+
+        let err = jm
+            .run(
+                &mut ec,
+                Command::simple(vec![
+                    Word::parse("true").unwrap(),
+                    Word::parse("${2}").unwrap(),
+                ]),
+            )
+            .expect_err("expanding an unset positional parameter should fail with nounset");
+        assert_eq!(err.kind(), &ErrorKind::UnboundVariable("2".into()));
+    }
+
+    #[test]
+    fn noexec() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+        let status = jm
+            .run(
+                &mut ec,
+                Command::group(vec![
+                    Command::simple(vec![
+                        Word::parse("set").unwrap(),
+                        Word::parse("-n").unwrap(),
+                    ]),
+                    Command::simple(vec![Word::parse("false").unwrap()]),
+                ]),
+            )
+            .expect("failed to run group");
+        assert_eq!(status.exit_code, 0);
+        assert!(ec.variables().options().noexec);
+    }
+
+    #[test]
+    fn event_traps() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+        let script = "trap 'set -- \"$1\"e' ERR; trap 'set -- \"$1\"x' EXIT
+            set -- ''
+            set -X 2>/dev/null
+            false && true
+            { false; } || true
+            true && set -X 2>/dev/null
+            { set -X 2>/dev/null; }";
+        let status = jm
+            .run(&mut ec, Command::parse(script).unwrap())
+            .expect("failed to run script");
+        traps::release(Condition::Err).expect("failed to remove the ERR trap");
+
+        assert_eq!(status.exit_code, 2);
+        assert_eq!(ec.variables().value(&OsString::from("1")), "eee");
+
+        jm.run_exit_trap(&mut ec)
+            .expect("failed to run the EXIT trap");
+        assert_eq!(ec.variables().value(&OsString::from("1")), "eeex");
+        assert_eq!(ec.variables().last_status(), 2);
+        assert!(!traps::is_trapped(Condition::Exit));
+    }
+
+    #[test]
+    fn child_signals() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+
+        // trapped signals are reset in children
+        let status = jm
+            .run(
+                &mut ec,
+                Command::parse("trap 'true' USR1; sh -c 'kill -USR1 $$; exit 3'").unwrap(),
+            )
+            .expect("failed to run script");
+        traps::release(Signal::SIGUSR1).expect("failed to release SIGUSR1");
+        assert_eq!(status.signal, Some(Signal::SIGUSR1));
+        assert_eq!(status.exit_code, 128 + Signal::SIGUSR1.number());
+        assert_eq!(status.to_string(), "User defined signal 1: SIGUSR1");
+
+        // background jobs ignore SIGINT
+        let status = jm
+            .run(
+                &mut ec,
+                Command::parse("sh -c 'kill -INT $$; exit 5' &").unwrap(),
+            )
+            .expect("failed to run script");
+        assert_eq!(status.exit_code, 0);
+
+        let pid: libc::pid_t = ec
+            .variables()
+            .value(&OsString::from("!"))
+            .to_string_lossy()
+            .parse()
+            .expect("$! should be set to the background job's pid");
+        let jid = jm.running_jobs[&pid];
+        assert_eq!(jm.r#await(jid).expect("failed to wait").exit_code, 5);
+    }
+
+    #[test]
+    fn redirect() {
+        forks!();
+
+        let out_file = "test/data/redirect-out.txt";
+        match std::fs::remove_file(&out_file) {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => panic!("failed to remove file: {}", err),
+        }
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+        let status = jm
+            .run(
+                &mut ec,
+                Command::parse(format!(
+                    "{{ printf 'hello\\n'; set; }} > {out}; printf world 2>&1 >>{out}",
+                    out = out_file
+                ))
+                .expect("failed to parse redirects"),
+            )
+            .expect("failed to run redirects");
+        assert_eq!(status.exit_code, 0);
+
+        let mut content = String::new();
+        File::open(out_file)
+            .expect("failed to open out file")
+            .read_to_string(&mut content)
+            .expect("failed to read out file");
+        assert!(content.starts_with("hello\n"));
+        assert!(content.ends_with("world"));
+        assert!(content.contains("\nLINENO=1\n"));
+    }
+
+    #[test]
+    fn error_location() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+        ec.set_script_name("error_location.rush");
+
+        let err = jm
+            .run(
+                &mut ec,
+                Command::parse("printf 'a\nb' > /dev/null; set -u; true $RUSH_NOT_SET")
+                    .expect("failed to parse"),
+            )
+            .expect_err("expanding an unset variable should fail");
+        assert_eq!(
+            err.to_string(),
+            "error_location.rush:2:25: RUSH_NOT_SET: unbound variable"
+        );
+        assert_eq!(ec.variables().value(&"LINENO".into()), "2");
+    }
+
+    #[test]
+    fn exec_failures() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+        let mut run = |script: &str| {
+            jm.run(&mut ec, Command::parse(script).expect("failed to parse"))
+                .expect("failed to run script")
+                .exit_code
+        };
+
+        // missing commands don't stop the script
+        assert_eq!(run("rush-not-a-real-command 2>/dev/null"), 127);
+        assert_eq!(run("rush-not-a-real-command 2>/dev/null; true"), 0);
+        assert_eq!(run("./rush-not-a-real-command 2>/dev/null"), 127);
+        assert_eq!(run("./Cargo.toml 2>/dev/null"), 126);
+        assert_eq!(run("{ ./Cargo.toml; } 2>/dev/null & wait $!"), 126);
+        assert_eq!(run("true < rush-not-a-real-file 2>/dev/null"), 1);
+        assert_eq!(run("true < rush-not-a-real-file 2>/dev/null; true"), 0);
+
+        // so do builtins and groups whose redirects fail
+        assert_eq!(run("{ echo x > /rush/not/a/dir/file; } 2>/dev/null"), 1);
+        let script =
+            "{ echo x > /rush/not/a/dir/file; test $? = 1 && echo y >&3; } 3>/dev/null 2>&3";
+        assert_eq!(run(script), 0);
+        let script =
+            "{ { echo x; } > /rush/not/a/dir/file; test $? = 1 && echo y >&3; } 3>/dev/null 2>&3";
+        assert_eq!(run(script), 0);
+
+        // files without `#!` are run as scripts
+        assert_eq!(run("./test/data/noshebang.rush 7"), 7);
+        assert_eq!(run("./test/data/noshebang.rush 8 & wait $!"), 8);
+    }
+
+    #[test]
+    fn assignments() {
+        forks!();
+
+        let mut ec = ExecutionContext::new();
+        let mut jm = JobManager::new();
+        let mut run = |ec: &mut ExecutionContext, script: &str| {
+            jm.run(ec, Command::parse(script).expect("failed to parse"))
+                .expect("failed to run script")
+                .exit_code
+        };
+        let value = |ec: &ExecutionContext, name: &str| ec.variables().value(&OsString::from(name));
+        let elements = |ec: &ExecutionContext, name: &str| {
+            ec.variables()
+                .elements(&OsString::from(name))
+                .into_iter()
+                .map(|e| e.into_string().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(&mut ec, "a=1 b=x; b+=y"), 0);
+        assert_eq!(value(&ec, "a"), "1");
+        assert_eq!(value(&ec, "b"), "xy");
+
+        // assignments before a command are only exported to it
+        assert_eq!(
+            run(
+                &mut ec,
+                "a=2 RUSH_ASSIGN_TEST=3 sh -c 'exit $a$RUSH_ASSIGN_TEST'"
+            ),
+            23
+        );
+        assert_eq!(value(&ec, "a"), "1");
+        assert!(!ec.variables().is_set(&"RUSH_ASSIGN_TEST".into()));
+        assert!(std::env::var("RUSH_ASSIGN_TEST").is_err());
+
+        assert_eq!(
+            run(&mut ec, "i=1; arr=(x 'y z' [5]=w); arr+=(v); arr[i+1]=u"),
+            0
+        );
+        assert_eq!(elements(&ec, "arr"), vec!["x", "y z", "u", "w", "v"]);
+        assert_eq!(
+            run(
+                &mut ec,
+                "copy=(\"${arr[@]}\" \"${none[@]}\"); n=${#arr[@]} last=${arr[-1]}"
+            ),
+            0
+        );
+        assert_eq!(elements(&ec, "copy"), elements(&ec, "arr"));
+        assert_eq!(value(&ec, "n"), "5");
+        assert_eq!(value(&ec, "last"), "v");
+
+        assert_eq!(run(&mut ec, "declare -A m; m[a b]=1 m[c]+=2; m[c]+=3"), 0);
+        assert_eq!(elements(&ec, "m"), vec!["1", "23"]);
+        assert_eq!(run(&mut ec, "m=(x) 2>/dev/null"), 1);
+        assert_eq!(run(&mut ec, "arr[-10]=x 2>/dev/null"), 1);
+        assert_eq!(run(&mut ec, "readonly a; a=2 2>/dev/null"), 1);
+    }
 }
@@ -1,7 +1,11 @@
+#[macro_use]
+pub mod span;
 pub mod ast;
+mod conditional;
 mod errors;
 pub mod exec;
 pub mod parser;
+pub mod pattern;
 pub mod word;
 pub use self::errors::*;
 pub use self::exec::{ExecutionContext, JobManager};
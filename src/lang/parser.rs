@@ -1,6 +1,7 @@
 use crate::lang::ast::*;
-use crate::lang::word::word;
 use crate::lang::word::Word;
+use crate::lang::word::{conditional_word, regex_word, subscript, variable_name, word};
+use crate::lang::ParseError;
 use nom;
 ///! Nom combinations for parsing RUSH shell scripts
 use nom::types::CompleteStr;
@@ -11,7 +12,11 @@ use std::str::FromStr;
 /// Characters Recognized as Whitespace:
 /// - \t (tab)
 /// - \  (space)
-named!(pub space<CompleteStr, CompleteStr>, eat_separator!(" \t"));
+/// - \\\n (an escaped newline, which continues the current line)
+named!(
+    pub space<CompleteStr, CompleteStr>,
+    recognize!(many0!(alt!(is_a!(" \t") | tag!("\\\n"))))
+);
 
 /// Line endings are whitespace that move the cursor to the next line
 named!(
@@ -29,6 +34,37 @@ macro_rules! sp (
   )
 );
 
+// Like `space`, but also skips newlines
+named!(
+    pub blank<CompleteStr, CompleteStr>,
+    recognize!(many0!(alt!(is_a!(" \t\n") | tag!("\\\n"))))
+);
+
+// Skip blank lines and comments, for places where a command may continue on the next line
+named!(
+    pub linebreak<CompleteStr, CompleteStr>,
+    recognize!(many0!(alt!(is_a!(" \t\n") | tag!("\\\n") | comment)))
+);
+
+/// Turn a recoverable error into a failure, so the error is reported where `$submac` failed instead of
+/// being discarded by `alt!` or `many0!`.
+///
+/// Used after tokens that must be followed by something, like `|` or `{`, so input that ends early is reported as incomplete.
+#[macro_export]
+macro_rules! required (
+  ($i:expr, $submac:ident!( $($args:tt)* )) => (
+    {
+      match $submac!($i, $($args)*) {
+        Err(nom::Err::Error(e)) => Err(nom::Err::Failure(e)),
+        r => r,
+      }
+    }
+  );
+  ($i:expr, $f:expr) => (
+    required!($i, call!($f))
+  );
+);
+
 /// A conditional can join two commands, depending on the result of the prior command the second may not be executed
 ///
 /// Supported conditional operators
@@ -74,7 +110,7 @@ named!(
 /// Pipes connect a command's standard out to another command's standard in
 named!(
     pub pipe<CompleteStr, char>,
-    one_of!("|")
+    terminated!(one_of!("|"), not!(char!('|')))
 );
 
 /// A separator splits WORDS into commands when they are on the same line
@@ -84,11 +120,13 @@ named!(
 /// Supported Separators
 /// - ; Acts a newline
 /// - & same as `;`, but asynchronously calls the previous command
+/// - \n ends the command, like `;`
 named!(
     pub separator<CompleteStr, Separator>,
     alt!(
-          tag!("&")   => { |_| Separator::Fork }
-        | tag!(";")   => { |_| Separator::Stop }
+          terminated!(tag!("&"), not!(char!('&')))   => { |_| Separator::Fork }
+        | terminated!(tag!(";"), not!(char!(';')))   => { |_| Separator::Stop }
+        | line_ending                                => { |_| Separator::Eol }
     )
 );
 
@@ -98,11 +136,74 @@ named!(
     terminated!(take_while1!(|c| c >= '0' && c <= '9'), one_of!("<>"))
 );
 
+// An assignment is a variable name, an optional subscript, and `=` or `+=` directly followed by the value.
+//
+// The value is a word, or a list of array elements in parentheses, which may each start with `[subscript]=`
+named!(
+    pub assignment<CompleteStr, Assignment>,
+    map!(
+        spanned!(do_parse!(
+            name: variable_name >>
+            subscript: opt!(subscript) >>
+            append: opt!(char!('+')) >>
+            char!('=') >>
+            value: alt!(
+                cond_reduce!(subscript.is_none(), array_value)
+                | opt!(word) => { |w: Option<Word>| AssignmentValue::Word(w.unwrap_or_else(Word::new)) }
+            ) >>
+            (name.0.to_string(), subscript, append.is_some(), value)
+        )),
+        |((name, subscript, append, value), span)| Assignment { name, subscript, append, value, span }
+    )
+);
+
+named!(
+    array_value<CompleteStr, AssignmentValue>,
+    map!(
+        preceded!(
+            char!('('),
+            required!(terminated!(
+                many0!(preceded!(linebreak, array_element)),
+                preceded!(linebreak, char!(')'))
+            ))
+        ),
+        AssignmentValue::Array
+    )
+);
+
+named!(
+    array_element<CompleteStr, (Option<Word>, Word)>,
+    alt!(
+        do_parse!(
+            subscript: subscript >>
+            char!('=') >>
+            value: opt!(conditional_word) >>
+            (Some(subscript), value.unwrap_or_else(Word::new))
+        )
+        | conditional_word => { |w| (None, w) }
+    )
+);
+
+// A simple command is any number of assignments, followed by the command's words.
+//
+// Only words before the command name are assignments, `echo a=1` prints `a=1`
 named!(
     pub simple_command<CompleteStr, Command>,
-    do_parse!(
-        args: separated_list!(space, preceded!(not!(io_number), word)) >>
-        (Command::simple(args))
+    map_opt!(
+        spanned!(pair!(
+            separated_list!(space, assignment),
+            opt!(preceded!(
+                space,
+                separated_nonempty_list!(space, preceded!(not!(alt!(io_number | tag!("#"))), word))
+            ))
+        )),
+        |((assignments, args), span): ((Vec<Assignment>, Option<Vec<Word>>), _)| {
+            let args = args.unwrap_or_default();
+            if assignments.is_empty() && args.is_empty() {
+                return None;
+            }
+            Some(Command::SimpleCommand(SimpleCommand { assignments, arguments: args, span }))
+        }
     )
 );
 
@@ -115,26 +216,163 @@ pub fn split_words<T: AsRef<str>>(s: T) -> Vec<Word> {
 
 named!(
     pub redirect_destination<CompleteStr, RedirectDestination>,
-    do_parse!(
-        number : opt!(map!(take_while1!(|c| c >= '0' && c <= '9'), |nums| RawFd::from_str(nums.0).unwrap())) >>
-        operation : call!(io_operator) >>
-        file: opt!(word) >>
-        (RedirectDestination::new(operation, number, file))
+    map!(
+        spanned!(do_parse!(
+            number : opt!(map_res!(take_while1!(|c| c >= '0' && c <= '9'), |nums: CompleteStr| RawFd::from_str(nums.0))) >>
+            operation : call!(io_operator) >>
+            file: opt!(sp!(word)) >>
+            (RedirectDestination::new(operation, number, file))
+        )),
+        |(mut redirect, span)| {
+            redirect.span = span;
+            redirect
+        }
     )
 );
 
 named!(
     pub group<CompleteStr, Command>,
-    sp!(delimited!(char!('{'), sp!(commandline), char!('}')))
+    sp!(map!(
+        spanned!(sp!(delimited!(
+            char!('{'),
+            preceded!(linebreak, commandline),
+            required!(preceded!(linebreak, char!('}')))
+        ))),
+        |(group, span)| group.with_span(span)
+    ))
 );
 
+// The end of a `[[` or `]]` keyword, they're only keywords when they're a whole word
+named!(
+    keyword_end<CompleteStr, ()>,
+    not!(conditional_word)
+);
+
+// A `[[ expression ]]` test
+//
+// Expressions are joined by `&&` and `||`, negated with `!` and grouped with parentheses.
+// Operators are the ones used by `test`, with `==` and `!=` matching patterns, and `=~` matching a regex.
+named!(
+    pub conditional<CompleteStr, Command>,
+    do_parse!(
+        start: spanned!(terminated!(tag!("[["), keyword_end)) >>
+        expression: required!(conditional_or) >>
+        end: required!(preceded!(blank, spanned!(terminated!(tag!("]]"), keyword_end)))) >>
+        (Command::Conditional(Box::new(Conditional {
+            expression,
+            span: start.1.to(end.1),
+        })))
+    )
+);
+
+named!(
+    conditional_or<CompleteStr, ConditionalExpression>,
+    do_parse!(
+        first: conditional_and >>
+        rest: fold_many0!(
+            preceded!(preceded!(blank, tag!("||")), required!(conditional_and)),
+            first,
+            |left, right| ConditionalExpression::Or(Box::new(left), Box::new(right))
+        ) >>
+        (rest)
+    )
+);
+
+named!(
+    conditional_and<CompleteStr, ConditionalExpression>,
+    do_parse!(
+        first: conditional_not >>
+        rest: fold_many0!(
+            preceded!(preceded!(blank, tag!("&&")), required!(conditional_not)),
+            first,
+            |left, right| ConditionalExpression::And(Box::new(left), Box::new(right))
+        ) >>
+        (rest)
+    )
+);
+
+named!(
+    conditional_not<CompleteStr, ConditionalExpression>,
+    alt!(
+        preceded!(
+            preceded!(blank, terminated!(char!('!'), keyword_end)),
+            required!(conditional_not)
+        ) => { |e| ConditionalExpression::Not(Box::new(e)) }
+        | preceded!(blank, conditional_primary)
+    )
+);
+
+named!(
+    conditional_primary<CompleteStr, ConditionalExpression>,
+    alt!(
+        delimited!(
+            char!('('),
+            required!(conditional_or),
+            required!(preceded!(blank, char!(')')))
+        )
+        | do_parse!(
+            left: conditional_operand >>
+            op: preceded!(space, conditional_binary_operator) >>
+            right: required!(preceded!(space, call!(conditional_right, op))) >>
+            (ConditionalExpression::Binary(left, op.0.to_string(), right))
+        )
+        | do_parse!(
+            op: conditional_unary_operator >>
+            operand: preceded!(space, conditional_operand) >>
+            (ConditionalExpression::Unary(op.0.to_string(), operand))
+        )
+        | conditional_operand => { ConditionalExpression::Word }
+    )
+);
+
+// Any word other than the closing `]]`
+named!(
+    conditional_operand<CompleteStr, Word>,
+    preceded!(not!(terminated!(tag!("]]"), keyword_end)), conditional_word)
+);
+
+named!(
+    conditional_unary_operator<CompleteStr, CompleteStr>,
+    terminated!(recognize!(pair!(char!('-'), one_of!("bcdefghknprstuwxzLS"))), keyword_end)
+);
+
+named!(
+    conditional_binary_operator<CompleteStr, CompleteStr>,
+    alt!(
+        terminated!(
+            alt!(
+                tag!("==") | tag!("!=") | tag!("=~") | tag!("=") | tag!("-eq") | tag!("-ne") | tag!("-lt")
+                | tag!("-le") | tag!("-gt") | tag!("-ge") | tag!("-nt") | tag!("-ot") | tag!("-ef")
+            ),
+            keyword_end
+        )
+        // `<` and `>` compare strings inside `[[ ]]`, they don't need to be separated from their operands
+        | tag!("<")
+        | tag!(">")
+    )
+);
+
+/// The right operand of a binary operator, which is a regex for `=~`
+fn conditional_right<'a>(
+    input: CompleteStr<'a>,
+    op: CompleteStr,
+) -> nom::IResult<CompleteStr<'a>, Word> {
+    match op.0 {
+        "=~" => regex_word(input),
+        _ => conditional_operand(input),
+    }
+}
+
 named!(
     pub redirect<CompleteStr, Command>,
     do_parse!(
-        command  : sp!(alt!(function | group | simple_command)) >>
-        redirect : opt!(many1!(sp!(redirect_destination))) >>
+        command  : sp!(alt!(function | group | conditional | simple_command)) >>
+        redirect : opt!(many1!(preceded!(space, redirect_destination))) >>
         (match redirect {
-            Some(v) => Command::redirect(command, v),
+            Some(v) => {
+                let span = command.span().to(v[v.len() - 1].span);
+                Command::redirect(command, v).with_span(span)
+            }
             None => command,
         })
     )
@@ -143,11 +381,12 @@ named!(
 named!(
     pub function<CompleteStr, Command>,
     do_parse!(
-        _kw : sp!(tag!("function")) >>
+        kw : sp!(spanned!(tag!("function"))) >>
         name : sp!(word) >>
         body : sp!(group) >>
         (Command::Function(Box::new(Function {
             name: name,
+            span: kw.1.to(body.span()),
             body: body,
         })))
     )
@@ -161,12 +400,13 @@ named!(
         sub: fold_many0!(
             do_parse!(
                 _op: sp!(pipe) >>
-                expr: sp!(redirect) >>
+                expr: required!(preceded!(linebreak, redirect)) >>
                 (expr)
             ),
             initial,
-            |start, expr| {
-                Command::pipeline(bang.is_some(), start, expr)
+            |start: Command, expr: Command| {
+                let span = start.span().to(expr.span());
+                Command::pipeline(bang.is_some(), start, expr).with_span(span)
             }
         ) >> (sub)
     )
@@ -179,23 +419,291 @@ named!(
         extended: fold_many0!(
             do_parse!(
                 op   : sp!(conditional_operator) >>
-                expr : sp!(pipeline) >>
+                expr : required!(preceded!(linebreak, pipeline)) >>
                 (op, expr)
             ),
             initial,
-            |start, (op, expr)| {
-                Command::conditional(start, op, expr)
+            |start: Command, (op, expr): (ConditionOperator, Command)| {
+                let span = start.span().to(expr.span());
+                Command::conditional(start, op, expr).with_span(span)
             }
         ) >> (extended)
     )
 );
 
+// A comment starts with a `#` at the beginning of a word, and runs until the end of the line
+named!(
+    pub comment<CompleteStr, CompleteStr>,
+    preceded!(tag!("#"), take_while!(|c| c != '\n'))
+);
+
+// Commands are terminated by a separator, and any number of blank lines
 named!(
-    pub comment<CompleteStr, Command>,
-    map!(preceded!(tag!("#"), take_until!("\n")), |s| Command::Comment(s.0.to_string()))
+    pub separator_list<CompleteStr, Option<Separator>>,
+    terminated!(opt!(sp!(separator)), blank)
 );
 
 named!(
     pub commandline<CompleteStr, Command>,
-    map!(sp!(separated_list!(separator, list)), |v| Command::group(v))
+    map!(
+        preceded!(blank, spanned!(many0!(map!(pair!(
+            alt!(
+                map!(spanned!(comment), |(text, span)| Command::Comment(Box::new(Comment {
+                    text: text.0.to_string(),
+                    span,
+                })))
+                | list
+            ),
+            separator_list
+        ), |(command, separator)| match separator {
+            Some(Separator::Fork) => Command::background(command),
+            _ => command,
+        })))),
+        |(v, span)| Command::group(v).with_span(span)
+    )
 );
+
+/// Parse a complete script, failing if any of the input couldn't be parsed
+pub fn parse<T: AsRef<str>>(s: T) -> Result<Command, ParseError> {
+    let source = s.as_ref();
+    match commandline(CompleteStr(source)) {
+        Ok((rest, mut command)) if rest.trim().is_empty() => {
+            command.resolve_spans(source);
+            Ok(command)
+        }
+        Ok((rest, _)) => Err(ParseError::new(source, rest.0)),
+        Err(nom::Err::Error(nom::Context::Code(rest, _)))
+        | Err(nom::Err::Failure(nom::Context::Code(rest, _))) => {
+            Err(ParseError::new(source, rest.0))
+        }
+        Err(_) => Err(ParseError::new(source, source)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lang::ast::{AssignmentValue, Command, ConditionalExpression};
+    use crate::lang::word::{Token, Word};
+    use crate::lang::{parser::parse, ParseErrorKind};
+
+    #[test]
+    fn rejects_trailing_input() {
+        let err = parse("true; echo }").expect_err("'}' without '{' should fail to parse");
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedToken);
+        assert_eq!(err.token, "}");
+        assert_eq!((err.line, err.column), (1, 12));
+
+        let err = parse("true && }").expect_err("'}' after '&&' should fail to parse");
+        assert_eq!(err.token, "}");
+        assert_eq!((err.line, err.column), (1, 9));
+        assert!(!err.is_incomplete());
+    }
+
+    #[test]
+    fn incomplete() {
+        for source in &[
+            "true &&",
+            "true ||\n",
+            "echo a |",
+            "{ true;",
+            "{\n true\n",
+            "echo 'a",
+        ] {
+            let err = parse(source).expect_err("incomplete input should fail to parse");
+            assert!(err.is_incomplete(), "{:?} should be incomplete", source);
+        }
+
+        parse("echo a |\n cat").expect("a pipeline should continue on the next line");
+        parse("{\n true;\n}").expect("a group should continue on the next line");
+        parse("true || false").expect("'||' isn't a pipe");
+    }
+
+    #[test]
+    fn error_position() {
+        let err = parse("{ true; }; echo \"hello").expect_err("unterminated quote should fail");
+        assert_eq!(err.kind, ParseErrorKind::UnterminatedQuote);
+        assert_eq!(err.token, "\"");
+        assert_eq!((err.line, err.column), (1, 17));
+
+        let err = parse("printf \"a\nb\" }").expect_err("'}' without '{' should fail to parse");
+        assert_eq!(err.input, "b\" }");
+        assert_eq!((err.line, err.column), (2, 4));
+
+        assert!(err
+            .in_script("error_position.rush")
+            .to_string()
+            .starts_with("error_position.rush:2:4: syntax error: unexpected \"}\"\n"));
+    }
+
+    #[test]
+    fn separators() {
+        parse("").expect("empty input should parse");
+        parse("true;").expect("trailing ';' should parse");
+        match parse("true & false").expect("'&' should parse") {
+            Command::Group(group) => match &group.commands[..] {
+                [Command::Background(_), Command::SimpleCommand(_)] => (),
+                c => panic!("expected a background and simple command, got {:?}", c),
+            },
+            c => panic!("expected a group, got {:?}", c),
+        }
+        parse("true;;").expect_err("';;' outside of a case statement should fail");
+    }
+
+    #[test]
+    fn multiline() {
+        let source =
+            "\n# leading comment\ntrue; # trailing comment\n\n  false\\\n  arg #\necho a#b\n";
+        let commands = match parse(source).expect("failed to parse") {
+            Command::Group(group) => group.commands,
+            c => panic!("expected a group, got {:?}", c),
+        };
+        assert_eq!(commands.len(), 6);
+
+        match &commands[2] {
+            Command::Comment(comment) => assert_eq!(comment.text, " trailing comment"),
+            c => panic!("expected a comment, got {:?}", c),
+        }
+        match &commands[3] {
+            Command::SimpleCommand(cmd) => assert_eq!(cmd.arguments.len(), 2),
+            c => panic!("expected a simple command, got {:?}", c),
+        }
+        match &commands[5] {
+            Command::SimpleCommand(cmd) => assert_eq!(cmd.arguments.len(), 2),
+            c => panic!("expected a simple command, got {:?}", c),
+        }
+
+        parse("echo a |\n  # comment\n  cat").expect("comments are allowed after a pipe");
+        assert!(parse("echo a \\").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn conditional() {
+        let expression = |source: &str| match parse(source).expect("failed to parse") {
+            Command::Group(group) => match group.commands.into_iter().next() {
+                Some(Command::Conditional(cond)) => cond.expression,
+                c => panic!("expected a conditional, got {:?}", c),
+            },
+            c => panic!("expected a group, got {:?}", c),
+        };
+
+        match expression("[[ -f a && ( b == c* || ! d ) ]]") {
+            ConditionalExpression::And(left, right) => {
+                assert!(matches!(*left, ConditionalExpression::Unary(ref op, _) if op == "-f"));
+                match *right {
+                    ConditionalExpression::Or(left, right) => {
+                        assert!(
+                            matches!(*left, ConditionalExpression::Binary(_, ref op, _) if op == "==")
+                        );
+                        assert!(matches!(*right, ConditionalExpression::Not(_)));
+                    }
+                    e => panic!("expected an or, got {:?}", e),
+                }
+            }
+            e => panic!("expected an and, got {:?}", e),
+        }
+
+        // parentheses and `|` are part of a regex
+        match expression("[[ $x =~ ^(a|b)+$ ]]") {
+            ConditionalExpression::Binary(_, op, right) => {
+                assert_eq!(op, "=~");
+                assert_eq!(right, Word::from(vec![Token::Regex("^(a|b)+$".into())]));
+            }
+            e => panic!("expected a binary operator, got {:?}", e),
+        }
+
+        assert!(matches!(
+            expression("[[ -n ]]"),
+            ConditionalExpression::Word(_)
+        ));
+        assert!(matches!(
+            expression("[[ a<b ]]"),
+            ConditionalExpression::Binary(_, ref op, _) if op == "<"
+        ));
+        parse("[[ a ]] && [[ b ]] >/dev/null").expect("conditionals should be commands");
+        parse("[[a").expect("`[[a` is a simple command");
+        assert!(parse("[[ a &&").unwrap_err().is_incomplete());
+        assert_eq!(parse("[[ ]]").unwrap_err().token, "]]");
+    }
+
+    #[test]
+    fn spans() {
+        let source = "true; printf 'a\nb' | cat >&2";
+        let commands = match parse(source).expect("failed to parse") {
+            Command::Group(group) => group.commands,
+            c => panic!("expected a group, got {:?}", c),
+        };
+
+        let pipeline = match &commands[1] {
+            Command::Pipeline(pipeline) => pipeline,
+            c => panic!("expected a pipeline, got {:?}", c),
+        };
+        assert_eq!(pipeline.span.start.offset, 6);
+        assert_eq!(pipeline.span.end.offset, source.len());
+        assert_eq!((pipeline.span.end.line, pipeline.span.end.column), (2, 13));
+
+        let redirect = match &pipeline.to {
+            Command::FileRedirect(redirect) => redirect,
+            c => panic!("expected a redirect, got {:?}", c),
+        };
+        assert_eq!(redirect.redirects[0].span.start.offset, 25);
+        assert_eq!(
+            (
+                redirect.left.span().start.line,
+                redirect.left.span().start.column
+            ),
+            (2, 6)
+        );
+
+        match &pipeline.from {
+            Command::SimpleCommand(printf) => {
+                let arg = printf.arguments[1].span();
+                assert_eq!(&source[arg.start.offset..arg.end.offset], "'a\nb'");
+                assert_eq!((arg.start.line, arg.start.column), (1, 14));
+            }
+            c => panic!("expected a simple command, got {:?}", c),
+        }
+    }
+
+    #[test]
+    fn assignments() {
+        let command = |source: &str| match parse(source).expect("failed to parse") {
+            Command::Group(mut group) => match group.commands.remove(0) {
+                Command::SimpleCommand(command) => command,
+                c => panic!("expected a simple command, got {:?}", c),
+            },
+            c => panic!("expected a group, got {:?}", c),
+        };
+
+        let c = command("a=1 b+=2 c[1 + 2]= echo d=3");
+        assert_eq!(c.assignments.len(), 3);
+        assert_eq!(c.arguments.len(), 2);
+        assert_eq!(
+            (c.assignments[0].name.as_str(), c.assignments[0].append),
+            ("a", false)
+        );
+        assert_eq!(
+            (c.assignments[1].name.as_str(), c.assignments[1].append),
+            ("b", true)
+        );
+        assert_eq!(
+            c.assignments[2].subscript,
+            Some(Word::from(vec![Token::Slice("1 + 2".into())]))
+        );
+        assert!(matches!(&c.assignments[2].value, AssignmentValue::Word(w) if *w == Word::new()));
+
+        let c = command("a=(x [2]=y\n  # a comment\n 'z' )");
+        assert!(c.arguments.is_empty());
+        match &c.assignments[0].value {
+            AssignmentValue::Array(elements) => {
+                assert_eq!(elements.len(), 3);
+                assert!(elements[0].0.is_none());
+                assert!(elements[1].0.is_some());
+            }
+            v => panic!("expected an array, got {:?}", v),
+        }
+
+        assert!(command("=x").assignments.is_empty());
+        assert!(command("a[1]x=y").assignments.is_empty());
+        assert!(parse("a=(x").unwrap_err().is_incomplete());
+    }
+}
@@ -0,0 +1,135 @@
+//! Shell pattern matching, used by `[[ == ]]`
+//!
+//! `*` matches any string, `?` matches any character, and `[...]` matches one of a set of characters.
+//! Sets can contain ranges like `a-z`, and are negated by a leading `!` or `^`.
+//! A backslash makes the next character match itself.
+
+/// Check if `pattern` matches the whole of `text`
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // where to resume after the last `*`, if the rest of the pattern stops matching
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match set(&pattern[p..], text[t]) {
+                Some((true, len)) => Some(len),
+                Some((false, _)) => None,
+                // an unterminated set is an ordinary `[`
+                None if text[t] == '[' => Some(1),
+                None => None,
+            },
+            Some('\\') if p + 1 < pattern.len() => {
+                if pattern[p + 1] == text[t] {
+                    Some(2)
+                } else {
+                    None
+                }
+            }
+            Some(c) if *c == text[t] => Some(1),
+            _ => None,
+        };
+
+        match (step, star) {
+            (Some(len), _) => {
+                p += len;
+                t += 1;
+            }
+            // let the last `*` match one more character, and try again
+            (None, Some((star_p, star_t))) => {
+                star = Some((star_p, star_t + 1));
+                p = star_p;
+                t = star_t + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Match `c` against the set at the start of `pattern`.
+///
+/// Returns whether it matched and the length of the set, or `None` if the set isn't terminated.
+fn set(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut start = *pattern.get(i)?;
+        // a `]` right after the opening bracket is part of the set
+        if start == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        if start == '\\' {
+            i += 1;
+            start = *pattern.get(i)?;
+        }
+        first = false;
+        i += 1;
+
+        let mut end = start;
+        if pattern.get(i) == Some(&'-') && pattern.get(i + 1).is_some_and(|c| *c != ']') {
+            end = pattern[i + 1];
+            i += 2;
+            if end == '\\' {
+                end = *pattern.get(i)?;
+                i += 1;
+            }
+        }
+        matched |= start <= c && c <= end;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::matches;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("", ""));
+        assert!(matches("*", ""));
+        assert!(matches("a*c", "abbbc"));
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rs.bak"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*a*b", "xxaxxab"));
+        assert!(matches("é?", "éa"));
+    }
+
+    #[test]
+    fn sets() {
+        assert!(matches("[abc]", "b"));
+        assert!(!matches("[abc]", "d"));
+        assert!(matches("[a-z]x", "qx"));
+        assert!(matches("[!a-z]", "Q"));
+        assert!(matches("[^a-z]", "Q"));
+        assert!(!matches("[!a-z]", "q"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[", "["));
+        assert!(matches("a[", "a["));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("a\\?*", "a?bc"));
+        assert!(matches("[\\]]", "]"));
+    }
+}
@@ -0,0 +1,102 @@
+//! Locations of AST nodes in the source they were parsed from
+use std::fmt;
+
+/// A single location in a script
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    /// Offset from the beginning of the script, in bytes
+    pub offset: usize,
+
+    /// 1-based line number, 0 if the position hasn't been resolved
+    pub line: usize,
+
+    /// 1-based column, in characters
+    pub column: usize,
+}
+
+/// The range of source code a node was parsed from, `end` is exclusive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Position {
+    /// Create an unresolved position from the amount of input left when the parser reached it.
+    ///
+    /// The parser only sees the remaining input, so offsets are counted from the *end* of the script
+    /// until `Span::resolve` is called with the full source.
+    pub fn remaining(len: usize) -> Position {
+        Position {
+            offset: len,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.line != 0
+    }
+
+    fn resolve(&mut self, source: &str) {
+        if self.is_resolved() {
+            return;
+        }
+
+        self.offset = source.len().saturating_sub(self.offset);
+        let before = &source[..self.offset];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        self.line = before.matches('\n').count() + 1;
+        self.column = before[line_start..].chars().count() + 1;
+    }
+}
+
+impl Span {
+    /// Create an unresolved span from the amount of input left before and after a node was parsed
+    pub fn remaining(start: usize, end: usize) -> Span {
+        Span {
+            start: Position::remaining(start),
+            end: Position::remaining(end),
+        }
+    }
+
+    /// A span starting at the beginning of `self` and ending at the end of `other`
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+
+    /// Convert offsets from the end of `source` to offsets from the start, and fill in line and column numbers
+    pub fn resolve(&mut self, source: &str) {
+        self.start.resolve(source);
+        self.end.resolve(source);
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Run a nom parser on a `CompleteStr` and pair its output with the (unresolved) span of input it consumed
+macro_rules! spanned (
+  ($i:expr, $submac:ident!( $($args:tt)* )) => (
+    {
+      let input = $i;
+      let start = input.0.len();
+      match $submac!(input, $($args)*) {
+        Ok((rest, o)) => {
+          let end = rest.0.len();
+          Ok((rest, (o, $crate::lang::span::Span::remaining(start, end))))
+        }
+        Err(e) => Err(e),
+      }
+    }
+  );
+  ($i:expr, $f:expr) => (
+    spanned!($i, call!($f))
+  );
+);
@@ -1,9 +1,11 @@
 use crate::env;
 use crate::expr;
-use crate::lang::{ErrorKind, Result};
+use crate::lang::span::Span;
+use crate::lang::{ErrorKind, ParseError, Result};
 use failure::ResultExt;
 use nom;
 use nom::types::CompleteStr;
+use std::result;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Token {
@@ -13,26 +15,88 @@ pub enum Token {
     Unquoted(Word),
     Quoted(Word),
     Multi(Vec<Word>),
-    Regex,
+    /// Unquoted text on the right of `=~`, passed to the regex engine as it is
+    Regex(String),
     Escape(char),
     Parameter(String, char, Word),
     Variable(String),
+    /// `${name[subscript]}`, the subscript `@` or `*` is every element
+    Element(String, Word),
+    /// `${#name}` or `${#name[subscript]}`, the length of a value, or the number of elements with `@` or `*`
+    Length(String, Option<Word>),
     Command(Word),
     Expr(Word),
     QuotedCommand(String),
     Slice(String),
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Word {
     parts: Vec<Token>,
+    span: Span,
+}
+
+/// Words are equal if they expand the same way, regardless of where they came from
+impl PartialEq for Word {
+    fn eq(&self, other: &Word) -> bool {
+        self.parts == other.parts
+    }
 }
 
+impl Eq for Word {}
+
+// A variable name is a letter or underscore, followed by any number of letters, digits and underscores
+named!(
+    pub variable_name<CompleteStr, CompleteStr>,
+    recognize!(pair!(
+        take_while1!(|x: char| x.is_ascii_alphabetic() || x == '_'),
+        take_while!(|x: char| x.is_ascii_alphanumeric() || x == '_')
+    ))
+);
+
+// Special parameters are a single character, positional parameters above 9 must be wrapped in braces
+named!(
+    pub special_parameter<CompleteStr, CompleteStr>,
+    recognize!(one_of!("?#$@*-!0123456789"))
+);
+
 named!(
     pub sigiled_expression<CompleteStr, Token>,
     alt!(
         delimited!(tag!("(("), expression_word, tag!("))")) => {|x| Token::Expr(x)}
-        | take_while!(|x| (x >= 'a' && x <= 'z') || (x >= 'A' && x <= 'Z') || x == '_') => {|x : CompleteStr| Token::Variable(x.0.to_string())}
+        | delimited!(
+            char!('{'),
+            pair!(variable_name, subscript),
+            char!('}')
+        ) => {|(name, subscript) : (CompleteStr, Word)| Token::Element(name.0.to_string(), subscript)}
+        | delimited!(
+            tag!("{#"),
+            pair!(variable_name, opt!(subscript)),
+            char!('}')
+        ) => {|(name, subscript) : (CompleteStr, Option<Word>)| Token::Length(name.0.to_string(), subscript)}
+        | delimited!(
+            char!('{'),
+            alt!(variable_name | take_while1!(|x: char| x.is_ascii_digit()) | special_parameter),
+            char!('}')
+        ) => {|x : CompleteStr| Token::Variable(x.0.to_string())}
+        | variable_name => {|x : CompleteStr| Token::Variable(x.0.to_string())}
+        | special_parameter => {|x : CompleteStr| Token::Variable(x.0.to_string())}
+        | value!(Token::Variable(String::new()))
+    )
+);
+
+// An array subscript in brackets, it's expanded like a double-quoted word, and may contain blanks
+named!(
+    pub subscript<CompleteStr, Word>,
+    delimited!(
+        char!('['),
+        map!(many1!(alt!(
+            preceded!(char!('$'), sigiled_expression)
+            | delimited!(char!('\''), many0!(single_quoted_token), char!('\'')) => {|x| Token::Quoted(Word::from(x))}
+            | delimited!(char!('"'), many0!(double_quoted_token), char!('"')) => {|x| Token::Quoted(Word::from(x))}
+            | take_while1!(|c: char| !"]$'\"\n".contains(c)) => {|x : CompleteStr| Token::Slice(x.0.to_string())}
+        )), Word::from),
+        char!(']')
     )
 );
 
@@ -64,10 +128,17 @@ named!(
     )
 );
 
+// A backslash before a newline joins two lines, and is removed along with the newline
+named!(
+    pub line_continuation<CompleteStr, Token>,
+    value!(Token::Slice(String::new()), tag!("\\\n"))
+);
+
 named! {
     pub double_quoted_token<CompleteStr, Token>,
     alt!(
-        preceded!(char!('\\'),
+        line_continuation
+        | preceded!(char!('\\'),
             alt!(
                 char!('"')
                 | char!('\\')
@@ -95,13 +166,13 @@ named!(
 
 named!(
     pub unquoted_token<CompleteStr, Token>,
-    alt!(preceded!(char!('\\'),
+    alt!(line_continuation
+        | preceded!(char!('\\'),
             alt!(
                 char!('"')
                 | char!('\\')
                 | char!('|')
                 | char!('n')
-                | char!('\n')
                 | char!('\'')
                 | char!('t')
                 | char!('$')
@@ -122,20 +193,85 @@ named!(
                 many0!(single_quoted_token),
             char!('\'')
         ) => { |c| Token::Quoted(Word::from(c)) }
-        | take_while1!(|c : char| c != '&'  && c != '"' && c != '{' && c != '}' && c != '\'' &&  c != '|' && c != ';' && c != '\n' && c != '\\' && c != '$' && !nom::is_space(c as u8)) => {|x : CompleteStr| Token::Slice(x.0.to_string())}
+        | take_while1!(|c : char| c != '&'  && c != '"' && c != '{' && c != '}' && c != '\'' &&  c != '|' && c != ';' && c != '<' && c != '>' && c != '\n' && c != '\\' && c != '$' && !nom::is_space(c as u8)) => {|x : CompleteStr| Token::Slice(x.0.to_string())}
     )
 );
 
 named!(pub word<CompleteStr, Word>,
-    map!(many0!(alt!(
+    map!(spanned!(many1!(alt!(
             unquoted_token
             | delimited!(char!('\''), many0!(single_quoted_token), char!('\'')) => {|x| Token::Quoted(Word::from(x))}
             | delimited!(char!('"'), many0!(double_quoted_token), char!('"')) => {|x| Token::Quoted(Word::from(x))}
-        )),
-        {|x| Word{parts : x}}
+        ))),
+        {|(x, span)| Word{parts : x, span}}
+    )
+);
+
+// Words in `[[ ]]` and array assignments are like other words, except parentheses are operators
+named!(pub conditional_word<CompleteStr, Word>,
+    map!(spanned!(many1!(alt!(
+            take_while1!(|c : char| !"&\"{}'|;<>()\n\\$".contains(c) && !nom::is_space(c as u8)) => {|x : CompleteStr| Token::Slice(x.0.to_string())}
+            | preceded!(not!(one_of!("()")), unquoted_token)
+        ))),
+        {|(x, span)| Word{parts : x, span}}
+    )
+);
+
+// The right side of `=~` is a regex, so unquoted parentheses, `|` and backslashes are part of the word
+named!(pub regex_word<CompleteStr, Word>,
+    map!(spanned!(many1!(alt!(
+            regex_slice
+            | preceded!(char!('$'), sigiled_expression)
+            | delimited!(char!('\''), many0!(single_quoted_token), char!('\'')) => {|x| Token::Quoted(Word::from(x))}
+            | delimited!(char!('"'), many0!(double_quoted_token), char!('"')) => {|x| Token::Quoted(Word::from(x))}
+        ))),
+        {|(x, span)| Word{parts : x, span}}
     )
 );
 
+/// Take unquoted regex text, up to a blank outside of parentheses or a character that starts a quote or expansion
+fn regex_slice(input: CompleteStr) -> nom::IResult<CompleteStr, Token> {
+    let mut depth = 0;
+    let mut chars = input.0.char_indices();
+    let mut end = input.0.len();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            // `$` is only an expansion if a name or special parameter follows, `^a$` ends with a literal `$`
+            '$' if input.0[i + 1..]
+                .starts_with(|c: char| c.is_ascii_alphanumeric() || "_{(?#@*!-".contains(c)) =>
+            {
+                end = i;
+                break;
+            }
+            '\'' | '"' | ')' | '&' | ';' | '<' | '>' | '\n' => {
+                end = i;
+                break;
+            }
+            ' ' | '\t' if depth == 0 => {
+                end = i;
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    if end == 0 {
+        return Err(nom::Err::Error(nom::Context::Code(
+            input,
+            nom::ErrorKind::Custom(0),
+        )));
+    }
+    Ok((
+        CompleteStr(&input.0[end..]),
+        Token::Regex(input.0[..end].to_string()),
+    ))
+}
+
 impl<T> From<T> for Word
 where
     T: IntoIterator<Item = Token>,
@@ -143,16 +279,42 @@ where
     fn from(v: T) -> Word {
         Word {
             parts: v.into_iter().collect(),
+            span: Span::default(),
         }
     }
 }
 
 impl Word {
     pub fn new() -> Word {
-        Word { parts: Vec::new() }
+        Word {
+            parts: Vec::new(),
+            span: Span::default(),
+        }
     }
-    pub fn parse<T: AsRef<str>>(s: T) -> Word {
-        word(CompleteStr(s.as_ref())).unwrap().1
+    /// Parse a single word, failing if there is any input left over
+    pub fn parse<T: AsRef<str>>(s: T) -> result::Result<Word, ParseError> {
+        let source = s.as_ref();
+        if source.is_empty() {
+            return Ok(Word::new());
+        }
+
+        match word(CompleteStr(source)) {
+            Ok((rest, mut w)) if rest.is_empty() => {
+                w.span.resolve(source);
+                Ok(w)
+            }
+            Ok((rest, _)) => Err(ParseError::new(source, rest.0)),
+            Err(_) => Err(ParseError::new(source, source)),
+        }
+    }
+
+    /// Get the range of source code this word was parsed from
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn span_mut(&mut self) -> &mut Span {
+        &mut self.span
     }
 
     pub fn compile(&self, vars: &mut env::Variables) -> Result<String> {
@@ -166,12 +328,48 @@ impl Word {
                 }
                 Token::Slice(v) => s.push_str(v),
                 Token::Expr(v) => {
-                    let evaluated: String = expr::eval(v.compile(vars)?.as_str(), vars)
-                        .context(ErrorKind::ExpressionError)?;
+                    let evaluated = match expr::eval(v.compile(vars)?.as_str(), vars) {
+                        Ok(evaluated) => evaluated,
+                        Err(e) => match e.kind() {
+                            expr::ErrorKind::Assign(e) => return Err((**e).clone().into()),
+                            _ => Err(e).context(ErrorKind::ExpressionError)?,
+                        },
+                    };
                     s.push_str(&evaluated)
                 }
+                // a lone '$' isn't followed by a name, so it's taken literally
+                Token::Variable(v) if v.is_empty() => s.push('$'),
                 Token::Variable(v) => {
-                    s.push_str(vars.value(&OsString::from(v)).to_str().unwrap_or(""))
+                    let name = OsString::from(v);
+                    if vars.options().nounset && v != "@" && v != "*" && !vars.is_set(&name) {
+                        return Err(ErrorKind::UnboundVariable(v.clone()).into());
+                    }
+                    s.push_str(vars.value(&name).to_str().unwrap_or(""))
+                }
+                Token::Element(name, subscript) if is_every_element(subscript) => {
+                    let elements = vars.elements(&OsString::from(name));
+                    let elements: Vec<_> = elements.iter().map(|e| e.to_string_lossy()).collect();
+                    s.push_str(&elements.join(" "))
+                }
+                Token::Element(name, subscript) => s.push_str(&element(vars, name, subscript)?),
+                Token::Length(name, subscript) => {
+                    let length = match subscript {
+                        Some(subscript) if is_every_element(subscript) => {
+                            vars.elements(&OsString::from(name)).len()
+                        }
+                        Some(subscript) => element(vars, name, subscript)?.chars().count(),
+                        None => {
+                            let name = OsString::from(name);
+                            if vars.options().nounset && !vars.is_set(&name) {
+                                return Err(ErrorKind::UnboundVariable(
+                                    name.to_string_lossy().into_owned(),
+                                )
+                                .into());
+                            }
+                            vars.value(&name).to_string_lossy().chars().count()
+                        }
+                    };
+                    s.push_str(&length.to_string())
                 }
                 Token::Escape(v) => s.push(match *v {
                     'n' => '\n',
@@ -186,9 +384,122 @@ impl Word {
                     _ => '\u{FFFD}',
                 }),
                 Token::Quoted(v) => s.extend(v.compile(vars)?.chars()),
+                Token::Regex(v) => s.push_str(v),
                 _ => unimplemented!(),
             };
         }
         Ok(s)
     }
+
+    /// Expand a word into fields, `${name[@]}` and `$@` expand to one field for each element, `${name[*]}` joins them.
+    ///
+    /// Text before and after the elements is joined to the first and last one.
+    /// A word that's only elements expands to no fields at all if there aren't any.
+    pub fn compile_fields(&self, vars: &mut env::Variables) -> Result<Vec<String>> {
+        let mut fields = vec![String::new()];
+        match self.expand_fields(vars, &mut fields)? {
+            true => Ok(fields),
+            false => Ok(Vec::new()),
+        }
+    }
+
+    /// Expand onto the last of `fields`, returns false if nothing was expanded except empty lists of elements
+    fn expand_fields(&self, vars: &mut env::Variables, fields: &mut Vec<String>) -> Result<bool> {
+        let mut expanded = false;
+        for x in &self.parts {
+            if let Token::Quoted(word) = x {
+                expanded |= word.expand_fields(vars, fields)?;
+                continue;
+            }
+
+            let last = fields.len() - 1;
+            match x.list_elements(vars) {
+                Some(elements) => {
+                    let mut elements = elements.into_iter();
+                    if let Some(first) = elements.next() {
+                        fields[last].push_str(&first);
+                        expanded = true;
+                    }
+                    fields.extend(elements);
+                }
+                None => {
+                    fields[last].push_str(&Word::from(vec![x.clone()]).compile(vars)?);
+                    expanded = true;
+                }
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Expand a word into a glob pattern, quoted and escaped characters are escaped so they only match themselves
+    pub fn compile_pattern(&self, vars: &mut env::Variables) -> Result<String> {
+        self.compile_quoting(vars, |s| {
+            let mut escaped = String::with_capacity(s.len());
+            for c in s.chars() {
+                if "*?[]\\".contains(c) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        })
+    }
+
+    /// Expand a word into a regex, quoted and escaped characters are escaped so they only match themselves
+    pub fn compile_regex(&self, vars: &mut env::Variables) -> Result<String> {
+        self.compile_quoting(vars, regex::escape)
+    }
+
+    fn compile_quoting(
+        &self,
+        vars: &mut env::Variables,
+        quote: fn(&str) -> String,
+    ) -> Result<String> {
+        let mut s = String::new();
+        for x in &self.parts {
+            match x {
+                Token::Quoted(_) | Token::Escape(_) | Token::Tilde => {
+                    s.push_str(&quote(&Word::from(vec![x.clone()]).compile(vars)?))
+                }
+                _ => s.push_str(&Word::from(vec![x.clone()]).compile(vars)?),
+            }
+        }
+        Ok(s)
+    }
+}
+
+impl Token {
+    /// Get the elements of a `${name[@]}` or `$@` expansion, which become separate fields, or `None` for any other token
+    fn list_elements(&self, vars: &env::Variables) -> Option<Vec<String>> {
+        let elements = match self {
+            Token::Element(name, subscript) if matches!(&subscript.parts[..], [Token::Slice(s)] if s == "@") => {
+                vars.elements(&name.into())
+            }
+            Token::Variable(name) if name == "@" => vars.positional().to_vec(),
+            _ => return None,
+        };
+        Some(
+            elements
+                .iter()
+                .map(|e| e.to_string_lossy().into_owned())
+                .collect(),
+        )
+    }
+}
+
+/// Check if a subscript is `@` or `*`, which refer to every element of an array
+fn is_every_element(subscript: &Word) -> bool {
+    matches!(&subscript.parts[..], [Token::Slice(s)] if s == "@" || s == "*")
+}
+
+/// Expand `${name[subscript]}`, an element that isn't set is empty
+fn element(vars: &mut env::Variables, name: &str, subscript: &Word) -> Result<String> {
+    let subscript = subscript.compile(vars)?;
+    match vars.element(&name.into(), &subscript)? {
+        Some(value) => Ok(value.to_string_lossy().into_owned()),
+        None if vars.options().nounset => {
+            Err(ErrorKind::UnboundVariable(format!("{}[{}]", name, subscript)).into())
+        }
+        None => Ok(String::new()),
+    }
 }
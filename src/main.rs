@@ -10,6 +10,7 @@ extern crate nixterm;
 #[cfg(test)]
 mod test_util;
 
+pub mod builtins;
 pub mod env;
 pub mod expr;
 pub mod jobs;
@@ -22,29 +23,81 @@ use std::io::Read;
 use std::process::exit;
 
 fn main() {
-    let shell = shell::Shell::new();
     let mut environ = lang::ExecutionContext::new();
     let mut job_manager = lang::JobManager::new();
 
     environ.variables_mut().define("RUSH_VERSION", "0.1.0");
 
-    match args().nth(1) {
+    // shell options come before the script name, e.g. `rush -u -o noexec script.rush arg1 arg2`
+    let mut argv = args().skip(1).peekable();
+    while let Some(arg) = argv.peek().cloned() {
+        let enable = match arg.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => break,
+        };
+        argv.next();
+
+        if arg == "--" {
+            break;
+        }
+
+        for flag in arg.chars().skip(1) {
+            let known = if flag == 'o' {
+                match argv.next() {
+                    Some(name) => environ.variables_mut().options_mut().set(&name, enable),
+                    None => false,
+                }
+            } else {
+                environ.variables_mut().options_mut().set_flag(flag, enable)
+            };
+
+            if !known {
+                eprintln!("rush: {}: invalid option", arg);
+                exit(2);
+            }
+        }
+    }
+
+    match argv.next() {
         Some(v) => {
+            environ.variables_mut().set_arg0(&v);
+            environ.set_script_name(v.as_str());
+            environ.variables_mut().set_positional(argv);
+
             let mut data = String::new();
             {
-                let mut f = File::open(v).unwrap();
+                let mut f = File::open(&v).unwrap();
                 f.read_to_string(&mut data).unwrap();
             }
-            exit(
-                job_manager
-                    .run(&mut environ, lang::ast::Command::from(data))
-                    .map(|exit_status| exit_status.exit_code)
-                    .unwrap_or_else(|e| {
-                        println!("{}", e);
-                        1
-                    }),
-            )
+
+            // the whole script is parsed up front, so with noexec all syntax errors are reported without running anything
+            let script = match lang::ast::Command::parse(&data) {
+                Ok(script) => script,
+                Err(e) => {
+                    eprintln!("{}", e.in_script(&v));
+                    exit(2);
+                }
+            };
+
+            if environ.variables().options().noexec {
+                exit(0);
+            }
+
+            let result = job_manager
+                .run(&mut environ, script)
+                .map(|exit_status| exit_status.exit_code);
+            job_manager.exit_shell(&mut environ, result)
+        }
+        None => {
+            if let Err(e) = env::traps::ignore_interactive() {
+                eprintln!("rush: failed to ignore interactive signals: {}", e);
+            }
+            shell::Shell::new()
+                .unwrap()
+                .run(&mut environ, &mut job_manager);
+            let status = environ.variables().last_status();
+            job_manager.exit_shell(&mut environ, Ok(status))
         }
-        None => shell.unwrap().run(&mut environ, &mut job_manager),
     }
 }
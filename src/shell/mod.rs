@@ -1,29 +1,56 @@
+use crate::env::traps;
 use crate::lang;
 use failure;
+use failure::ResultExt;
 use lang::ast::Command;
 use lang::parser;
-use lang::word::Word;
+use nix::poll::{poll, EventFlags, PollFd};
+use nix::unistd;
 use nixterm;
 use nixterm::events::Key;
 use std::ffi::OsString;
 use std::io;
 use std::io::Write;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// The shell's standard input, read one byte at a time.
+///
+/// The terminal buffers its input, by only giving it a byte at a time the buffer stays empty,
+/// so polling stdin's file descriptor shows whether there is a key waiting to be read.
+struct Input;
+
+impl io::Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(1);
+        unistd::read(0, &mut buf[..len]).map_err(io::Error::other)
+    }
+}
+
+impl AsRawFd for Input {
+    fn as_raw_fd(&self) -> RawFd {
+        0
+    }
+}
 
 pub struct Shell {
     command_buffer: String,
     old_settings: nixterm::term::Settings,
-    term: nixterm::Term<io::Stdin, io::Stdout>,
+    term: nixterm::Term<Input, io::Stdout>,
     history: Vec<String>,
     exit: bool,
+    interrupted: bool,
 }
 
 impl Shell {
     pub fn new() -> nixterm::Result<Shell> {
-        let t = nixterm::Term::new()?;
+        let info = nixterm::terminfo::from_env()
+            .context(nixterm::ErrorKind::FailedToCreateTermInstance)?;
+        let t = nixterm::Term::from_streams(info, Input, io::stdout());
         Ok(Shell {
             command_buffer: String::new(),
             history: Vec::new(),
             exit: false,
+            interrupted: false,
             old_settings: t.settings(),
             term: t,
         })
@@ -36,32 +63,55 @@ impl Shell {
         }
     }
 
+    /// Print an error from running a command, returns true if it was `exit` and the shell should end
+    fn handle_error(ec: &mut lang::ExecutionContext, e: lang::Error) -> bool {
+        match e.kind() {
+            lang::ErrorKind::Exit(status) => {
+                ec.variables_mut().set_last_status(*status);
+                true
+            }
+            _ => {
+                Shell::print_error(e);
+                false
+            }
+        }
+    }
+
     pub fn run(&mut self, ec: &mut lang::ExecutionContext, jm: &mut lang::JobManager) {
         while !self.exit_requested() {
+            // interactive shells ignore noexec, otherwise there would be no way to turn it back off
+            ec.variables_mut().options_mut().noexec = false;
+
+            // signals received while reading the last command are handled before the next prompt
+            if let Err(e) = jm.run_traps(ec) {
+                self.exit = Shell::handle_error(ec, e);
+                continue;
+            }
+
             let prefix_command = ec
                 .variables()
                 .value(&OsString::from("RUSH_PROMPT"))
                 .to_string_lossy()
                 .to_string();
 
-            match jm.run(
-                ec,
-                if prefix_command.is_empty() {
-                    Command::simple(
-                        ["printf", "'rush-%s$ '", "$RUSH_VERSION"]
-                            .iter()
-                            .map(|w| Word::parse(w))
-                            .collect(),
-                    )
-                } else {
-                    Command::from(prefix_command)
-                },
-            ) {
-                Err(e) => Shell::print_error(e),
+            let prompt = if prefix_command.is_empty() {
+                Command::parse("printf 'rush-%s$ ' \"$RUSH_VERSION\"")
+            } else {
+                Command::parse(prefix_command)
+            };
+
+            match prompt
+                .map_err(lang::Error::from)
+                .and_then(|command| jm.run(ec, command))
+            {
+                Err(e) => {
+                    self.exit = Shell::handle_error(ec, e);
+                    continue;
+                }
                 _ => (),
             }
 
-            let buffer = match self.readline(ec) {
+            let mut buffer = match self.readline(ec, jm) {
                 Ok(v) => v,
                 Err(e) => {
                     println!();
@@ -69,33 +119,138 @@ impl Shell {
                     continue;
                 }
             };
-            if !self.exit_requested() {
-                println!();
+            if self.exit_requested() {
+                continue;
+            }
+            println!();
 
-                if !buffer.is_empty() {
-                    self.history.push(buffer.clone());
-                    match jm.run(ec, Command::from(buffer)) {
-                        Err(e) => {
-                            Shell::print_error(e);
-                            continue;
+            if buffer.is_empty() {
+                continue;
+            }
+
+            // keep reading lines until the input is a complete command
+            let parsed = loop {
+                match Command::parse(&buffer) {
+                    Err(ref e) if e.is_incomplete() => match self.continuation(ec, jm) {
+                        Some(line) => {
+                            buffer.push('\n');
+                            buffer.push_str(&line);
                         }
-                        _ => (),
-                    }
+                        None => break None,
+                    },
+                    result => break Some(result),
                 }
+            };
+
+            self.history.push(buffer);
+            if let Some(result) = parsed {
+                if let Err(e) = result
+                    .map_err(lang::Error::from)
+                    .and_then(|command| jm.run(ec, command))
+                {
+                    self.exit = Shell::handle_error(ec, e);
+                }
+            }
+        }
+    }
+
+    /// Print the `PS2` prompt and read another line of an incomplete command.
+    ///
+    /// Returns `None` if reading was interrupted, in which case the whole command should be discarded.
+    fn continuation(
+        &mut self,
+        ec: &mut lang::ExecutionContext,
+        jm: &mut lang::JobManager,
+    ) -> Option<String> {
+        let prompt = ec.variables().value(&OsString::from("PS2"));
+        let prompt = if prompt.is_empty() {
+            "> ".to_string()
+        } else {
+            prompt.to_string_lossy().to_string()
+        };
+
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "{}", prompt).and_then(|_| stdout.flush());
+
+        let line = match self.readline(ec, jm) {
+            Ok(v) => v,
+            Err(e) => {
+                println!();
+                Shell::print_error(e);
+                return None;
             }
+        };
+        if self.exit_requested() || self.interrupted {
+            println!();
+            return None;
+        }
+
+        println!();
+        Some(line)
+    }
+
+    /// Block until there's input on stdin, returns false if a trapped signal was received first
+    fn wait_for_input() -> bool {
+        let traps = match traps::fd() {
+            Some(fd) => fd,
+            None => return true,
+        };
+
+        let mut fds = [
+            PollFd::new(0, EventFlags::POLLIN),
+            PollFd::new(traps, EventFlags::POLLIN),
+        ];
+        match poll(&mut fds, -1) {
+            Ok(_) => !fds[1]
+                .revents()
+                .is_some_and(|e| e.contains(EventFlags::POLLIN)),
+            // the signal handler interrupted poll, the trap's pipe is readable now
+            Err(_) => false,
         }
     }
 
-    pub fn readline(&mut self, environ: &mut lang::ExecutionContext) -> nixterm::Result<String> {
+    pub fn readline(
+        &mut self,
+        environ: &mut lang::ExecutionContext,
+        jm: &mut lang::JobManager,
+    ) -> nixterm::Result<String> {
         self.command_buffer.clear();
+        self.interrupted = false;
 
         let mut hist_index = self.history.len();
         let mut xoffset: isize = 0;
         self.term.update(self.old_settings.clone().raw()).unwrap();
 
-        for k in self.term.read_keys() {
+        let mut keys = self.term.read_keys();
+        loop {
+            if !Shell::wait_for_input() {
+                // traps run with the terminal's normal settings, then the line being edited is redrawn below their output
+                self.term.update(self.old_settings.clone()).unwrap();
+                println!();
+                if let Err(e) = jm.run_traps(environ) {
+                    if Shell::handle_error(environ, e) {
+                        self.exit = true;
+                        return Ok(String::new());
+                    }
+                }
+                self.term.update(self.old_settings.clone().raw()).unwrap();
+                self.term
+                    .writer()
+                    .print(&self.command_buffer)
+                    .shift_cursor(-xoffset, 0)
+                    .done()
+                    .unwrap();
+                continue;
+            }
+
+            let k = match keys.next() {
+                Some(k) => k,
+                None => break,
+            };
+
             let backtrack = self.command_buffer.len() as isize;
-            self.term
+            let _ = self
+                .term
                 .writer()
                 .shift_cursor(xoffset - backtrack, 0)
                 .done();
@@ -103,7 +258,8 @@ impl Shell {
             match k? {
                 Key::Control(c) => {
                     if c == 'D' && self.command_buffer.len() == 0 {
-                        self.term
+                        let _ = self
+                            .term
                             .writer()
                             .print(&self.command_buffer)
                             .print("exit")
@@ -112,12 +268,14 @@ impl Shell {
                         break;
                     }
                     if c == 'C' {
-                        self.term
+                        let _ = self
+                            .term
                             .writer()
                             .print(&self.command_buffer)
                             .print("^C")
                             .done();
                         self.command_buffer.clear();
+                        self.interrupted = true;
                         break;
                     }
                 }
@@ -167,7 +325,7 @@ impl Shell {
                 .unwrap();
         }
 
-        self.term.update(self.old_settings.clone());
+        let _ = self.term.update(self.old_settings.clone());
         Ok(self.command_buffer.clone())
     }
 